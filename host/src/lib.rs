@@ -0,0 +1,74 @@
+//! The hardware independent modules of the firmware, built for the host so they can be exercised by
+//! tests and the simulation. The modules are shared with the firmware, only `simulation` and
+//! `testing` live here.
+#![no_std]
+
+#[path = "../../src/actuator.rs"]
+pub mod actuator;
+#[path = "../../src/auth.rs"]
+pub mod auth;
+#[path = "../../src/calibration.rs"]
+pub mod calibration;
+#[path = "../../src/drying.rs"]
+pub mod drying;
+#[path = "../../src/events.rs"]
+pub mod events;
+#[path = "../../src/expander.rs"]
+pub mod expander;
+#[path = "../../src/flow_calibration.rs"]
+pub mod flow_calibration;
+#[path = "../../src/flow_meter.rs"]
+pub mod flow_meter;
+#[path = "../../src/http.rs"]
+pub mod http;
+#[path = "../../src/manifold.rs"]
+pub mod manifold;
+#[path = "../../src/manual_override.rs"]
+pub mod manual_override;
+#[path = "../../src/motor_current.rs"]
+pub mod motor_current;
+#[path = "../../src/nutrient.rs"]
+pub mod nutrient;
+#[path = "../../src/persistence.rs"]
+pub mod persistence;
+#[path = "../../src/plant_profile.rs"]
+pub mod plant_profile;
+#[path = "../../src/pump_command.rs"]
+pub mod pump_command;
+#[path = "../../src/pump_control.rs"]
+pub mod pump_control;
+#[path = "../../src/pump_driver.rs"]
+pub mod pump_driver;
+#[path = "../../src/pump_safety.rs"]
+pub mod pump_safety;
+#[path = "../../src/ramp.rs"]
+pub mod ramp;
+#[path = "../../src/seesaw.rs"]
+pub mod seesaw;
+#[path = "../../src/sensor_fault.rs"]
+pub mod sensor_fault;
+#[path = "../../src/server_command.rs"]
+pub mod server_command;
+#[path = "../../src/soil_estimator.rs"]
+pub mod soil_estimator;
+#[path = "../../src/statistics.rs"]
+pub mod statistics;
+#[path = "../../src/stepper.rs"]
+pub mod stepper;
+#[path = "../../src/tank.rs"]
+pub mod tank;
+#[path = "../../src/tank_sensors.rs"]
+pub mod tank_sensors;
+#[path = "../../src/temperature_guard.rs"]
+pub mod temperature_guard;
+#[path = "../../src/upload.rs"]
+pub mod upload;
+#[path = "../../src/watering.rs"]
+pub mod watering;
+#[path = "../../src/watering_history.rs"]
+pub mod watering_history;
+#[path = "../../src/zone.rs"]
+pub mod zone;
+
+pub mod simulation;
+pub mod testing;
@@ -0,0 +1,344 @@
+use core::task::Poll;
+
+use embassy_futures::poll_once;
+use embassy_sync::{blocking_mutex::raw::NoopRawMutex, channel::Channel};
+use embassy_time::{Duration, Instant};
+use heapless::Vec;
+use log::error;
+
+use crate::{calibration::CalibrationMode, events::Event, flow_calibration::{FlowCalibration, FlowPoint}, persistence::{PersistQueue, StoredCalibration}, plant_profile::PlantProfile, pump_command::{AbortReason, PumpChannel, PumpCommand, PumpOutcome, PumpStatus}, seesaw, sensor_fault::SensorFaultDetector, soil_estimator::{EstimatorChannels, SoilEstimator}, statistics::{AggregateRecord, RunningStats}};
+
+/// Parameters of the bucket water-balance model of a single pot.
+#[derive(Debug, Clone, Copy)]
+pub struct SoilModelConfig {
+    /// Water the pot holds when fully saturated in ml.
+    pub saturation_ml: f64,
+    /// Water content above which the pot starts draining, as a fraction of saturation.
+    pub field_capacity: f64,
+    /// Fraction of the water above field capacity that drains per hour.
+    pub drainage_per_hour: f64,
+    /// Evapotranspiration in ml per hour at 20 °C and field capacity.
+    pub evapotranspiration_ml_per_hour: f64,
+    /// Water delivered by the pump at full duty in ml per second.
+    pub pump_flow_ml_per_s: f64,
+    /// Raw Seesaw reading of a bone dry pot.
+    pub raw_dry: u16,
+    /// Raw Seesaw reading of a saturated pot.
+    pub raw_wet: u16,
+    /// Peak to peak amplitude of the uniform noise added to the raw moisture reading.
+    pub raw_noise: u16,
+    /// Mean soil temperature in °C.
+    pub mean_temperature: f64,
+    /// Peak to peak day/night soil temperature swing in °C.
+    pub daily_temperature_swing: f64,
+}
+
+impl Default for SoilModelConfig {
+    fn default() -> Self {
+        Self {
+            saturation_ml: 600.0,
+            field_capacity: 0.7,
+            drainage_per_hour: 0.5,
+            evapotranspiration_ml_per_hour: 6.0,
+            pump_flow_ml_per_s: 20.0,
+            raw_dry: 320,
+            raw_wet: 1015,
+            raw_noise: 12,
+            mean_temperature: 20.0,
+            daily_temperature_swing: 6.0,
+        }
+    }
+}
+
+/// Water balance of a single pot: pump input, drainage above field capacity and evapotranspiration.
+pub struct SoilModel {
+    config: SoilModelConfig,
+    water_ml: f64,
+    drained_ml: f64,
+    pumped_ml: f64,
+}
+
+impl SoilModel {
+    pub fn new(config: SoilModelConfig, initial_fraction: f64) -> Self {
+        Self {
+            water_ml: config.saturation_ml * initial_fraction.clamp(0.0, 1.0),
+            config,
+            drained_ml: 0.0,
+            pumped_ml: 0.0,
+        }
+    }
+
+    /// Water content as a fraction of saturation.
+    pub fn water_fraction(&self) -> f64 {
+        self.water_ml / self.config.saturation_ml
+    }
+
+    pub fn pumped_ml(&self) -> f64 {
+        self.pumped_ml
+    }
+
+    pub fn drained_ml(&self) -> f64 {
+        self.drained_ml
+    }
+
+    /// Soil temperature at `at`, a triangle wave peaking mid afternoon.
+    pub fn temperature(&self, at: Instant) -> f64 {
+        const DAY_S: u64 = 24 * 60 * 60;
+        let phase = ((at.as_secs() + 9 * 60 * 60) % DAY_S) as f64 / DAY_S as f64;
+        let triangle = if phase < 0.5 { 4.0 * phase - 1.0 } else { 3.0 - 4.0 * phase };
+        self.config.mean_temperature + 0.5 * self.config.daily_temperature_swing * triangle
+    }
+
+    /// Advances the model by `dt` with the pump running at `duty` percent.
+    pub fn step(&mut self, dt: Duration, duty: u8, temperature: f64) {
+        let seconds = dt.as_micros() as f64 / 1_000_000.0;
+        let hours = seconds / 3600.0;
+        let saturation = self.config.saturation_ml;
+
+        let pumped = self.config.pump_flow_ml_per_s * (duty.min(100) as f64 / 100.0) * seconds;
+        self.pumped_ml += pumped;
+        self.water_ml += pumped;
+
+        let field_capacity = self.config.field_capacity * saturation;
+        if self.water_ml > field_capacity {
+            let drained = ((self.water_ml - field_capacity) * self.config.drainage_per_hour * hours)
+                .min(self.water_ml - field_capacity);
+            self.water_ml -= drained;
+            self.drained_ml += drained;
+        }
+        if self.water_ml > saturation {
+            self.drained_ml += self.water_ml - saturation;
+            self.water_ml = saturation;
+        }
+
+        // Plants transpire less the drier the soil gets and roughly double per 10 °C.
+        let availability = (self.water_ml / field_capacity).min(1.0);
+        let temperature_factor = (1.0 + (temperature - 20.0) / 10.0).max(0.1);
+        let evaporated = self.config.evapotranspiration_ml_per_hour * availability * temperature_factor * hours;
+        self.water_ml = (self.water_ml - evaporated).max(0.0);
+    }
+
+    /// Noise free raw Seesaw reading for the current water content.
+    pub fn raw_moisture(&self) -> u16 {
+        let span = self.config.raw_wet as f64 - self.config.raw_dry as f64;
+        (self.config.raw_dry as f64 + span * self.water_fraction()) as u16
+    }
+}
+
+/// Deterministic xorshift generator so runs can be reproduced from a seed.
+pub struct NoiseSource {
+    state: u32,
+}
+
+impl NoiseSource {
+    pub fn new(seed: u32) -> Self {
+        Self { state: seed.max(1) }
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.state = x;
+        x
+    }
+
+    /// Uniform integer noise in `-(amplitude / 2)..=amplitude / 2`.
+    pub fn centered(&mut self, amplitude: u16) -> i32 {
+        if amplitude == 0 {
+            return 0;
+        }
+        (self.next_u32() % (amplitude as u32 + 1)) as i32 - (amplitude / 2) as i32
+    }
+}
+
+/// Channels wired between the simulated sensor, the estimator under test and the simulated pump.
+pub struct SimulationIo<const RN: usize, const ON: usize, const EN: usize> {
+    pub messurements: Channel<NoopRawMutex, seesaw::Messurement, RN>,
+    pub messurement_log: Channel<NoopRawMutex, AggregateRecord, ON>,
+    pub events: Channel<NoopRawMutex, Event, EN>,
+    pub pump: PumpChannel,
+    pub persist: PersistQueue,
+}
+
+impl<const RN: usize, const ON: usize, const EN: usize> SimulationIo<RN, ON, EN> {
+    pub fn new() -> Self {
+        Self { messurements: Channel::new(), messurement_log: Channel::new(), events: Channel::new(), pump: PumpChannel::new(), persist: Channel::new() }
+    }
+
+    pub fn estimator<'a>(&'a self, profile: &'a PlantProfile) -> SoilEstimator<'a, RN, ON, EN> {
+        let fault_detector = SensorFaultDetector::new(Default::default());
+        let calibration = StoredCalibration { calibration: profile.calibration, mode: CalibrationMode::Learning };
+        let channels = EstimatorChannels { messurements: self.messurements.receiver(), pump: &self.pump, aggregates: self.messurement_log.sender(), events: self.events.sender(), persist: &self.persist };
+        SoilEstimator::new(0, channels, fault_detector, profile, calibration)
+    }
+}
+
+impl<const RN: usize, const ON: usize, const EN: usize> Default for SimulationIo<RN, ON, EN> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PumpRecord {
+    pub at: Instant,
+    pub duty: u8,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct LogRecord {
+    pub at: Instant,
+    pub water_fraction: f64,
+    pub record: AggregateRecord,
+}
+
+/// Closed loop between a `SoilModel` and a `SoilEstimator` running on virtual time.
+pub struct Simulation<const PN: usize, const LN: usize> {
+    model: SoilModel,
+    noise: NoiseSource,
+    now: Instant,
+    sample_period: Duration,
+    duty: u8,
+    /// Command being carried out, when it started and when it is due to end.
+    active: Option<(PumpCommand, Instant, Option<Instant>)>,
+    /// Flow the simulated controller believes in, doses are planned and reported with it.
+    flow: FlowCalibration,
+    pump_log: Vec<PumpRecord, PN>,
+    messurement_log: Vec<LogRecord, LN>,
+}
+
+impl<const PN: usize, const LN: usize> Simulation<PN, LN> {
+    pub fn new(model: SoilModel, seed: u32, sample_period: Duration) -> Self {
+        let flow = FlowCalibration::new(&[FlowPoint { duty: 100, ml_per_s: model.config.pump_flow_ml_per_s }]);
+        Self {
+            model,
+            noise: NoiseSource::new(seed),
+            now: Instant::from_ticks(0),
+            sample_period,
+            duty: 0,
+            active: None,
+            flow,
+            pump_log: Vec::new(),
+            messurement_log: Vec::new(),
+        }
+    }
+
+    pub fn now(&self) -> Instant {
+        self.now
+    }
+
+    pub fn model(&self) -> &SoilModel {
+        &self.model
+    }
+
+    /// Replaces the flow calibration, e.g. with a wrong one to see the effect of a badly calibrated pump.
+    pub fn set_flow_calibration(&mut self, flow: FlowCalibration) {
+        self.flow = flow;
+    }
+
+    /// Pump duty changes in the order the estimator commanded them.
+    pub fn pump_log(&self) -> &[PumpRecord] {
+        &self.pump_log
+    }
+
+    /// Aggregated records the estimator forwarded for upload.
+    pub fn messurement_log(&self) -> &[LogRecord] {
+        &self.messurement_log
+    }
+
+    /// Simulates one sample period and lets the estimator process the resulting messurement.
+    pub fn step<const RN: usize, const ON: usize, const EN: usize>(&mut self, io: &SimulationIo<RN, ON, EN>, estimator: &mut SoilEstimator<'_, RN, ON, EN>) {
+        let temperature = self.model.temperature(self.now);
+        let end = self.now + self.sample_period;
+        if let Some(until) = self.active.and_then(|(_, _, until)| until).filter(|until| *until <= end) {
+            self.model.step(until - self.now, self.duty, temperature);
+            self.now = until;
+            self.finish(io, PumpOutcome::Completed);
+            self.set_duty(0);
+        }
+        self.model.step(end - self.now, self.duty, temperature);
+        self.now = end;
+
+        let raw = self.model.raw_moisture() as i32 + self.noise.centered(self.model.config.raw_noise);
+        let messurement = seesaw::Messurement {
+            temp: temperature as f32,
+            moisture: raw.clamp(0, u16::MAX as i32) as u16,
+            at: self.now,
+        };
+        estimator.update(messurement);
+
+        if let Poll::Ready(command) = poll_once(io.pump.command.wait()) {
+            self.command(io, command);
+        }
+        // Nothing to persist to, the learned calibration stays in the estimator.
+        while io.persist.try_receive().is_ok() {}
+        while let Ok(record) = io.messurement_log.try_receive() {
+            let record = LogRecord { at: self.now, water_fraction: self.model.water_fraction(), record };
+            if self.messurement_log.push(record).is_err() {
+                error!("Simulation messurement log is full");
+            }
+        }
+    }
+
+    /// Carries out a command like the pump task would, without safety limits.
+    fn command<const RN: usize, const ON: usize, const EN: usize>(&mut self, io: &SimulationIo<RN, ON, EN>, command: PumpCommand) {
+        let outcome = match (self.active.map(|(active, _, _)| active), command) {
+            (Some(PumpCommand::Duty(_)), PumpCommand::Off) => PumpOutcome::Completed,
+            (_, PumpCommand::Off) => PumpOutcome::Aborted(AbortReason::Stopped),
+            _ => PumpOutcome::Aborted(AbortReason::Superseded),
+        };
+        self.finish(io, outcome);
+        let (duty, until) = match command {
+            PumpCommand::Off | PumpCommand::Duty(0) => (0, None),
+            PumpCommand::Duty(duty) => (duty, None),
+            PumpCommand::RunFor(duration, duty) => (duty, Some(self.now + duration)),
+            PumpCommand::Dose { ml, duty } => match self.flow.plan(ml, duty, Duration::MAX) {
+                Some((duty, run)) => (duty, Some(self.now + run)),
+                None => {
+                    let status = PumpStatus::aborted(command, AbortReason::Unsupported);
+                    if let Err(err) = io.pump.status.try_send(status) {
+                        error!("Simulation failed to report pump status {:?}", err);
+                    }
+                    self.set_duty(0);
+                    return;
+                },
+            },
+        };
+        if duty > 0 {
+            self.active = Some((command, self.now, until));
+        } else if command.reports_status() {
+            if let Err(err) = io.pump.status.try_send(PumpStatus::skipped(command)) {
+                error!("Simulation failed to report pump status {:?}", err);
+            }
+        }
+        self.set_duty(duty);
+    }
+
+    fn finish<const RN: usize, const ON: usize, const EN: usize>(&mut self, io: &SimulationIo<RN, ON, EN>, outcome: PumpOutcome) {
+        let Some((command, started, _)) = self.active.take() else {
+            return;
+        };
+        let runtime = self.now - started;
+        let volume_ml = self.flow.volume(self.duty, runtime);
+        if let Err(err) = io.pump.status.try_send(PumpStatus { command, outcome, started, runtime, average_duty: self.duty as f64, volume_ml, current_ma: RunningStats::new() }) {
+            error!("Simulation failed to report pump status {:?}", err);
+        }
+    }
+
+    fn set_duty(&mut self, duty: u8) {
+        if duty != self.duty && self.pump_log.push(PumpRecord { at: self.now, duty }).is_err() {
+            error!("Simulation pump log is full");
+        }
+        self.duty = duty;
+    }
+
+    /// Runs the closed loop for `duration` of virtual time.
+    pub fn run<const RN: usize, const ON: usize, const EN: usize>(&mut self, io: &SimulationIo<RN, ON, EN>, estimator: &mut SoilEstimator<'_, RN, ON, EN>, duration: Duration) {
+        let end = self.now + duration;
+        while self.now < end {
+            self.step(io, estimator);
+        }
+    }
+}
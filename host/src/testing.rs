@@ -0,0 +1,113 @@
+//! Helpers for tests that run the firmware tasks on the mock time driver.
+extern crate std;
+
+use core::{future::Future, pin::pin, task::{Context, Poll, RawWaker, RawWakerVTable, Waker}};
+use std::sync::{Mutex, MutexGuard};
+
+use embassy_time::{Duration, Instant, MockDriver};
+
+static TIME: Mutex<()> = Mutex::new(());
+
+/// Holds the mock clock for one test, it is shared by all tests of a binary. The clock is not reset,
+/// that would drop the alarm of the timer queue, so tests work relative to `Instant::now()`.
+pub fn lock_time() -> MutexGuard<'static, ()> {
+    TIME.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn noop_waker() -> Waker {
+    const VTABLE: RawWakerVTable = RawWakerVTable::new(|_| RawWaker::new(core::ptr::null(), &VTABLE), |_| {}, |_| {}, |_| {});
+    // SAFETY: the vtable ignores the data pointer.
+    unsafe { Waker::from_raw(RawWaker::new(core::ptr::null(), &VTABLE)) }
+}
+
+/// Polls `future` while advancing the mock clock in `tick` steps until it finishes or `limit` of
+/// virtual time has passed. Timers fire at the first poll after they are due, so `tick` bounds the
+/// timing error.
+pub fn run_for<F: Future>(future: F, limit: Duration, tick: Duration) -> Option<F::Output> {
+    let mut future = pin!(future);
+    let waker = noop_waker();
+    let mut context = Context::from_waker(&waker);
+    let end = Instant::now() + limit;
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+            return Some(output);
+        }
+        if Instant::now() >= end {
+            return None;
+        }
+        MockDriver::get().advance(tick);
+    }
+}
+
+/// Flash in RAM that counts the writes, erased bytes read as 0xFF.
+pub struct MemoryStorage {
+    pub bytes: std::vec::Vec<u8>,
+    /// Start and length of every write.
+    pub writes: std::vec::Vec<(u32, usize)>,
+}
+
+impl MemoryStorage {
+    pub fn new(capacity: usize) -> Self {
+        Self { bytes: std::vec![0xFF; capacity], writes: std::vec::Vec::new() }
+    }
+}
+
+impl embedded_storage::ReadStorage for MemoryStorage {
+    type Error = ();
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), ()> {
+        let source = self.bytes.get(offset as usize..offset as usize + bytes.len()).ok_or(())?;
+        bytes.copy_from_slice(source);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        self.bytes.len()
+    }
+}
+
+impl embedded_storage::Storage for MemoryStorage {
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), ()> {
+        let target = self.bytes.get_mut(offset as usize..offset as usize + bytes.len()).ok_or(())?;
+        target.copy_from_slice(bytes);
+        self.writes.push((offset, bytes.len()));
+        Ok(())
+    }
+}
+
+/// Lets a test look at the flash after the code under test is done with it.
+impl embedded_storage::ReadStorage for &mut MemoryStorage {
+    type Error = ();
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), ()> {
+        (**self).read(offset, bytes)
+    }
+
+    fn capacity(&self) -> usize {
+        (**self).capacity()
+    }
+}
+
+impl embedded_storage::Storage for &mut MemoryStorage {
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), ()> {
+        (**self).write(offset, bytes)
+    }
+}
+
+/// Houseplant zone on the first probe address without sensors or nutrient pump.
+pub fn zone_config(actuator: crate::zone::Actuator) -> crate::zone::ZoneConfig {
+    crate::zone::ZoneConfig {
+        name: "test",
+        sensor_address: 0x36,
+        profile: "houseplant",
+        actuator,
+        pump_limits: Default::default(),
+        pump_ramp: Default::default(),
+        flow_sensor: None,
+        current_sense: None,
+        pump_flow: &[crate::flow_calibration::FlowPoint { duty: 100, ml_per_s: 20.0 }],
+        prime_time: Duration::from_secs(8),
+        manual_calibration: None,
+        nutrient: None,
+    }
+}
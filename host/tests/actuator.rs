@@ -0,0 +1,66 @@
+use dewy_host::{actuator::{ActuatorError, ActuatorRegistry}, expander::{ExpanderConfig, ExpanderKind}, testing::zone_config, zone::{Actuator, CurrentSense, FlowSensor, McpwmUnit, Operator, PwmOutput, PwmPin, ValveOutput, ZoneConfig}};
+
+const PUMP0: PwmOutput = PwmOutput::new(McpwmUnit::Mcpwm0, Operator::Operator0, PwmPin::A);
+const PUMP1: PwmOutput = PwmOutput::new(McpwmUnit::Mcpwm0, Operator::Operator1, PwmPin::A);
+const PCF8574: ExpanderConfig = ExpanderConfig { kind: ExpanderKind::Pcf8574, address: 0x20, active_low: true };
+
+fn manifold(valve: usize) -> ZoneConfig {
+    zone_config(Actuator::Manifold { pump: PUMP0, valve: ValveOutput::Gpio(valve) })
+}
+
+fn registry(zones: &[ZoneConfig]) -> Result<ActuatorRegistry, ActuatorError> {
+    ActuatorRegistry::new(zones, &[PCF8574])
+}
+
+#[test]
+fn zones_of_a_manifold_share_the_pump() {
+    let registry = registry(&[manifold(0), zone_config(Actuator::Pump(PUMP1)), manifold(1)]).unwrap();
+    let pumps = registry.pumps();
+    assert_eq!(pumps.len(), 2);
+    assert!(pumps[0].is_manifold());
+    assert_eq!(pumps[0].owner(), 0);
+    assert_eq!(pumps[0].zones.iter().map(|(zone, _)| *zone).collect::<Vec<_>>(), [0, 2]);
+    assert!(!pumps[1].is_manifold());
+}
+
+#[test]
+fn outputs_are_claimed_once() {
+    let pump = zone_config(Actuator::Pump(PUMP0));
+    assert_eq!(registry(&[pump, pump]).err(), Some(ActuatorError::PumpShared(PUMP0)));
+    assert_eq!(registry(&[pump, manifold(0)]).err(), Some(ActuatorError::PumpShared(PUMP0)));
+    assert_eq!(registry(&[manifold(0), manifold(0)]).err(), Some(ActuatorError::ValveShared(ValveOutput::Gpio(0))));
+}
+
+#[test]
+fn unwired_outputs_are_refused() {
+    assert_eq!(registry(&[manifold(2)]).err(), Some(ActuatorError::NoValvePin(2)));
+    let unwired = PwmOutput::new(McpwmUnit::Mcpwm1, Operator::Operator0, PwmPin::B);
+    assert_eq!(registry(&[zone_config(Actuator::Pump(unwired))]).err(), Some(ActuatorError::NoPwmPin(unwired)));
+    let expander_valve = |expander, pin| zone_config(Actuator::Valve(ValveOutput::Expander { expander, pin }));
+    assert!(registry(&[expander_valve(0, 7)]).is_ok());
+    assert_eq!(registry(&[expander_valve(0, 8)]).err(), Some(ActuatorError::NoExpanderPin { expander: 0, pin: 8 }));
+    assert_eq!(registry(&[expander_valve(1, 0)]).err(), Some(ActuatorError::NoExpander(1)));
+    assert_eq!(ActuatorRegistry::new(&[], &[PCF8574; 3]).err(), Some(ActuatorError::TooManyExpanders));
+    assert_eq!(registry(&[zone_config(Actuator::Pump(PUMP0)); 5]).err(), Some(ActuatorError::TooManyZones));
+}
+
+#[test]
+fn sensor_inputs_are_checked() {
+    let metered = |input| ZoneConfig { flow_sensor: Some(FlowSensor { input, pulses_per_litre: 450.0 }), ..zone_config(Actuator::Pump(PUMP0)) };
+    assert_eq!(registry(&[metered(2)]).err(), Some(ActuatorError::NoFlowSensorInput(2)));
+    let second = ZoneConfig { actuator: Actuator::Pump(PUMP1), ..metered(1) };
+    assert!(registry(&[metered(0), second]).is_ok());
+    assert_eq!(registry(&[metered(1), second]).err(), Some(ActuatorError::FlowSensorShared(1)));
+
+    let sensed = |input| ZoneConfig { current_sense: Some(CurrentSense { input, ma_per_count: 1.0, limits: Default::default() }), ..zone_config(Actuator::Pump(PUMP0)) };
+    assert_eq!(registry(&[sensed(3)]).err(), Some(ActuatorError::NoShuntInput(3)));
+    let second = ZoneConfig { actuator: Actuator::Pump(PUMP1), ..sensed(2) };
+    assert_eq!(registry(&[sensed(2), second]).err(), Some(ActuatorError::CurrentSenseShared(2)));
+}
+
+#[test]
+fn zone_config_names_a_preset() {
+    assert_eq!(zone_config(Actuator::Pump(PUMP0)).plant_profile().map(|profile| profile.name), Some("houseplant"));
+    let typo = ZoneConfig { profile: "housplant", ..zone_config(Actuator::Pump(PUMP0)) };
+    assert!(typo.plant_profile().is_none());
+}
@@ -0,0 +1,52 @@
+use dewy_host::auth::{AuthError, Authentication, DeviceKey};
+
+const KEY: DeviceKey = DeviceKey { id: "planter-1", secret: b"s3cret" };
+const LOCAL_NONCE: u64 = 0x0123_4567_89ab_cdef;
+const SERVER_NONCE: &[u8] = b"00000000deadbeef";
+/// Signatures as `tools/auth_server.py` computes them for `KEY` and the nonces above.
+const SERVER_SIGNATURE: &[u8] = b"354438b56bde7a3d817fb484286d77c1d8d2514994a2247b8d7801ac1a7ff1a8";
+const UPLOAD_SIGNATURE: &str = "6d02ecae3899cd50b9411a86eceac779b8558223a5bcacf795d0f55e6d652729";
+
+#[test]
+fn handshake_names_the_device_and_its_nonce() {
+    assert_eq!(KEY.handshake_header(LOCAL_NONCE).as_str(), "Dewy-Handshake id=planter-1,nonce=0123456789abcdef");
+    assert_eq!(KEY.handshake_header(7).as_str(), "Dewy-Handshake id=planter-1,nonce=0000000000000007");
+}
+
+#[test]
+fn accepts_the_server_that_knows_the_secret() {
+    let authentication = Authentication::accept(&KEY, LOCAL_NONCE, Some(SERVER_NONCE), Some(SERVER_SIGNATURE)).unwrap();
+    assert_eq!(authentication, Authentication { server_nonce: 0xdead_beef });
+    // Hex case and surrounding space of the headers do not matter.
+    let upper = SERVER_SIGNATURE.to_ascii_uppercase();
+    assert!(Authentication::accept(&KEY, LOCAL_NONCE, Some(b" 00000000DEADBEEF "), Some(&upper)).is_ok());
+}
+
+#[test]
+fn refuses_replayed_or_forged_handshakes() {
+    // A response recorded for another device nonce.
+    assert_eq!(Authentication::accept(&KEY, LOCAL_NONCE + 1, Some(SERVER_NONCE), Some(SERVER_SIGNATURE)), Err(AuthError::BadSignature));
+    assert_eq!(Authentication::accept(&KEY, LOCAL_NONCE, Some(b"00000000deadbeee"), Some(SERVER_SIGNATURE)), Err(AuthError::BadSignature));
+    let other = DeviceKey { id: "planter-1", secret: b"guess" };
+    assert_eq!(Authentication::accept(&other, LOCAL_NONCE, Some(SERVER_NONCE), Some(SERVER_SIGNATURE)), Err(AuthError::BadSignature));
+}
+
+#[test]
+fn refuses_incomplete_handshake_responses() {
+    assert_eq!(Authentication::accept(&KEY, LOCAL_NONCE, None, Some(SERVER_SIGNATURE)), Err(AuthError::MissingHeader));
+    assert_eq!(Authentication::accept(&KEY, LOCAL_NONCE, Some(SERVER_NONCE), None), Err(AuthError::MissingHeader));
+    assert_eq!(Authentication::accept(&KEY, LOCAL_NONCE, Some(b"nonce"), Some(SERVER_SIGNATURE)), Err(AuthError::Malformed));
+    assert_eq!(Authentication::accept(&KEY, LOCAL_NONCE, Some(SERVER_NONCE), Some(&SERVER_SIGNATURE[..62])), Err(AuthError::Malformed));
+    let mut not_hex = SERVER_SIGNATURE.to_vec();
+    not_hex[10] = b'g';
+    assert_eq!(Authentication::accept(&KEY, LOCAL_NONCE, Some(SERVER_NONCE), Some(&not_hex)), Err(AuthError::Malformed));
+}
+
+#[test]
+fn signs_requests_with_the_server_nonce() {
+    let authentication = Authentication { server_nonce: 0xdead_beef };
+    let header = authentication.sign_request(&KEY, "POST", "/api/upload", br#"{"a":1}"#);
+    assert_eq!(header.as_str(), format!("Dewy-HMAC-SHA256 id=planter-1,nonce=00000000deadbeef,signature={}", UPLOAD_SIGNATURE));
+    // The signature covers the body.
+    assert_ne!(authentication.sign_request(&KEY, "POST", "/api/upload", br#"{"a":2}"#), header);
+}
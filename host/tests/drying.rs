@@ -0,0 +1,63 @@
+use dewy_host::drying::DryingModel;
+use embassy_time::{Duration, Instant};
+
+const FLOOR: f64 = 320.0;
+
+fn at_minutes(minutes: u64) -> Instant {
+    Instant::from_ticks(0) + Duration::from_secs(minutes * 60)
+}
+
+/// Moisture of a pot drying with `rate` per hour from 600 counts above the floor.
+fn moisture(rate: f64, minutes: u64) -> f64 {
+    FLOOR + 600.0 * (-rate * minutes as f64 / 60.0).exp()
+}
+
+fn dried(rate: f64, until_minutes: u64) -> DryingModel {
+    let mut model = DryingModel::new(FLOOR, 60.0);
+    for minutes in (0..=until_minutes).step_by(5) {
+        model.update(moisture(rate, minutes), at_minutes(minutes));
+    }
+    model
+}
+
+#[test]
+fn fit_recovers_the_drying_rate() {
+    let estimate = dried(0.1, 3 * 60).estimate(FLOOR + 200.0).expect("three hours of drying are enough");
+    assert!((estimate.rate_per_hour - 0.1).abs() < 1e-6);
+    // 600 counts decay to 200 after ln(3) / 0.1 hours, three of them have passed.
+    assert!((estimate.hours_to_threshold - (3.0f64.ln() / 0.1 - 3.0)).abs() < 1e-3);
+}
+
+#[test]
+fn no_estimate_before_enough_drying() {
+    assert_eq!(dried(0.1, 30).estimate(FLOOR + 200.0), None);
+    assert_eq!(dried(0.1, 3 * 60).estimate(FLOOR - 1.0), None);
+}
+
+#[test]
+fn rising_moisture_is_not_drying() {
+    let mut model = DryingModel::new(FLOOR, 1000.0);
+    for minutes in (0..=3 * 60).step_by(5) {
+        model.update(FLOOR + 100.0 + minutes as f64 / 10.0, at_minutes(minutes));
+    }
+    assert_eq!(model.estimate(FLOOR + 50.0), None);
+}
+
+#[test]
+fn watering_restarts_the_fit() {
+    let mut model = dried(0.1, 3 * 60);
+    model.update(moisture(0.1, 0), at_minutes(3 * 60 + 5));
+    assert_eq!(model.estimate(FLOOR + 200.0), None);
+    assert_eq!(model.snapshot().n, 1);
+}
+
+#[test]
+fn restored_fit_continues() {
+    let model = dried(0.1, 3 * 60);
+    let mut restored = DryingModel::new(FLOOR, 60.0);
+    restored.restore(&model.snapshot(), at_minutes(10_000));
+    assert_eq!(restored.estimate(FLOOR + 200.0), model.estimate(FLOOR + 200.0));
+    restored.update(moisture(0.1, 3 * 60 + 5), at_minutes(10_005));
+    let estimate = restored.estimate(FLOOR + 200.0).expect("the fit goes on");
+    assert!((estimate.rate_per_hour - 0.1).abs() < 1e-6);
+}
@@ -0,0 +1,111 @@
+use core::convert::Infallible;
+
+use dewy_host::http::{HttpClient, HttpError, Method};
+use embassy_futures::block_on;
+use embedded_io_async::{ErrorType, Read, Write};
+
+/// Connection that hands out a canned response `piece` bytes at a time and keeps the request.
+struct Wire {
+    response: &'static [u8],
+    read: usize,
+    piece: usize,
+    request: Vec<u8>,
+}
+
+impl Wire {
+    fn new(response: &'static [u8], piece: usize) -> Self {
+        Self { response, read: 0, piece, request: Vec::new() }
+    }
+}
+
+impl ErrorType for Wire {
+    type Error = Infallible;
+}
+
+impl Read for Wire {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Infallible> {
+        let length = buf.len().min(self.piece).min(self.response.len() - self.read);
+        buf[..length].copy_from_slice(&self.response[self.read..self.read + length]);
+        self.read += length;
+        Ok(length)
+    }
+}
+
+impl Write for Wire {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Infallible> {
+        self.request.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    async fn flush(&mut self) -> Result<(), Infallible> {
+        Ok(())
+    }
+}
+
+/// Status and body of the response to a GET of `/`, or how it failed.
+fn get(response: &'static [u8], piece: usize, buffer_len: usize) -> Result<(u16, Vec<u8>), HttpError<Infallible>> {
+    let mut wire = Wire::new(response, piece);
+    let mut buffer = vec![0; buffer_len];
+    let mut client = HttpClient::new(&mut wire, "example.org", &mut buffer);
+    block_on(client.request(Method::Get, "/", &[], &[])).map(|response| (response.status, response.body.to_vec()))
+}
+
+#[test]
+fn writes_the_request_head_and_body() {
+    let mut wire = Wire::new(b"HTTP/1.1 204 No Content\r\n\r\n", 64);
+    let mut buffer = [0; 256];
+    let mut client = HttpClient::new(&mut wire, "example.org", &mut buffer);
+    let status = block_on(client.request(Method::Post, "/api/upload", &[("Content-Type", b"application/json")], b"{}")).unwrap().status;
+    assert_eq!(status, 204);
+    assert_eq!(
+        String::from_utf8(wire.request).unwrap(),
+        "POST /api/upload HTTP/1.1\r\nHost: example.org\r\nUser-Agent: Dewy\r\nContent-Length: 2\r\nContent-Type: application/json\r\n\r\n{}",
+    );
+}
+
+#[test]
+fn reads_a_body_of_a_given_length() {
+    let response = b"HTTP/1.1 200 OK\r\ncontent-length: 11\r\n\r\nhello worldnext response";
+    assert_eq!(get(response, 5, 256).unwrap(), (200, b"hello world".to_vec()));
+}
+
+#[test]
+fn decodes_a_chunked_body() {
+    let response = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n6;ext=1\r\n world\r\n0\r\n\r\n";
+    for piece in [1, 3, 7, 256] {
+        assert_eq!(get(response, piece, 256).unwrap(), (200, b"hello world".to_vec()), "read {} bytes at a time", piece);
+    }
+    let broken = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello!!0\r\n\r\n";
+    assert!(matches!(get(broken, 64, 256), Err(HttpError::InvalidChunk)));
+}
+
+#[test]
+fn reads_until_the_connection_closes_without_a_length() {
+    let response = b"HTTP/1.1 200 OK\r\nConnection: close\r\n\r\n{\"commands\": []}";
+    assert_eq!(get(response, 4, 256).unwrap(), (200, b"{\"commands\": []}".to_vec()));
+}
+
+#[test]
+fn no_content_has_no_body() {
+    // Anything after the head belongs to the next response.
+    let response = b"HTTP/1.1 204 No Content\r\n\r\nHTTP/1.1 200 OK";
+    assert_eq!(get(response, 64, 256).unwrap(), (204, Vec::new()));
+}
+
+#[test]
+fn finds_headers_ignoring_case() {
+    let mut wire = Wire::new(b"HTTP/1.1 200 OK\r\nX-Dewy-Nonce: 0123abcd\r\nContent-Length: 0\r\n\r\n", 64);
+    let mut buffer = [0; 256];
+    let mut client = HttpClient::new(&mut wire, "example.org", &mut buffer);
+    let response = block_on(client.request(Method::Get, "/auth", &[], &[])).unwrap();
+    assert_eq!(response.header("x-dewy-nonce"), Some(b"0123abcd".as_slice()));
+    assert_eq!(response.header("X-Dewy-Signature"), None);
+}
+
+#[test]
+fn incomplete_and_oversized_responses_fail() {
+    assert!(matches!(get(b"HTTP/1.1 200 OK\r\nContent-Length: 20\r\n\r\ntoo short", 64, 256), Err(HttpError::Closed)));
+    assert!(matches!(get(b"HTTP/1.1 200 OK\r\nContent-Length: 200\r\n\r\n", 64, 64), Err(HttpError::TooLarge)));
+    assert!(matches!(get(b"HTTP/1.1 200 OK\r\nContent-Length: many\r\n\r\n", 64, 256), Err(HttpError::InvalidContentLength)));
+    assert!(matches!(get(b"HTTP/1.1 200 OK\r\n", 64, 256), Err(HttpError::Closed)));
+}
@@ -0,0 +1,93 @@
+use dewy_host::{events::{Event, EVENT_QUEUE}, flow_calibration::{FlowCalibration, FlowPoint}, flow_meter::MockFlowMeter, manifold::{run_manifold, ManifoldZone}, persistence::PersistQueue, pump_command::{PumpChannel, PumpCommand, PumpOutcome, PumpStatus}, pump_control::{run_output, PumpOutput}, pump_driver::{DriverAction, MockPumpDriver}, pump_safety::SafetyLimits, ramp::{RampConfig, RampProfile}, testing::{lock_time, run_for}};
+use embassy_futures::{join::join, select::{select, Either}};
+use embassy_sync::{blocking_mutex::raw::NoopRawMutex, channel::Channel, signal::Signal};
+use embassy_time::{Duration, Instant, Timer};
+
+const TICK: Duration = Duration::from_millis(10);
+const COOLDOWN: Duration = Duration::from_secs(10);
+
+/// A manifold pump run by the real output loop and two zones behind valves.
+struct Rig {
+    pump: PumpChannel,
+    zones: [PumpChannel; 2],
+    reset: Signal<NoopRawMutex, ()>,
+    events: Channel<NoopRawMutex, Event, EVENT_QUEUE>,
+    persist: PersistQueue,
+}
+
+impl Rig {
+    fn new() -> Self {
+        Self { pump: PumpChannel::new(), zones: [PumpChannel::new(), PumpChannel::new()], reset: Signal::new(), events: Channel::new(), persist: Channel::new() }
+    }
+
+    async fn command(&self, zone: usize, command: PumpCommand) -> (PumpStatus, Instant) {
+        self.zones[zone].command.signal(command);
+        let status = self.zones[zone].status.receive().await;
+        (status, Instant::now())
+    }
+
+    /// Runs `script` against the manifold and returns it with the valve timelines.
+    fn run<S: core::future::Future>(&self, script: S) -> (S::Output, [MockPumpDriver<16>; 2]) {
+        let mut pump = MockPumpDriver::<64>::new();
+        let output = PumpOutput {
+            zone: 0,
+            channel: &self.pump,
+            reset: &self.reset,
+            limits: SafetyLimits { min_cooldown: COOLDOWN, ..Default::default() },
+            ramp: RampConfig { up: RampProfile::Step, down: RampProfile::Step },
+            flow: FlowCalibration::new(&[FlowPoint { duty: 100, ml_per_s: 20.0 }]),
+            events: self.events.sender(),
+            persist: &self.persist,
+            tank: None,
+            current: None,
+        };
+        let mut zones = [0, 1].map(|zone| ManifoldZone { zone, channel: &self.zones[zone], valve: MockPumpDriver::<16>::new() });
+        let tasks = join(run_output(&mut pump, None::<&mut MockFlowMeter>, output), run_manifold(&self.pump, &mut zones, Duration::from_ticks(0), COOLDOWN));
+        let output = match run_for(select(tasks, script), Duration::from_secs(60), TICK).expect("script finished in time") {
+            Either::First(_) => unreachable!("the manifold never returns"),
+            Either::Second(output) => output,
+        };
+        (output, zones.map(|zone| zone.valve))
+    }
+}
+
+fn opened_at(valve: &MockPumpDriver<16>) -> Option<Instant> {
+    valve.timeline().iter().find(|(_, action)| *action == DriverAction::Duty(100.0)).map(|(at, _)| *at)
+}
+
+#[test]
+fn waiting_zone_runs_after_the_cooldown() {
+    let _time = lock_time();
+    let rig = Rig::new();
+    let run = PumpCommand::RunFor(Duration::from_secs(2), 100);
+    let ((first, second), valves) = rig.run(join(rig.command(0, run), async {
+        Timer::after(Duration::from_secs(1)).await;
+        rig.command(1, run).await
+    }));
+
+    assert_eq!((first.0.outcome, second.0.outcome), (PumpOutcome::Completed, PumpOutcome::Completed));
+    let opened = opened_at(&valves[1]).expect("second valve opened");
+    assert!(opened >= first.1 + COOLDOWN, "opened {:?} after the first run", opened - first.1);
+    assert!(opened < first.1 + COOLDOWN + Duration::from_millis(100), "opened {:?} after the first run", opened - first.1);
+    let closed = valves[0].timeline().last().unwrap();
+    assert!(closed.1 == DriverAction::Duty(0.0) && closed.0 < opened);
+    assert!(rig.events.try_receive().is_err());
+}
+
+#[test]
+fn idle_manifold_holds_a_command_during_the_cooldown() {
+    let _time = lock_time();
+    let rig = Rig::new();
+    let run = PumpCommand::RunFor(Duration::from_secs(1), 100);
+    let ((first, again), valves) = rig.run(async {
+        let first = rig.command(0, run).await;
+        // Long after the valve closed, but still within the cooldown of the pump.
+        Timer::after(Duration::from_secs(3)).await;
+        (first, rig.command(0, run).await)
+    });
+
+    assert_eq!(again.0.outcome, PumpOutcome::Completed);
+    assert!(again.0.started >= first.1 + COOLDOWN);
+    let openings = valves[0].timeline().iter().filter(|(_, action)| *action == DriverAction::Duty(100.0)).count();
+    assert_eq!(openings, 2);
+}
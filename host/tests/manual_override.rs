@@ -0,0 +1,143 @@
+use dewy_host::{events::{Event, EVENT_QUEUE}, manual_override::{run_override, ManualCommand, ManualQueue, OverrideConfig}, pump_command::{AbortReason, PumpChannel, PumpCommand, PumpOutcome, PumpStatus}, testing::{lock_time, run_for}, watering_history::{HistoryQuery, RunReason, WateringLog, WateringRecord}, zone::WateredChannel};
+use embassy_futures::{select::{select, Either}, yield_now};
+use embassy_sync::{blocking_mutex::raw::NoopRawMutex, channel::Channel};
+use embassy_time::{Duration, Timer};
+
+const TICK: Duration = Duration::from_millis(10);
+const PRIME: Duration = Duration::from_secs(8);
+
+/// The override layer of one zone, the test plays its automatic controller and its pump output.
+struct Rig {
+    auto: PumpChannel,
+    manual: ManualQueue,
+    output: PumpChannel,
+    events: Channel<NoopRawMutex, Event, EVENT_QUEUE>,
+    history: WateringLog,
+    watered: WateredChannel,
+}
+
+impl Rig {
+    fn new() -> Self {
+        Self { auto: PumpChannel::new(), manual: Channel::new(), output: PumpChannel::new(), events: Channel::new(), history: WateringLog::new(), watered: Channel::new() }
+    }
+
+    fn run<S: core::future::Future>(&self, script: S) -> S::Output {
+        let task = run_override(OverrideConfig { zone: 0, auto: &self.auto, manual: &self.manual, output: &self.output, prime: PRIME, events: self.events.sender(), history: &self.history, watered: Some(&self.watered) });
+        match run_for(select(task, script), Duration::from_secs(20 * 60), TICK).expect("script finished in time") {
+            Either::First(_) => unreachable!("the override never returns"),
+            Either::Second(output) => output,
+        }
+    }
+
+    /// Lets the override take what was sent to it.
+    async fn settle(&self) {
+        for _ in 0..4 {
+            yield_now().await;
+        }
+    }
+
+    /// Plays the output: takes the next command and reports it ran for `seconds`.
+    async fn output_runs(&self, seconds: u64) -> PumpCommand {
+        let command = self.output.command.wait().await;
+        let status = PumpStatus { runtime: Duration::from_secs(seconds), outcome: PumpOutcome::Completed, ..PumpStatus::skipped(command) };
+        self.output.status.try_send(status).unwrap();
+        command
+    }
+
+    fn records(&self) -> heapless::Vec<WateringRecord, 8> {
+        self.history.query(&HistoryQuery::default())
+    }
+}
+
+#[test]
+fn only_automatic_runs_reach_the_nutrient_dosing() {
+    let _time = lock_time();
+    let rig = Rig::new();
+    let (auto, primed, status) = rig.run(async {
+        rig.auto.command.signal(PumpCommand::RunFor(Duration::from_secs(5), 80));
+        let auto = rig.output_runs(5).await;
+        let status = rig.auto.status.receive().await;
+        rig.manual.try_send((ManualCommand::Prime, RunReason::Manual)).unwrap();
+        let primed = rig.output_runs(8).await;
+        rig.settle().await;
+        (auto, primed, status)
+    });
+
+    assert_eq!(auto, PumpCommand::RunFor(Duration::from_secs(5), 80));
+    assert_eq!(status.outcome, PumpOutcome::Completed);
+    assert_eq!(primed, PumpCommand::RunFor(PRIME, 100));
+    let reasons: Vec<RunReason> = rig.records().iter().map(|record| record.reason).collect();
+    assert_eq!(reasons, [RunReason::Auto, RunReason::Manual]);
+    assert_eq!(rig.watered.try_receive().map(|record| record.reason), Ok(RunReason::Auto));
+    assert!(rig.watered.try_receive().is_err());
+    // The controller counts the water of the prime too.
+    let prime = rig.auto.status.try_receive().unwrap();
+    assert_eq!((prime.command, prime.runtime), (PumpCommand::RunFor(PRIME, 100), Duration::from_secs(8)));
+    assert!(rig.auto.status.try_receive().is_err());
+}
+
+#[test]
+fn queued_manual_commands_are_all_carried_out() {
+    let _time = lock_time();
+    let rig = Rig::new();
+    let commands = rig.run(async {
+        rig.manual.try_send((ManualCommand::On(40), RunReason::Remote)).unwrap();
+        rig.manual.try_send((ManualCommand::RunFor(Duration::from_secs(3), 60), RunReason::Remote)).unwrap();
+        rig.settle().await;
+        // The output only saw the last command, the first one did not run at all.
+        let ran = rig.output_runs(3).await;
+        rig.settle().await;
+        ran
+    });
+
+    assert_eq!(commands, PumpCommand::RunFor(Duration::from_secs(3), 60));
+    let records = rig.records();
+    assert_eq!(records.len(), 1);
+    assert_eq!((records[0].reason, records[0].duration), (RunReason::Remote, Duration::from_secs(3)));
+    let events: Vec<Event> = core::iter::from_fn(|| rig.events.try_receive().ok()).collect();
+    assert!(matches!(events[..], [Event::ManualControl { command: ManualCommand::On(40), .. }, Event::ManualControl { command: ManualCommand::RunFor(..), .. }, Event::PumpRun { .. }]), "{:?}", events);
+}
+
+#[test]
+fn automatic_command_replaced_before_it_ran_is_reported_once() {
+    let _time = lock_time();
+    let rig = Rig::new();
+    let (replaced, late) = rig.run(async {
+        rig.auto.command.signal(PumpCommand::RunFor(Duration::from_secs(5), 80));
+        rig.settle().await;
+        rig.manual.try_send((ManualCommand::RunFor(Duration::from_secs(2), 50), RunReason::Manual)).unwrap();
+        let replaced = rig.auto.status.receive().await;
+        rig.output_runs(2).await;
+        rig.settle().await;
+        (replaced, rig.auto.status.try_receive().ok())
+    });
+
+    assert_eq!(replaced.command, PumpCommand::RunFor(Duration::from_secs(5), 80));
+    assert_eq!(replaced.outcome, PumpOutcome::Aborted(AbortReason::Overridden));
+    // The status of the manual run follows for the records of the controller.
+    assert_eq!(late.map(|status| status.command), Some(PumpCommand::RunFor(Duration::from_secs(2), 50)));
+    let records = rig.records();
+    assert_eq!((records.len(), records[0].reason), (1, RunReason::Manual));
+}
+
+#[test]
+fn automatic_control_resumes_after_the_timeout() {
+    let _time = lock_time();
+    let rig = Rig::new();
+    let (refused, resumed) = rig.run(async {
+        rig.manual.try_send((ManualCommand::Off, RunReason::Manual)).unwrap();
+        rig.output.command.wait().await;
+        rig.auto.command.signal(PumpCommand::RunFor(Duration::from_secs(5), 80));
+        let refused = rig.auto.status.receive().await;
+        Timer::after(Duration::from_secs(15 * 60)).await;
+        // Hands back with an Off, then takes automatic commands again.
+        let off = rig.output.command.wait().await;
+        rig.auto.command.signal(PumpCommand::RunFor(Duration::from_secs(5), 80));
+        (refused, (off, rig.output_runs(5).await, rig.auto.status.receive().await))
+    });
+
+    assert_eq!(refused.outcome, PumpOutcome::Aborted(AbortReason::Overridden));
+    assert_eq!(resumed.0, PumpCommand::Off);
+    assert_eq!(resumed.1, PumpCommand::RunFor(Duration::from_secs(5), 80));
+    assert_eq!(resumed.2.outcome, PumpOutcome::Completed);
+}
@@ -0,0 +1,44 @@
+use dewy_host::{motor_current::{CurrentLimits, MotorMonitor, MotorState}, pump_safety::PumpFault};
+use embassy_time::{Duration, Instant};
+
+fn at(millis: u64) -> Instant {
+    Instant::from_ticks(0) + Duration::from_millis(millis)
+}
+
+#[test]
+fn classifies_the_current_bands() {
+    let monitor = MotorMonitor::new(CurrentLimits::default());
+    assert_eq!(monitor.classify(5.0), MotorState::OpenCircuit);
+    assert_eq!(monitor.classify(80.0), MotorState::DryRunning);
+    assert_eq!(monitor.classify(400.0), MotorState::Normal);
+    assert_eq!(monitor.classify(1500.0), MotorState::Stalled);
+}
+
+#[test]
+fn inrush_and_ramps_are_not_classified() {
+    let mut monitor = MotorMonitor::new(CurrentLimits::default());
+    assert_eq!(monitor.update(1500.0, true, at(0)), (None, false));
+    monitor.start(at(0));
+    assert_eq!(monitor.update(1500.0, true, at(100)), (None, false));
+    assert_eq!(monitor.update(400.0, false, at(600)), (None, false));
+    assert_eq!(monitor.update(400.0, true, at(600)), (Some(MotorState::Normal), true));
+    assert_eq!(monitor.update(410.0, true, at(700)), (None, true));
+    assert_eq!(monitor.state(), MotorState::Normal);
+}
+
+#[test]
+fn fault_needs_to_persist_for_the_confirm_time() {
+    let limits = CurrentLimits::default();
+    let mut monitor = MotorMonitor::new(limits);
+    monitor.start(at(0));
+    monitor.update(80.0, true, at(500));
+    assert_eq!(monitor.fault(at(1400)), None);
+    // A normal sample in between restarts the confirmation.
+    monitor.update(400.0, true, at(1000));
+    monitor.update(80.0, true, at(1200));
+    assert_eq!(monitor.fault(at(2100)), None);
+    monitor.update(85.0, true, at(2100));
+    assert_eq!(monitor.fault(at(2200)), Some(PumpFault::DryRunning));
+    monitor.stop();
+    assert_eq!((monitor.state(), monitor.fault(at(5000))), (MotorState::Off, None));
+}
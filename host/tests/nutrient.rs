@@ -0,0 +1,66 @@
+use dewy_host::{events::{Event, EVENT_QUEUE}, nutrient::{run_nutrient, NutrientDoser, NutrientLimits, NutrientSchedule}, pump_command::{PumpChannel, PumpCommand, PumpStatus}, testing::{lock_time, run_for}, watering_history::{EndCause, Fluid, HistoryQuery, RunReason, WateringLog, WateringRecord}, zone::WateredChannel};
+use embassy_futures::select::{select, Either};
+use embassy_sync::{blocking_mutex::raw::NoopRawMutex, channel::Channel};
+use embassy_time::{Duration, Instant};
+
+const LIMITS: NutrientLimits = NutrientLimits { max_dose_ml: 20.0, max_daily_ml: 30.0, min_dose_ml: 1.0 };
+const HOUR: Duration = Duration::from_secs(60 * 60);
+
+fn water(volume_ml: f64, reason: RunReason, end: EndCause) -> WateringRecord {
+    WateringRecord { id: 0, zone: 0, fluid: Fluid::Water, started: Instant::now(), duration: Duration::from_secs(30), average_duty: 80.0, volume_ml, reason, end }
+}
+
+#[test]
+fn per_litre_doses_are_capped() {
+    let start = Instant::from_ticks(0);
+    let mut doser = NutrientDoser::new(NutrientSchedule::PerLitre { ml_per_litre: 10.0 }, LIMITS, start);
+    assert_eq!(doser.dose_for(500.0, start), Some(5.0));
+    assert_eq!(doser.dose_for(0.0, start), None);
+    // Below what the pump delivers accurately.
+    assert_eq!(doser.dose_for(50.0, start), None);
+    assert_eq!(doser.dose_for(3000.0, start), Some(20.0));
+    doser.dosed(20.0, start);
+    assert_eq!(doser.dose_for(3000.0, start + HOUR), Some(10.0));
+    doser.dosed(10.0, start + HOUR);
+    assert_eq!(doser.dose_for(500.0, start + 2 * HOUR), None);
+    assert_eq!(doser.dose_for(500.0, start + 25 * HOUR), Some(5.0));
+}
+
+#[test]
+fn weekly_dose_accrues_up_to_one_week() {
+    let start = Instant::from_ticks(0);
+    let mut doser = NutrientDoser::new(NutrientSchedule::Weekly { ml_per_week: 14.0 }, LIMITS, start);
+    assert_eq!(doser.dose_for(500.0, start + 24 * HOUR), Some(2.0));
+    doser.dosed(2.0, start + 24 * HOUR);
+    // Three weeks without watering still give a single week's dose.
+    assert_eq!(doser.dose_for(500.0, start + 24 * 22 * HOUR), Some(14.0));
+}
+
+#[test]
+fn only_completed_automatic_waterings_get_nutrient() {
+    let _time = lock_time();
+    let watered: WateredChannel = Channel::new();
+    let pump = PumpChannel::new();
+    let history = WateringLog::new();
+    let events: Channel<NoopRawMutex, Event, EVENT_QUEUE> = Channel::new();
+    let doser = NutrientDoser::new(NutrientSchedule::PerLitre { ml_per_litre: 10.0 }, LIMITS, Instant::now());
+
+    let script = async {
+        watered.send(water(1000.0, RunReason::Auto, EndCause::Interrupted)).await;
+        watered.send(water(1500.0, RunReason::Remote, EndCause::Completed)).await;
+        watered.send(water(2000.0, RunReason::Auto, EndCause::TankEmpty)).await;
+        watered.send(water(500.0, RunReason::Auto, EndCause::Completed)).await;
+        let command = pump.command.wait().await;
+        pump.status.send(PumpStatus { runtime: Duration::from_secs(5), volume_ml: 5.0, ..PumpStatus::skipped(command) }).await;
+        (command, events.receive().await)
+    };
+    let Some(Either::Second((command, event))) = run_for(select(run_nutrient(0, doser, 60, &watered, &pump, &history, events.sender()), script), Duration::from_secs(1), Duration::from_millis(10)) else {
+        panic!("nutrient dosed");
+    };
+    assert_eq!(command, PumpCommand::Dose { ml: 5.0, duty: 60 });
+    assert!(!pump.command.signaled());
+
+    let records = history.query::<4>(&HistoryQuery::default());
+    assert!(matches!(records[..], [WateringRecord { fluid: Fluid::Nutrient, reason: RunReason::Auto, volume_ml, .. }] if volume_ml == 5.0), "{:?}", records);
+    assert!(matches!(event, Event::PumpRun { record } if record == records[0]));
+}
@@ -0,0 +1,77 @@
+use dewy_host::{calibration::{Calibration, CalibrationMode}, drying::DryingSnapshot, flow_calibration::{FlowCalibration, FlowPoint}, persistence::{PersistError, PersistRequest, Store, StoredCalibration, DEFAULT_OFFSET, REGION_SIZE}, soil_estimator::EstimatorSnapshot, testing::MemoryStorage, watering::WateringSnapshot};
+use embassy_time::Duration;
+
+const SECTOR_SIZE: u32 = 0x1000;
+
+fn store() -> Store<MemoryStorage> {
+    Store::new(MemoryStorage::new(REGION_SIZE as usize), 0)
+}
+
+fn calibration() -> StoredCalibration {
+    StoredCalibration { calibration: Calibration::new(310.0, 1020.0), mode: CalibrationMode::Manual }
+}
+
+fn snapshot() -> EstimatorSnapshot {
+    EstimatorSnapshot {
+        moisture: 640.5,
+        temperature: 21.25,
+        drying: DryingSnapshot { lowest: 600.0, n: 42, sum_t: 10.5, sum_y: 250.0, sum_tt: 4.0, sum_ty: 60.0, last_t: 1.5 },
+        watering: WateringSnapshot { soak_remaining: None, since_watering: Some(Duration::from_secs(3600)) },
+    }
+}
+
+fn flow() -> FlowCalibration {
+    FlowCalibration::new(&[FlowPoint { duty: 50, ml_per_s: 8.0 }, FlowPoint { duty: 100, ml_per_s: 19.5 }])
+}
+
+fn save_all<S: embedded_storage::Storage>(store: &mut Store<S>, zone: usize) where S::Error: core::fmt::Debug {
+    store.save(&PersistRequest::Calibration { zone, calibration: calibration() }).unwrap();
+    store.save(&PersistRequest::Estimator { zone, snapshot: snapshot() }).unwrap();
+    store.save(&PersistRequest::Flow { zone, calibration: flow() }).unwrap();
+}
+
+#[test]
+fn records_survive_a_round_trip() {
+    let mut store = store();
+    save_all(&mut store, 3);
+    assert_eq!(store.load_calibration(3).unwrap(), calibration());
+    assert_eq!(store.load_estimator(3).unwrap(), snapshot());
+    assert_eq!(store.load_flow(3).unwrap().points(), flow().points());
+    assert!(matches!(store.load_calibration(2), Err(PersistError::Missing)));
+}
+
+#[test]
+fn every_record_is_one_write_to_a_sector_of_its_own() {
+    let mut storage = MemoryStorage::new(REGION_SIZE as usize);
+    let mut store = Store::new(&mut storage, 0);
+    for zone in 0..4 {
+        save_all(&mut store, zone);
+    }
+    assert_eq!(storage.writes.len(), 12);
+    let mut sectors: Vec<u32> = storage.writes.iter().map(|&(offset, len)| {
+        assert_eq!(offset / SECTOR_SIZE, (offset + len as u32 - 1) / SECTOR_SIZE, "record crosses a sector");
+        offset / SECTOR_SIZE
+    }).collect();
+    sectors.sort();
+    sectors.dedup();
+    assert_eq!(sectors.len(), 12);
+}
+
+#[test]
+fn torn_record_is_corrupt() {
+    let mut storage = MemoryStorage::new(REGION_SIZE as usize);
+    Store::new(&mut storage, 0).save_calibration(0, &calibration()).unwrap();
+    let (offset, len) = storage.writes[0];
+    storage.bytes[offset as usize + len - 6] ^= 0x01;
+    assert!(matches!(Store::new(&mut storage, 0).load_calibration(0), Err(PersistError::Corrupt)));
+}
+
+#[test]
+fn store_fits_its_partition() {
+    let table = include_str!("../../partitions.csv");
+    let dewy = table.lines().find(|line| line.starts_with("dewy")).expect("partition of the store");
+    let fields: Vec<&str> = dewy.split(',').map(str::trim).collect();
+    let parse = |field: &str| u32::from_str_radix(field.trim_start_matches("0x"), 16).unwrap();
+    assert_eq!(parse(fields[3]), DEFAULT_OFFSET);
+    assert_eq!(parse(fields[4]), REGION_SIZE);
+}
@@ -0,0 +1,22 @@
+use dewy_host::plant_profile::{by_name, FERN, PRESETS};
+
+#[test]
+fn presets_are_found_by_name() {
+    for preset in PRESETS {
+        assert_eq!(by_name(preset.name).map(|profile| profile.name), Some(preset.name));
+    }
+    assert_eq!(by_name("fern").map(|profile| profile.water_below), Some(FERN.water_below));
+    assert!(by_name("cactus").is_none());
+    assert!(by_name("Fern").is_none());
+}
+
+#[test]
+fn presets_are_consistent() {
+    for preset in PRESETS {
+        assert!(preset.calibration.dry < preset.calibration.wet, "{}", preset.name);
+        assert!((0.0..1.0).contains(&preset.water_below), "{}", preset.name);
+        assert!(preset.dose_ml > 0.0 && (1..=100).contains(&preset.dose_duty), "{}", preset.name);
+        assert!(preset.aggregation_window > preset.sample_period, "{}", preset.name);
+        assert!(PRESETS.iter().filter(|other| other.name == preset.name).count() == 1, "{}", preset.name);
+    }
+}
@@ -0,0 +1,352 @@
+use dewy_host::{events::{Event, EVENT_QUEUE}, flow_calibration::{FlowCalibration, FlowPoint}, flow_meter::{FlowMeter, MockFlowMeter}, motor_current::CurrentLimits, persistence::{PersistQueue, PersistRequest}, pump_command::{AbortReason, PumpChannel, PumpCommand, PumpOutcome, PumpStatus}, pump_control::{record_flow, run_output, run_valve, PumpOutput, ValveControl}, pump_driver::{DriverAction, MockPumpDriver}, pump_safety::{PumpFault, SafetyLimits}, ramp::{RampConfig, RampProfile}, testing::{lock_time, run_for}};
+use core::cell::RefCell;
+
+use embassy_futures::select::{select, Either};
+use embassy_sync::{blocking_mutex::raw::NoopRawMutex, channel::Channel, signal::Signal};
+use embassy_time::{Duration, Instant, Timer};
+
+const TICK: Duration = Duration::from_millis(10);
+
+/// Channels of one output under test.
+struct Rig {
+    channel: PumpChannel,
+    reset: Signal<NoopRawMutex, ()>,
+    events: Channel<NoopRawMutex, Event, EVENT_QUEUE>,
+    persist: PersistQueue,
+}
+
+impl Rig {
+    fn new() -> Self {
+        Self { channel: PumpChannel::new(), reset: Signal::new(), events: Channel::new(), persist: Channel::new() }
+    }
+
+    fn output(&self) -> PumpOutput<'_> {
+        PumpOutput {
+            zone: 0,
+            channel: &self.channel,
+            reset: &self.reset,
+            limits: SafetyLimits::default(),
+            ramp: Default::default(),
+            flow: FlowCalibration::new(&[FlowPoint { duty: 100, ml_per_s: 20.0 }]),
+            events: self.events.sender(),
+            persist: &self.persist,
+            tank: None,
+            current: None,
+        }
+    }
+
+    fn valve(&self, flow: FlowCalibration, limits: SafetyLimits) -> ValveControl<'_> {
+        ValveControl { zone: 0, channel: &self.channel, reset: &self.reset, limits, flow, events: self.events.sender(), persist: &self.persist, tank: None }
+    }
+
+    fn events(&self) -> Vec<Event> {
+        core::iter::from_fn(|| self.events.try_receive().ok()).collect()
+    }
+
+    async fn command(&self, command: PumpCommand) -> PumpStatus {
+        self.channel.command.signal(command);
+        self.channel.status.receive().await
+    }
+}
+
+/// Runs `script` against the output loop `task` on the mock clock.
+fn drive<T: core::future::Future, S: core::future::Future>(task: T, script: S, limit: Duration) -> S::Output {
+    match run_for(select(task, script), limit, TICK).expect("script finished in time") {
+        Either::First(_) => unreachable!("the output loop never returns"),
+        Either::Second(output) => output,
+    }
+}
+
+#[test]
+fn zero_duty_run_completes_at_once() {
+    let _time = lock_time();
+    let rig = Rig::new();
+    let mut driver = MockPumpDriver::<32>::new();
+    let status = drive(run_output(&mut driver, None::<&mut MockFlowMeter>, rig.output()), rig.command(PumpCommand::RunFor(Duration::from_secs(5), 0)), Duration::from_secs(1));
+    assert_eq!(status.outcome, PumpOutcome::Completed);
+    assert_eq!((status.runtime, status.volume_ml), (Duration::from_ticks(0), 0.0));
+    assert!(!driver.is_enabled());
+}
+
+#[test]
+fn empty_dose_is_unsupported() {
+    let _time = lock_time();
+    let rig = Rig::new();
+    let mut driver = MockPumpDriver::<32>::new();
+    let status = drive(run_output(&mut driver, None::<&mut MockFlowMeter>, rig.output()), rig.command(PumpCommand::Dose { ml: 0.0, duty: 50 }), Duration::from_secs(1));
+    assert_eq!(status.outcome, PumpOutcome::Aborted(AbortReason::Unsupported));
+}
+
+#[test]
+fn valve_reports_every_command_but_off() {
+    let _time = lock_time();
+    let rig = Rig::new();
+    let mut valve = MockPumpDriver::<32>::new();
+    let flow = FlowCalibration::new(&[FlowPoint { duty: 100, ml_per_s: 50.0 }]);
+    let script = async {
+        let zero = rig.command(PumpCommand::RunFor(Duration::from_secs(5), 0)).await;
+        let dose = rig.command(PumpCommand::Dose { ml: 100.0, duty: 30 }).await;
+        rig.channel.command.signal(PumpCommand::Duty(100));
+        Timer::after(Duration::from_secs(1)).await;
+        let stopped = rig.command(PumpCommand::Off).await;
+        rig.channel.command.signal(PumpCommand::Off);
+        Timer::after(Duration::from_secs(1)).await;
+        (zero, dose, stopped, rig.channel.status.try_receive().ok())
+    };
+    let limits = SafetyLimits { min_cooldown: Duration::from_ticks(0), ..Default::default() };
+    let (zero, dose, stopped, extra) = drive(run_valve(&mut valve, rig.valve(flow, limits)), script, Duration::from_secs(10));
+    assert_eq!((zero.outcome, zero.runtime), (PumpOutcome::Completed, Duration::from_ticks(0)));
+    assert_eq!(dose.outcome, PumpOutcome::Completed);
+    assert_eq!(dose.runtime, Duration::from_secs(2));
+    assert!((dose.volume_ml - 100.0).abs() < 1e-9);
+    assert_eq!((stopped.command, stopped.outcome), (PumpCommand::Duty(100), PumpOutcome::Completed));
+    assert_eq!(extra, None);
+    assert_eq!(valve.duty(), 0.0);
+}
+
+#[test]
+fn open_valve_is_closed_by_its_on_time_limit() {
+    let _time = lock_time();
+    let rig = Rig::new();
+    let mut valve = MockPumpDriver::<32>::new();
+    let flow = FlowCalibration::new(&[FlowPoint { duty: 100, ml_per_s: 50.0 }]);
+    let limits = SafetyLimits { max_on_time: Duration::from_secs(30), ..Default::default() };
+    let script = async {
+        // Never switched off.
+        let cut = rig.command(PumpCommand::Duty(100)).await;
+        let refused = rig.command(PumpCommand::RunFor(Duration::from_secs(5), 100)).await;
+        rig.reset.signal(());
+        Timer::after(Duration::from_secs(60)).await;
+        let reopened = rig.command(PumpCommand::RunFor(Duration::from_secs(5), 100)).await;
+        (cut, refused, reopened)
+    };
+    let (cut, refused, reopened) = drive(run_valve(&mut valve, rig.valve(flow, limits)), script, Duration::from_secs(120));
+    assert_eq!((cut.outcome, cut.runtime), (PumpOutcome::Aborted(AbortReason::SafetyLimit(PumpFault::MaxOnTime)), Duration::from_secs(30)));
+    assert_eq!(refused.outcome, PumpOutcome::Aborted(AbortReason::Refused(PumpFault::MaxOnTime)));
+    assert_eq!(reopened.outcome, PumpOutcome::Completed);
+    assert_eq!(valve.duty(), 0.0);
+    assert!(matches!(rig.events()[..], [Event::PumpFault { fault: PumpFault::MaxOnTime, .. }, Event::PumpFault { fault: PumpFault::MaxOnTime, .. }, Event::PumpFaultReset { .. }]));
+}
+
+#[test]
+fn commands_that_report_a_status() {
+    assert!(!PumpCommand::Off.reports_status());
+    assert!(!PumpCommand::Duty(0).reports_status());
+    assert!(PumpCommand::Duty(1).reports_status());
+    assert!(PumpCommand::RunFor(Duration::from_secs(1), 0).reports_status());
+    assert!(PumpCommand::Dose { ml: 0.0, duty: 0 }.reports_status());
+}
+
+const NO_RAMP: RampConfig = RampConfig { up: RampProfile::Step, down: RampProfile::Step };
+
+#[test]
+fn timed_run_ramps_the_driver_up_and_down() {
+    let _time = lock_time();
+    let rig = Rig::new();
+    let mut driver = MockPumpDriver::<256>::new();
+    let start = Instant::now();
+    let ramp = RampConfig { up: RampProfile::Slew { percent_per_s: 100.0 }, down: RampProfile::Slew { percent_per_s: 200.0 } };
+    let script = async {
+        let status = rig.command(PumpCommand::RunFor(Duration::from_secs(2), 60)).await;
+        // Long enough for the ramp down and the driver to be disabled.
+        Timer::after(Duration::from_secs(3)).await;
+        status
+    };
+    let status = drive(run_output(&mut driver, None::<&mut MockFlowMeter>, PumpOutput { ramp, ..rig.output() }), script, Duration::from_secs(10));
+
+    assert_eq!(status.outcome, PumpOutcome::Completed);
+    assert_eq!(status.runtime, Duration::from_secs(2));
+    // 0.6 s of the run are spent ramping up at half the duty on average.
+    assert!((status.average_duty - 51.0).abs() < 1.0, "average duty {}", status.average_duty);
+    let timeline = driver.timeline();
+    assert_eq!(timeline.first().map(|(_, action)| *action), Some(DriverAction::Enable));
+    let peak = timeline.iter().find(|(_, action)| *action == DriverAction::Duty(60.0)).expect("ramped to the duty");
+    assert!((peak.0 - start).as_millis().abs_diff(600) <= 20, "at full duty after {} ms", (peak.0 - start).as_millis());
+    let duties: Vec<f64> = timeline.iter().filter_map(|(_, action)| match action { DriverAction::Duty(duty) => Some(*duty), _ => None }).collect();
+    assert!(duties.windows(2).take_while(|pair| pair[0] < 60.0).all(|pair| pair[0] <= pair[1]));
+    assert_eq!(duties.last(), Some(&0.0));
+    let (disabled, action) = timeline.last().unwrap();
+    assert_eq!(*action, DriverAction::Disable);
+    // The driver stays enabled for the stop delay after the run ended, the ramp down included.
+    let disabled = (*disabled - start).as_millis();
+    assert!((4000..4100).contains(&disabled), "disabled after {} ms", disabled);
+    assert!(!driver.is_enabled());
+}
+
+#[test]
+fn on_time_limit_latches_until_reset() {
+    let _time = lock_time();
+    let rig = Rig::new();
+    let mut driver = MockPumpDriver::<64>::new();
+    let limits = SafetyLimits { max_on_time: Duration::from_secs(1), min_cooldown: Duration::from_secs(2), ..Default::default() };
+    let script = async {
+        let cut = rig.command(PumpCommand::Duty(100)).await;
+        Timer::after(Duration::from_secs(5)).await;
+        let refused = rig.command(PumpCommand::Duty(100)).await;
+        rig.reset.signal(());
+        Timer::after(TICK).await;
+        rig.channel.command.signal(PumpCommand::Duty(50));
+        Timer::after(Duration::from_millis(500)).await;
+        let stopped = rig.command(PumpCommand::Off).await;
+        (cut, refused, stopped)
+    };
+    let (cut, refused, stopped) = drive(run_output(&mut driver, None::<&mut MockFlowMeter>, PumpOutput { limits, ramp: NO_RAMP, ..rig.output() }), script, Duration::from_secs(20));
+
+    assert_eq!(cut.outcome, PumpOutcome::Aborted(AbortReason::SafetyLimit(PumpFault::MaxOnTime)));
+    assert_eq!(cut.runtime, Duration::from_secs(1));
+    assert_eq!(refused.outcome, PumpOutcome::Aborted(AbortReason::Refused(PumpFault::MaxOnTime)));
+    assert_eq!((stopped.command, stopped.outcome), (PumpCommand::Duty(50), PumpOutcome::Completed));
+    let events = rig.events();
+    // The cut and the refused start both report the latched fault.
+    assert!(matches!(events[..], [Event::PumpFault { fault: PumpFault::MaxOnTime, .. }, Event::PumpFault { fault: PumpFault::MaxOnTime, .. }, Event::PumpFaultReset { .. }]), "{:?}", events);
+}
+
+#[test]
+fn cooldown_refuses_without_a_fault_event() {
+    let _time = lock_time();
+    let rig = Rig::new();
+    let mut driver = MockPumpDriver::<64>::new();
+    let limits = SafetyLimits { min_cooldown: Duration::from_secs(10), ..Default::default() };
+    let script = async {
+        rig.command(PumpCommand::RunFor(Duration::from_secs(1), 100)).await;
+        let early = rig.command(PumpCommand::RunFor(Duration::from_secs(1), 100)).await;
+        Timer::after(Duration::from_secs(10)).await;
+        let later = rig.command(PumpCommand::RunFor(Duration::from_secs(1), 100)).await;
+        (early, later)
+    };
+    let (early, later) = drive(run_output(&mut driver, None::<&mut MockFlowMeter>, PumpOutput { limits, ramp: NO_RAMP, ..rig.output() }), script, Duration::from_secs(20));
+    assert_eq!(early.outcome, PumpOutcome::Aborted(AbortReason::Refused(PumpFault::Cooldown)));
+    assert_eq!(later.outcome, PumpOutcome::Completed);
+    assert!(rig.events().is_empty());
+}
+
+#[test]
+fn stalled_motor_is_stopped() {
+    let _time = lock_time();
+    let rig = Rig::new();
+    let mut driver = MockPumpDriver::<64>::new();
+    driver.set_current(Some(1500.0));
+    let current = CurrentLimits::default();
+    let status = drive(run_output(&mut driver, None::<&mut MockFlowMeter>, PumpOutput { ramp: NO_RAMP, current: Some(current), ..rig.output() }), rig.command(PumpCommand::Duty(100)), Duration::from_secs(10));
+    assert_eq!(status.outcome, PumpOutcome::Aborted(AbortReason::SafetyLimit(PumpFault::Stalled)));
+    assert!(status.runtime >= current.inrush + current.confirm);
+    assert!(status.runtime < current.inrush + current.confirm + Duration::from_millis(500));
+    assert_eq!(status.current_ma.summary().map(|summary| summary.mean), Some(1500.0));
+}
+
+/// Lends a mock meter to the output loop while the test keeps adding water to it.
+struct SharedMeter<'a>(&'a RefCell<MockFlowMeter>);
+
+impl FlowMeter for SharedMeter<'_> {
+    fn take_volume(&mut self) -> f64 {
+        self.0.borrow_mut().take_volume()
+    }
+}
+
+/// Feeds `ml_per_s` into the meter until `script` finishes.
+async fn flowing<S: core::future::Future>(meter: &RefCell<MockFlowMeter>, ml_per_s: f64, script: S) -> S::Output {
+    let water = async {
+        loop {
+            Timer::after(TICK).await;
+            meter.borrow_mut().add(ml_per_s * TICK.as_millis() as f64 / 1000.0);
+        }
+    };
+    match select(script, water).await {
+        Either::First(output) => output,
+        Either::Second(never) => never,
+    }
+}
+
+#[test]
+fn metered_dose_stops_at_the_counted_volume() {
+    let _time = lock_time();
+    let rig = Rig::new();
+    let mut driver = MockPumpDriver::<64>::new();
+    let meter = RefCell::new(MockFlowMeter::new());
+    // The calibration claims 20 ml/s, the meter counts 25 ml/s.
+    let script = flowing(&meter, 25.0, rig.command(PumpCommand::Dose { ml: 100.0, duty: 100 }));
+    let status = drive(run_output(&mut driver, Some(&mut SharedMeter(&meter)), PumpOutput { ramp: NO_RAMP, ..rig.output() }), script, Duration::from_secs(10));
+    assert_eq!(status.outcome, PumpOutcome::Completed);
+    assert!((100.0..=110.0).contains(&status.volume_ml), "metered {} ml", status.volume_ml);
+    assert!(status.runtime < Duration::from_millis(4500), "ran for {:?}", status.runtime);
+    assert!(meter.borrow().total() >= status.volume_ml);
+}
+
+#[test]
+fn dry_line_trips_no_flow() {
+    let _time = lock_time();
+    let rig = Rig::new();
+    let mut driver = MockPumpDriver::<64>::new();
+    let mut meter = MockFlowMeter::new();
+    let limits = SafetyLimits::default();
+    let status = drive(run_output(&mut driver, Some(&mut meter), PumpOutput { limits, ramp: NO_RAMP, ..rig.output() }), rig.command(PumpCommand::Duty(100)), Duration::from_secs(20));
+    assert_eq!(status.outcome, PumpOutcome::Aborted(AbortReason::SafetyLimit(PumpFault::NoFlow)));
+    assert!(status.runtime >= limits.no_flow_timeout && status.runtime < limits.no_flow_timeout + Duration::from_secs(1));
+    assert_eq!((status.volume_ml, meter.total()), (0.0, 0.0));
+    // A dry run says nothing about the flow of the pump.
+    assert!(rig.persist.try_receive().is_err());
+}
+
+#[test]
+fn steady_metered_run_corrects_the_flow_calibration() {
+    let _time = lock_time();
+    let rig = Rig::new();
+    let mut driver = MockPumpDriver::<64>::new();
+    let meter = RefCell::new(MockFlowMeter::new());
+    let script = flowing(&meter, 30.0, rig.command(PumpCommand::RunFor(Duration::from_secs(10), 100)));
+    let status = drive(run_output(&mut driver, Some(&mut SharedMeter(&meter)), PumpOutput { ramp: NO_RAMP, ..rig.output() }), script, Duration::from_secs(20));
+    assert_eq!(status.outcome, PumpOutcome::Completed);
+    assert!((status.volume_ml - 300.0).abs() < 5.0, "metered {} ml", status.volume_ml);
+    let Ok(PersistRequest::Flow { zone: 0, calibration }) = rig.persist.try_receive() else {
+        panic!("flow calibration queued for storage");
+    };
+    // Moves 30 % of the way from the 20 ml/s it knew towards the 30 ml/s it measured.
+    let learned = calibration.flow_at(100).unwrap();
+    assert!((learned - 23.0).abs() < 0.2, "learned {} ml/s", learned);
+}
+
+#[test]
+fn recorded_flow_is_queued_for_storage() {
+    let rig = Rig::new();
+    let mut flow = FlowCalibration::new(&[FlowPoint { duty: 100, ml_per_s: 20.0 }]);
+    record_flow(0, &mut flow, FlowPoint::measured(50, Duration::from_secs(20), 180.0).unwrap(), &rig.persist);
+    assert_eq!(flow.points(), [FlowPoint { duty: 50, ml_per_s: 9.0 }, FlowPoint { duty: 100, ml_per_s: 20.0 }]);
+    let Ok(PersistRequest::Flow { zone: 0, calibration }) = rig.persist.try_receive() else {
+        panic!("flow calibration queued for storage");
+    };
+    assert_eq!(calibration, flow);
+
+    record_flow(0, &mut flow, FlowPoint { duty: 0, ml_per_s: 5.0 }, &rig.persist);
+    record_flow(0, &mut flow, FlowPoint { duty: 60, ml_per_s: f64::NAN }, &rig.persist);
+    assert_eq!(flow.points().len(), 2);
+    assert!(rig.persist.try_receive().is_err());
+}
+
+#[test]
+fn measured_flow_reaches_the_output_and_times_doses() {
+    let _time = lock_time();
+    let rig = Rig::new();
+    let mut driver = MockPumpDriver::<64>::new();
+    let script = async {
+        rig.channel.calibrate.signal(FlowPoint { duty: 100, ml_per_s: 10.0 });
+        Timer::after(TICK).await;
+        rig.command(PumpCommand::Dose { ml: 50.0, duty: 100 }).await
+    };
+    let status = drive(run_output(&mut driver, None::<&mut MockFlowMeter>, PumpOutput { ramp: NO_RAMP, ..rig.output() }), script, Duration::from_secs(20));
+    assert!(matches!(rig.persist.try_receive(), Ok(PersistRequest::Flow { zone: 0, .. })));
+    // At the measured 10 ml/s instead of the configured 20 ml/s.
+    assert_eq!(status.runtime, Duration::from_secs(5));
+    assert!((status.volume_ml - 50.0).abs() < 1e-9, "{:?}", status);
+}
+
+#[test]
+fn stepped_run_counts_its_water() {
+    let _time = lock_time();
+    let rig = Rig::new();
+    let mut driver = MockPumpDriver::<64>::new();
+    let status = drive(run_output(&mut driver, None::<&mut MockFlowMeter>, PumpOutput { ramp: NO_RAMP, ..rig.output() }), rig.command(PumpCommand::RunFor(Duration::from_secs(3), 100)), Duration::from_secs(10));
+    assert_eq!(status.runtime, Duration::from_secs(3));
+    // Without a ramp the output only wakes up at the end of the run.
+    assert_eq!(status.average_duty, 100.0);
+    assert!((status.volume_ml - 60.0).abs() < 1e-9, "{} ml", status.volume_ml);
+}
@@ -0,0 +1,81 @@
+use dewy_host::pump_safety::{PumpFault, SafetyLimits, SafetyMonitor};
+use embassy_time::{Duration, Instant};
+
+const LIMITS: SafetyLimits = SafetyLimits {
+    max_on_time: Duration::from_secs(30),
+    max_daily_runtime: Duration::from_secs(60),
+    min_cooldown: Duration::from_secs(60),
+    no_flow_timeout: Duration::from_secs(5),
+};
+
+fn at(seconds: u64) -> Instant {
+    Instant::from_ticks(0) + Duration::from_secs(seconds)
+}
+
+fn run(monitor: &mut SafetyMonitor, start: u64, seconds: u64) {
+    monitor.start(at(start)).unwrap();
+    monitor.stop(at(start + seconds));
+}
+
+#[test]
+fn cooldown_refuses_without_latching() {
+    let mut monitor = SafetyMonitor::new(LIMITS);
+    run(&mut monitor, 0, 10);
+    assert_eq!(monitor.start(at(30)), Err(PumpFault::Cooldown));
+    assert_eq!(monitor.fault(), None);
+    assert!(!monitor.is_running());
+    assert_eq!(monitor.start(at(70)), Ok(()));
+}
+
+#[test]
+fn on_time_cuts_the_run_and_latches() {
+    let mut monitor = SafetyMonitor::new(LIMITS);
+    monitor.start(at(0)).unwrap();
+    assert_eq!(monitor.cutoff(at(0)), Some(at(30)));
+    assert_eq!(monitor.check(at(29)), Ok(()));
+    assert_eq!(monitor.check(at(30)), Err(PumpFault::MaxOnTime));
+    assert!(!monitor.is_running());
+    assert_eq!(monitor.start(at(1000)), Err(PumpFault::MaxOnTime));
+    monitor.reset();
+    assert_eq!(monitor.start(at(1000)), Ok(()));
+}
+
+#[test]
+fn daily_runtime_latches_until_reset_and_recovers_after_a_day() {
+    let mut monitor = SafetyMonitor::new(LIMITS);
+    run(&mut monitor, 0, 25);
+    run(&mut monitor, 100, 25);
+    monitor.start(at(200)).unwrap();
+    // 10 s of the daily budget are left.
+    assert_eq!(monitor.cutoff(at(200)), Some(at(210)));
+    assert_eq!(monitor.check(at(210)), Err(PumpFault::DailyRuntime));
+
+    monitor.reset();
+    assert_eq!(monitor.start(at(300)), Err(PumpFault::DailyRuntime));
+    monitor.reset();
+    // The runs are summed in 15 minute buckets, they count until the first bucket is a day old.
+    assert_eq!(monitor.start(at(24 * 60 * 60 + 300)), Err(PumpFault::DailyRuntime));
+    monitor.reset();
+    assert_eq!(monitor.start(at(24 * 60 * 60 + 15 * 60)), Ok(()));
+}
+
+#[test]
+fn many_short_runs_all_count_against_the_day() {
+    let limits = SafetyLimits { min_cooldown: Duration::from_secs(1), max_daily_runtime: Duration::from_secs(100), ..LIMITS };
+    let mut monitor = SafetyMonitor::new(limits);
+    for run_index in 0..100 {
+        run(&mut monitor, run_index * 10, 1);
+    }
+    assert_eq!(monitor.start(at(1000)), Err(PumpFault::DailyRuntime));
+}
+
+#[test]
+fn tripped_fault_latches() {
+    let mut monitor = SafetyMonitor::new(LIMITS);
+    monitor.start(at(0)).unwrap();
+    assert_eq!(monitor.trip(PumpFault::Stalled, at(3)), PumpFault::Stalled);
+    assert!(!monitor.is_running());
+    assert_eq!(monitor.start(at(500)), Err(PumpFault::Stalled));
+    assert!(PumpFault::Stalled.latches() && PumpFault::Stalled.blocks_flow());
+    assert!(!PumpFault::Cooldown.latches());
+}
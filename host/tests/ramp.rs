@@ -0,0 +1,54 @@
+use dewy_host::ramp::{Ramp, RampConfig, RampProfile};
+use embassy_time::{Duration, Instant};
+
+fn at_ms(ms: u64) -> Instant {
+    Instant::from_ticks(0) + Duration::from_millis(ms)
+}
+
+#[test]
+fn profile_durations_scale_with_the_change() {
+    assert_eq!(RampProfile::Step.duration(100.0), Duration::from_ticks(0));
+    assert_eq!(RampProfile::Slew { percent_per_s: 200.0 }.duration(-50.0), Duration::from_millis(250));
+    assert_eq!(RampProfile::Slew { percent_per_s: 0.0 }.duration(50.0), Duration::from_ticks(0));
+    assert_eq!(RampProfile::SCurve { full_scale: Duration::from_secs(2) }.duration(25.0), Duration::from_millis(500));
+}
+
+#[test]
+fn slew_moves_linearly_and_settles() {
+    let mut ramp = Ramp::new(RampConfig { up: RampProfile::Slew { percent_per_s: 100.0 }, down: RampProfile::Step });
+    ramp.set_target(80, at_ms(1000));
+    assert_eq!(ramp.duty_at(at_ms(1000)), 0.0);
+    assert!((ramp.duty_at(at_ms(1400)) - 40.0).abs() < 1e-9);
+    assert_eq!(ramp.settles_at(), at_ms(1800));
+    assert!(!ramp.is_settled(at_ms(1799)));
+    assert_eq!(ramp.duty_at(at_ms(1800)), 80.0);
+
+    ramp.set_target(0, at_ms(2000));
+    assert!(ramp.is_settled(at_ms(2000)));
+    assert_eq!(ramp.duty_at(at_ms(2000)), 0.0);
+}
+
+#[test]
+fn s_curve_is_symmetric_and_gentle_at_the_ends() {
+    let mut ramp = Ramp::new(RampConfig { up: RampProfile::SCurve { full_scale: Duration::from_secs(1) }, down: RampProfile::Step });
+    ramp.set_target(100, at_ms(0));
+    assert!((ramp.duty_at(at_ms(500)) - 50.0).abs() < 1e-9);
+    assert!(ramp.duty_at(at_ms(100)) < 10.0);
+    assert!(ramp.duty_at(at_ms(900)) > 90.0);
+    assert!((ramp.duty_at(at_ms(250)) + ramp.duty_at(at_ms(750)) - 100.0).abs() < 1e-9);
+}
+
+#[test]
+fn retarget_starts_from_the_current_duty() {
+    let mut ramp = Ramp::new(RampConfig { up: RampProfile::Slew { percent_per_s: 100.0 }, down: RampProfile::Slew { percent_per_s: 200.0 } });
+    ramp.set_target(100, at_ms(0));
+    ramp.set_target(0, at_ms(500));
+    assert!((ramp.duty_at(at_ms(500)) - 50.0).abs() < 1e-9);
+    assert_eq!(ramp.settles_at(), at_ms(750));
+}
+
+#[test]
+fn compensation_is_half_of_each_ramp() {
+    let config = RampConfig { up: RampProfile::Slew { percent_per_s: 100.0 }, down: RampProfile::SCurve { full_scale: Duration::from_millis(400) } };
+    assert_eq!(config.run_compensation(50), (Duration::from_millis(250), Duration::from_millis(100)));
+}
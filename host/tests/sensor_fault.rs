@@ -0,0 +1,58 @@
+use dewy_host::{seesaw::Messurement, sensor_fault::{PlausibilityLimits, SensorFault, SensorFaultDetector}};
+use embassy_time::Instant;
+
+fn sample(moisture: u16, temp: f32) -> Messurement {
+    Messurement { temp, moisture, at: Instant::from_ticks(0) }
+}
+
+#[test]
+fn out_of_range_readings_are_rejected() {
+    let mut detector = SensorFaultDetector::new(PlausibilityLimits::default());
+    assert_eq!(detector.check(&sample(100, 20.0)), Err(SensorFault::MoistureOutOfRange(100)));
+    assert_eq!(detector.check(&sample(2100, 20.0)), Err(SensorFault::MoistureOutOfRange(2100)));
+    assert_eq!(detector.check(&sample(600, 85.0)), Err(SensorFault::TemperatureOutOfRange(85.0)));
+    assert_eq!(detector.check(&sample(600, 20.0)), Ok(()));
+}
+
+#[test]
+fn single_spike_is_one_impossible_step() {
+    let mut detector = SensorFaultDetector::new(PlausibilityLimits::default());
+    assert_eq!(detector.check(&sample(600, 20.0)), Ok(()));
+    assert_eq!(detector.check(&sample(1500, 20.0)), Err(SensorFault::ImpossibleStep { from: 600, to: 1500 }));
+    assert_eq!(detector.check(&sample(605, 20.0)), Ok(()));
+}
+
+#[test]
+fn step_is_judged_against_the_last_good_sample() {
+    let mut detector = SensorFaultDetector::new(PlausibilityLimits::default());
+    assert_eq!(detector.check(&sample(600, 20.0)), Ok(()));
+    assert_eq!(detector.check(&sample(1500, 20.0)), Err(SensorFault::ImpossibleStep { from: 600, to: 1500 }));
+    assert_eq!(detector.check(&sample(1510, 20.0)), Err(SensorFault::ImpossibleStep { from: 600, to: 1510 }));
+    assert_eq!(detector.check(&sample(900, 20.0)), Ok(()));
+    // Steps that disagree with each other never settle.
+    assert_eq!(detector.check(&sample(1500, 20.0)), Err(SensorFault::ImpossibleStep { from: 900, to: 1500 }));
+    assert_eq!(detector.check(&sample(300, 20.0)), Err(SensorFault::ImpossibleStep { from: 900, to: 300 }));
+    assert_eq!(detector.check(&sample(1500, 20.0)), Err(SensorFault::ImpossibleStep { from: 900, to: 1500 }));
+}
+
+#[test]
+fn lasting_shift_becomes_the_new_baseline() {
+    let mut detector = SensorFaultDetector::new(PlausibilityLimits::default());
+    assert_eq!(detector.check(&sample(600, 20.0)), Ok(()));
+    assert_eq!(detector.check(&sample(1500, 20.0)), Err(SensorFault::ImpossibleStep { from: 600, to: 1500 }));
+    assert_eq!(detector.check(&sample(1510, 20.0)), Err(SensorFault::ImpossibleStep { from: 600, to: 1510 }));
+    assert_eq!(detector.check(&sample(1490, 20.0)), Ok(()));
+    assert_eq!(detector.check(&sample(1480, 20.0)), Ok(()));
+    assert_eq!(detector.check(&sample(600, 20.0)), Err(SensorFault::ImpossibleStep { from: 1480, to: 600 }));
+}
+
+#[test]
+fn frozen_probe_is_stuck() {
+    let limits = PlausibilityLimits { stuck_samples: 3, ..Default::default() };
+    let mut detector = SensorFaultDetector::new(limits);
+    for _ in 0..3 {
+        assert_eq!(detector.check(&sample(600, 20.0)), Ok(()));
+    }
+    assert_eq!(detector.check(&sample(600, 20.0)), Err(SensorFault::Stuck { moisture: 600, samples: 3 }));
+    assert_eq!(detector.check(&sample(601, 20.0)), Ok(()));
+}
@@ -0,0 +1,110 @@
+use dewy_host::{calibration::Calibration, flow_calibration::FlowPoint, manual_override::ManualCommand, soil_estimator::EstimatorInput, server_command::{parse, run_server_commands, ServerCommand, ServerCommandQueue, ServerResponse}, testing::{lock_time, run_for}, watering_history::{Fluid, HistoryQuery, RunReason}, zone::ZoneIo};
+use embassy_futures::select::{select, Either};
+use embassy_sync::channel::Channel;
+use embassy_time::{Duration, Instant};
+
+#[test]
+fn reads_the_pump_commands_of_a_response() {
+    let body = br#"{"commands": [
+        {"pump": {"zone": 0, "command": {"on": {"duty": 40}}}},
+        {"pump": {"zone": 1, "command": {"run_for": {"seconds": 30, "duty": 80}}}},
+        {"pump": {"zone": 0, "command": "prime"}},
+        {"pump": {"zone": 1, "command": "auto"}}
+    ]}"#;
+    let commands = parse(body).unwrap().commands;
+    assert_eq!(commands[..], [
+        ServerCommand::Pump { zone: 0, command: ManualCommand::On(40) },
+        ServerCommand::Pump { zone: 1, command: ManualCommand::RunFor(Duration::from_secs(30), 80) },
+        ServerCommand::Pump { zone: 0, command: ManualCommand::Prime },
+        ServerCommand::Pump { zone: 1, command: ManualCommand::Auto },
+    ]);
+}
+
+#[test]
+fn acknowledgement_without_commands() {
+    assert_eq!(parse(b"").unwrap(), ServerResponse::default());
+    assert_eq!(parse(b"{}").unwrap(), ServerResponse::default());
+    assert_eq!(parse(br#"{"commands": [], "stored": 12}"#).unwrap(), ServerResponse::default());
+    assert!(parse(br#"{"commands": [{"pump": {"zone": 0, "command": "flood"}}]}"#).is_err());
+    assert!(parse(b"<html>").is_err());
+}
+
+#[test]
+fn pump_commands_go_to_the_zone_as_remote() {
+    let zones = [ZoneIo::new(), ZoneIo::new()];
+    let queue: ServerCommandQueue = Channel::new();
+    queue.try_send(ServerCommand::Pump { zone: 1, command: ManualCommand::Off }).unwrap();
+    queue.try_send(ServerCommand::Pump { zone: 2, command: ManualCommand::Prime }).unwrap();
+    queue.try_send(ServerCommand::Pump { zone: 0, command: ManualCommand::Prime }).unwrap();
+    let received = async { (zones[0].manual.receive().await, zones[1].manual.try_receive().ok()) };
+    let Some(Either::Second((first, second))) = run_for(select(run_server_commands(&queue, &zones), received), Duration::from_secs(1), Duration::from_millis(10)) else {
+        panic!("commands dispatched");
+    };
+    assert_eq!(first, (ManualCommand::Prime, RunReason::Remote));
+    assert_eq!(second, Some((ManualCommand::Off, RunReason::Remote)));
+}
+
+#[test]
+fn calibration_commands_go_to_the_estimator() {
+    let body = br#"{"commands": [
+        {"calibration": {"zone": 0, "command": {"manual": {"dry": 350, "wet": 1420.5}}}},
+        {"calibration": {"zone": 0, "command": "learn"}}
+    ]}"#;
+    let commands = parse(body).unwrap().commands;
+    assert_eq!(commands[..], [
+        ServerCommand::Estimator { zone: 0, input: EstimatorInput::ManualCalibration(Calibration::new(350.0, 1420.5)) },
+        ServerCommand::Estimator { zone: 0, input: EstimatorInput::LearnCalibration },
+    ]);
+
+    let zones = [ZoneIo::new()];
+    let queue: ServerCommandQueue = Channel::new();
+    for command in commands {
+        queue.try_send(command).unwrap();
+    }
+    let received = async { (zones[0].estimator.receive().await, zones[0].estimator.receive().await) };
+    let Some(Either::Second(inputs)) = run_for(select(run_server_commands(&queue, &zones), received), Duration::from_secs(1), Duration::from_millis(10)) else {
+        panic!("commands dispatched");
+    };
+    assert_eq!(inputs, (EstimatorInput::ManualCalibration(Calibration::new(350.0, 1420.5)), EstimatorInput::LearnCalibration));
+    assert!(zones[0].manual.try_receive().is_err());
+}
+
+#[test]
+fn cup_measurement_calibrates_the_zone_pump() {
+    let body = br#"{"commands": [
+        {"flow": {"zone": 0, "duty": 80, "seconds": 30, "ml": 540}},
+        {"flow": {"zone": 0, "duty": 80, "seconds": 0, "ml": 540}}
+    ]}"#;
+    let commands = parse(body).unwrap().commands;
+    assert_eq!(commands[..], [ServerCommand::Flow { zone: 0, point: FlowPoint { duty: 80, ml_per_s: 18.0 } }]);
+
+    let zones = [ZoneIo::new()];
+    let queue: ServerCommandQueue = Channel::new();
+    queue.try_send(commands[0]).unwrap();
+    let Some(Either::Second(point)) = run_for(select(run_server_commands(&queue, &zones), zones[0].pump.calibrate.wait()), Duration::from_secs(1), Duration::from_millis(10)) else {
+        panic!("measurement dispatched");
+    };
+    assert_eq!(point, FlowPoint { duty: 80, ml_per_s: 18.0 });
+}
+
+#[test]
+fn ambient_temperature_is_stamped_when_it_arrives() {
+    let _time = lock_time();
+    let before = Instant::now();
+    let commands = parse(br#"{"commands": [{"ambient_temperature": {"zone": 1, "celsius": -2.5}}]}"#).unwrap().commands;
+    let [ServerCommand::Estimator { zone: 1, input: EstimatorInput::AmbientTemperature { celsius, at } }] = commands[..] else {
+        panic!("unexpected commands {:?}", commands);
+    };
+    assert_eq!(celsius, -2.5);
+    assert!(at >= before);
+}
+
+#[test]
+fn reads_the_history_request() {
+    let response = parse(br#"{"history": {"zone": 1, "fluid": "nutrient", "after_id": 41}}"#).unwrap();
+    assert!(response.commands.is_empty());
+    assert_eq!(response.history, Some(HistoryQuery { zone: Some(1), fluid: Some(Fluid::Nutrient), since: None, after_id: Some(41) }));
+    let response = parse(br#"{"commands": [{"pump": {"zone": 0, "command": "off"}}], "history": {}}"#).unwrap();
+    assert_eq!(response.commands.len(), 1);
+    assert_eq!(response.history, Some(HistoryQuery::default()));
+}
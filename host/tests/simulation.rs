@@ -0,0 +1,92 @@
+use dewy_host::{events::Event, flow_calibration::{FlowCalibration, FlowPoint}, plant_profile::HOUSEPLANT, simulation::{Simulation, SimulationIo, SoilModel, SoilModelConfig}, temperature_guard::WateringDecision, testing::lock_time};
+use embassy_time::Duration;
+
+type Io = SimulationIo<4, 64, 64>;
+type Sim = Simulation<64, 64>;
+
+const HOURS: u64 = 60 * 60;
+
+fn simulation(config: SoilModelConfig, initial_fraction: f64) -> Sim {
+    Simulation::new(SoilModel::new(config, initial_fraction), 7, HOUSEPLANT.sample_period)
+}
+
+fn events(io: &Io) -> Vec<Event> {
+    core::iter::from_fn(|| io.events.try_receive().ok()).collect()
+}
+
+#[test]
+fn dry_pot_is_watered_above_the_threshold() {
+    let _time = lock_time();
+    let io = Io::new();
+    let mut estimator = io.estimator(&HOUSEPLANT);
+    let mut simulation = simulation(SoilModelConfig::default(), 0.2);
+    simulation.run(&io, &mut estimator, Duration::from_secs(2 * HOURS));
+
+    let first = simulation.pump_log().first().expect("the dry pot is watered");
+    assert!(first.duty > 0);
+    assert!(first.at.as_secs() < 5 * 60, "watered after the warmup, not at {:?}", first.at);
+    assert!(simulation.model().pumped_ml() >= HOUSEPLANT.dose_ml - 1.0);
+    assert!(simulation.model().water_fraction() > HOUSEPLANT.water_below);
+    assert!(events(&io).iter().any(|event| matches!(event, Event::Watered { .. })));
+    assert!(!simulation.messurement_log().is_empty());
+}
+
+#[test]
+fn moist_pot_is_left_alone() {
+    let _time = lock_time();
+    let io = Io::new();
+    let mut estimator = io.estimator(&HOUSEPLANT);
+    let mut simulation = simulation(SoilModelConfig::default(), 0.65);
+    simulation.run(&io, &mut estimator, Duration::from_secs(2 * HOURS));
+
+    assert!(simulation.pump_log().is_empty());
+    assert_eq!(simulation.model().pumped_ml(), 0.0);
+    assert!(simulation.model().water_fraction() < 0.65);
+}
+
+#[test]
+fn frost_inhibits_watering() {
+    let _time = lock_time();
+    let io = Io::new();
+    let mut estimator = io.estimator(&HOUSEPLANT);
+    let config = SoilModelConfig { mean_temperature: 1.0, daily_temperature_swing: 0.0, ..Default::default() };
+    let mut simulation = simulation(config, 0.2);
+    simulation.run(&io, &mut estimator, Duration::from_secs(HOURS));
+
+    assert!(simulation.pump_log().is_empty());
+    assert!(events(&io).iter().any(|event| matches!(event, Event::Watering { decision: WateringDecision::FrostInhibit { .. }, .. })));
+}
+
+#[test]
+fn overstated_flow_calibration_underwaters() {
+    let _time = lock_time();
+    let io = Io::new();
+    let mut estimator = io.estimator(&HOUSEPLANT);
+    let mut simulation = simulation(SoilModelConfig::default(), 0.2);
+    // The pump delivers 20 ml/s, the controller believes in twice that.
+    simulation.set_flow_calibration(FlowCalibration::new(&[FlowPoint { duty: 100, ml_per_s: 40.0 }]));
+    simulation.run(&io, &mut estimator, Duration::from_secs(10 * 60));
+
+    let delivered = events(&io).into_iter().find_map(|event| match event {
+        Event::Watered { requested_ml, delivered_ml, .. } => Some((requested_ml, delivered_ml)),
+        _ => None,
+    });
+    let (requested_ml, reported_ml) = delivered.expect("one dose finished");
+    assert!((reported_ml - requested_ml).abs() < 2.0, "the controller believes it delivered {} ml", reported_ml);
+    assert!((simulation.model().pumped_ml() - requested_ml / 2.0).abs() < 2.0, "the pot got {} ml", simulation.model().pumped_ml());
+}
+
+#[test]
+fn runs_are_reproducible_from_the_seed() {
+    let _time = lock_time();
+    let run = || {
+        let io = Io::new();
+        let mut estimator = io.estimator(&HOUSEPLANT);
+        let mut simulation = simulation(SoilModelConfig::default(), 0.3);
+        simulation.run(&io, &mut estimator, Duration::from_secs(HOURS));
+        let pumps: Vec<_> = simulation.pump_log().iter().map(|record| (record.at, record.duty)).collect();
+        let moisture: Vec<_> = simulation.messurement_log().iter().map(|record| record.record.moisture.mean).collect();
+        (pumps, moisture)
+    };
+    assert_eq!(run(), run());
+}
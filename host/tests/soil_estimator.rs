@@ -0,0 +1,152 @@
+use dewy_host::{calibration::{Calibration, CalibrationMode}, persistence::{PersistRequest, StoredCalibration}, plant_profile::HOUSEPLANT, pump_command::{PumpCommand, PumpOutcome, PumpStatus}, events::Event, seesaw::Messurement, temperature_guard::WateringDecision, simulation::SimulationIo, soil_estimator::{EstimatorInput, EstimatorInputs, EstimatorSnapshot, SoilEstimator}, testing::lock_time};
+use embassy_futures::{block_on, select::{select, Either}};
+use embassy_sync::channel::Channel;
+use embassy_time::{Duration, Instant};
+
+type Io = SimulationIo<4, 64, 64>;
+
+/// Feeds `samples` readings around `moisture`, one per sample period from `start`, and returns the
+/// estimator snapshots it queued for flash.
+fn feed(io: &Io, estimator: &mut SoilEstimator<'_, 4, 64, 64>, start: &mut Instant, samples: u64, moisture: u16) -> Vec<EstimatorSnapshot> {
+    let mut snapshots = Vec::new();
+    for sample in 0..samples {
+        *start += HOUSEPLANT.sample_period;
+        estimator.update(Messurement { temp: 20.0, moisture: moisture + (sample % 2) as u16, at: *start });
+        while let Ok(request) = io.persist.try_receive() {
+            if let PersistRequest::Estimator { snapshot, .. } = request {
+                snapshots.push(snapshot);
+            }
+        }
+        while io.messurement_log.try_receive().is_ok() {}
+        while io.events.try_receive().is_ok() {}
+    }
+    snapshots
+}
+
+fn samples_per(duration: Duration) -> u64 {
+    duration.as_secs() / HOUSEPLANT.sample_period.as_secs()
+}
+
+#[test]
+fn unchanged_snapshot_is_written_once() {
+    let _time = lock_time();
+    let io = Io::new();
+    let mut estimator = io.estimator(&HOUSEPLANT);
+    let mut now = Instant::from_ticks(0);
+    // Moist enough that it is never watered.
+    assert_eq!(feed(&io, &mut estimator, &mut now, samples_per(Duration::from_secs(4 * 60 * 60)), 900).len(), 1);
+    assert_eq!(feed(&io, &mut estimator, &mut now, samples_per(Duration::from_secs(31 * 60)), 850).len(), 1);
+}
+
+#[test]
+fn restored_snapshot_is_not_written_again() {
+    let _time = lock_time();
+    let io = Io::new();
+    let mut estimator = io.estimator(&HOUSEPLANT);
+    let mut now = Instant::from_ticks(0);
+    let snapshot = feed(&io, &mut estimator, &mut now, samples_per(Duration::from_secs(35 * 60)), 900)[0];
+
+    let io = Io::new();
+    let mut restored = io.estimator(&HOUSEPLANT);
+    restored.restore(&snapshot, now);
+    assert_eq!(feed(&io, &mut restored, &mut now, samples_per(Duration::from_secs(2 * 60 * 60)), 900).len(), 0);
+}
+
+fn persisted_calibration(io: &Io) -> Option<StoredCalibration> {
+    core::iter::from_fn(|| io.persist.try_receive().ok()).find_map(|request| match request {
+        PersistRequest::Calibration { calibration, .. } => Some(calibration),
+        _ => None,
+    })
+}
+
+#[test]
+fn calibration_is_pinned_and_released() {
+    let io = Io::new();
+    let mut estimator = io.estimator(&HOUSEPLANT);
+    let manual = Calibration::new(320.0, 1250.0);
+    estimator.apply(EstimatorInput::ManualCalibration(manual));
+    assert_eq!(persisted_calibration(&io), Some(StoredCalibration { calibration: manual, mode: CalibrationMode::Manual }));
+
+    estimator.apply(EstimatorInput::ManualCalibration(Calibration::new(900.0, 400.0)));
+    assert_eq!(persisted_calibration(&io), None);
+
+    estimator.apply(EstimatorInput::LearnCalibration);
+    assert_eq!(persisted_calibration(&io), Some(StoredCalibration { calibration: manual, mode: CalibrationMode::Learning }));
+    estimator.apply(EstimatorInput::LearnCalibration);
+    assert_eq!(persisted_calibration(&io), None);
+}
+
+#[test]
+fn manual_calibration_maps_the_readings() {
+    let _time = lock_time();
+    let io = Io::new();
+    let mut estimator = io.estimator(&HOUSEPLANT);
+    let inputs: EstimatorInputs = Channel::new();
+    inputs.try_send(EstimatorInput::ManualCalibration(Calibration::new(400.0, 1400.0))).unwrap();
+    let mut at = Instant::from_ticks(0);
+    let window = async {
+        for sample in 0..1000 {
+            at += HOUSEPLANT.sample_period;
+            io.messurements.send(Messurement { temp: 20.0, moisture: 900 + sample % 2, at }).await;
+            if let Ok(record) = io.messurement_log.try_receive() {
+                return record;
+            }
+        }
+        panic!("no window closed");
+    };
+    let Either::Second(record) = block_on(select(estimator.run(&inputs), window)) else {
+        unreachable!("the estimator never returns");
+    };
+    assert!((record.relative_moisture.mean - 0.5).abs() < 0.002, "relative moisture {:?}", record.relative_moisture);
+}
+
+/// Watering decisions reported while `samples` dry readings come in.
+fn dry_decisions(io: &Io, estimator: &mut SoilEstimator<'_, 4, 64, 64>, start: &mut Instant, samples: u64) -> Vec<WateringDecision> {
+    let mut decisions = Vec::new();
+    for sample in 0..samples {
+        *start += HOUSEPLANT.sample_period;
+        estimator.update(Messurement { temp: 20.0, moisture: 300 + (sample % 2) as u16, at: *start });
+        while let Ok(event) = io.events.try_receive() {
+            if let Event::Watering { decision, .. } = event {
+                decisions.push(decision);
+            }
+        }
+        while io.messurement_log.try_receive().is_ok() {}
+        while io.persist.try_receive().is_ok() {}
+    }
+    decisions
+}
+
+#[test]
+fn ambient_frost_holds_the_watering_until_it_goes_stale() {
+    let _time = lock_time();
+    let io = Io::new();
+    let mut estimator = io.estimator(&HOUSEPLANT);
+    let mut now = Instant::from_ticks(0);
+    estimator.apply(EstimatorInput::AmbientTemperature { celsius: 1.0, at: now });
+    let decisions = dry_decisions(&io, &mut estimator, &mut now, HOUSEPLANT.warmup_samples + 2);
+    assert!(matches!(decisions[..], [WateringDecision::FrostInhibit { temperature }] if temperature == 1.0), "{:?}", decisions);
+    assert!(!io.pump.command.signaled());
+
+    let decisions = dry_decisions(&io, &mut estimator, &mut now, samples_per(Duration::from_secs(2 * 60 * 60)));
+    assert!(matches!(decisions[..], [WateringDecision::Water { .. }]), "{:?}", decisions);
+    assert!(io.pump.command.signaled());
+}
+
+#[test]
+fn manual_runs_count_in_the_window_record() {
+    let _time = lock_time();
+    let io = Io::new();
+    let mut estimator = io.estimator(&HOUSEPLANT);
+    let mut now = Instant::from_ticks(0);
+    let prime = PumpStatus { runtime: Duration::from_secs(8), volume_ml: 40.0, outcome: PumpOutcome::Completed, ..PumpStatus::skipped(PumpCommand::RunFor(Duration::from_secs(8), 100)) };
+    io.pump.status.try_send(prime).unwrap();
+    let mut records = Vec::new();
+    for sample in 0..=HOUSEPLANT.warmup_samples + samples_per(HOUSEPLANT.aggregation_window) {
+        now += HOUSEPLANT.sample_period;
+        estimator.update(Messurement { temp: 20.0, moisture: 900 + (sample % 2) as u16, at: now });
+        records.extend(core::iter::from_fn(|| io.messurement_log.try_receive().ok()));
+    }
+    let record = records.first().expect("window closed");
+    assert_eq!((record.water_delivered_ml, record.pump_runtime), (40.0, Duration::from_secs(8)));
+}
@@ -0,0 +1,57 @@
+use dewy_host::statistics::{AggregateSample, AggregateSpan, Aggregator, RunningStats};
+use embassy_time::{Duration, Instant};
+
+fn sample(moisture: f64) -> AggregateSample {
+    AggregateSample { moisture, relative_moisture: moisture / 1000.0, temperature: 20.0, drying_rate: Some(0.05), hours_to_watering: Some(12.0) }
+}
+
+#[test]
+fn running_stats_summarise_and_merge() {
+    let mut all = RunningStats::new();
+    let mut first = RunningStats::new();
+    let mut second = RunningStats::new();
+    for value in [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0] {
+        all.push(value);
+        if value < 5.0 { first.push(value) } else { second.push(value) }
+    }
+    first.merge(&second);
+    first.merge(&RunningStats::new());
+    let summary = all.summary().unwrap();
+    assert_eq!((summary.min, summary.max, summary.mean), (2.0, 9.0, 5.0));
+    assert!((summary.stddev - 2.0).abs() < 1e-12);
+    let merged = first.summary().unwrap();
+    assert!((merged.mean - summary.mean).abs() < 1e-12 && (merged.stddev - summary.stddev).abs() < 1e-12);
+    assert_eq!(RunningStats::new().summary(), None);
+}
+
+#[test]
+fn windows_close_and_roll_up_into_days() {
+    let window = Duration::from_secs(15 * 60);
+    let mut aggregator = Aggregator::new(2, window);
+    let start = Instant::from_ticks(0);
+    let mut windows = Vec::new();
+    let mut days = Vec::new();
+    // Windows close every 16 samples, the first one after a day closes the day as well.
+    for minute in 0..91 * 16 {
+        if minute == 10 {
+            aggregator.add_pumping(Duration::from_secs(6), 120.0, &RunningStats::new());
+        }
+        let (closed_window, closed_day) = aggregator.push(&sample(500.0 + (minute % 2) as f64), start + Duration::from_secs(minute * 60));
+        windows.extend(closed_window);
+        days.extend(closed_day);
+    }
+
+    assert_eq!(windows.len(), 91);
+    let first = &windows[0];
+    assert_eq!((first.zone, first.span, first.samples, first.duration), (2, AggregateSpan::Window, 16, window));
+    assert_eq!((first.water_delivered_ml, first.pump_runtime), (120.0, Duration::from_secs(6)));
+    assert_eq!((first.moisture.min, first.moisture.max), (500.0, 501.0));
+    assert_eq!(first.pump_current_ma, None);
+    assert_eq!(first.drying_rate, Some(0.05));
+    assert_eq!(windows[1].water_delivered_ml, 0.0);
+
+    assert_eq!(days.len(), 1);
+    let day = &days[0];
+    assert_eq!((day.span, day.water_delivered_ml), (AggregateSpan::Day, 120.0));
+    assert_eq!(day.samples, windows.iter().map(|window| window.samples).sum::<u32>());
+}
@@ -0,0 +1,133 @@
+use dewy_host::{events::{Event, EVENT_QUEUE}, flow_calibration::{FlowCalibration, FlowPoint}, persistence::PersistQueue, pump_command::{AbortReason, PumpChannel, PumpCommand, PumpOutcome, PumpStatus}, pump_safety::SafetyLimits, stepper::{run_stepper, StepperDriver, StepperOutput, StepperProfile}, testing::{lock_time, run_for}};
+use core::cell::Cell;
+
+use embassy_futures::select::{select, Either};
+use embassy_sync::{blocking_mutex::raw::NoopRawMutex, channel::Channel, signal::Signal};
+use embassy_time::{Duration, Instant, Timer};
+
+/// Finer than the step interval at top speed, so no step is dropped for a late wakeup.
+const TICK: Duration = Duration::from_millis(1);
+/// Top speed in 0.5 s, 25 steps of ramp each way.
+const PROFILE: StepperProfile = StepperProfile { max_speed: 100.0, acceleration: 200.0 };
+
+/// Counts the steps and notes when the coils were last switched off.
+#[derive(Default)]
+struct Coils {
+    steps: Cell<u64>,
+    enabled: Cell<bool>,
+    disabled_at: Cell<Option<Instant>>,
+}
+
+impl StepperDriver for &Coils {
+    fn enable(&mut self) {
+        self.enabled.set(true);
+    }
+
+    fn disable(&mut self) {
+        self.enabled.set(false);
+        self.disabled_at.set(Some(Instant::now()));
+    }
+
+    fn step(&mut self) {
+        self.steps.set(self.steps.get() + 1);
+    }
+}
+
+struct Rig {
+    channel: PumpChannel,
+    reset: Signal<NoopRawMutex, ()>,
+    events: Channel<NoopRawMutex, Event, EVENT_QUEUE>,
+    persist: PersistQueue,
+}
+
+impl Rig {
+    fn new() -> Self {
+        Self { channel: PumpChannel::new(), reset: Signal::new(), events: Channel::new(), persist: Channel::new() }
+    }
+
+    /// 100 steps per ml.
+    fn output(&self) -> StepperOutput<'_> {
+        StepperOutput {
+            zone: 0,
+            channel: &self.channel,
+            reset: &self.reset,
+            limits: SafetyLimits::default(),
+            profile: PROFILE,
+            flow: FlowCalibration::new(&[FlowPoint { duty: 100, ml_per_s: 1.0 }]),
+            events: self.events.sender(),
+            persist: &self.persist,
+            tank: None,
+        }
+    }
+
+    async fn command(&self, command: PumpCommand) -> PumpStatus {
+        self.channel.command.signal(command);
+        self.channel.status.receive().await
+    }
+}
+
+fn drive<T: core::future::Future, S: core::future::Future>(task: T, script: S, limit: Duration) -> S::Output {
+    match run_for(select(task, script), limit, TICK).expect("script finished in time") {
+        Either::First(_) => unreachable!("the stepper loop never returns"),
+        Either::Second(output) => output,
+    }
+}
+
+#[test]
+fn zero_duty_timed_run_reports_at_once() {
+    let _time = lock_time();
+    let rig = Rig::new();
+    let coils = Coils::default();
+    let status = drive(run_stepper(&mut &coils, rig.output()), rig.command(PumpCommand::RunFor(Duration::from_secs(5), 0)), Duration::from_secs(1));
+    assert_eq!(status.outcome, PumpOutcome::Completed);
+    assert_eq!((status.runtime, status.volume_ml), (Duration::from_ticks(0), 0.0));
+    assert_eq!(coils.steps.get(), 0);
+}
+
+#[test]
+fn empty_dose_is_unsupported() {
+    let _time = lock_time();
+    let rig = Rig::new();
+    let coils = Coils::default();
+    let status = drive(run_stepper(&mut &coils, rig.output()), rig.command(PumpCommand::Dose { ml: 0.0, duty: 50 }), Duration::from_secs(1));
+    assert_eq!(status.outcome, PumpOutcome::Aborted(AbortReason::Unsupported));
+}
+
+#[test]
+fn timed_run_counts_the_deceleration() {
+    let _time = lock_time();
+    let rig = Rig::new();
+    let coils = Coils::default();
+    let start = Instant::now();
+    let script = async {
+        let status = rig.command(PumpCommand::RunFor(Duration::from_secs(2), 100)).await;
+        Timer::after(Duration::from_secs(3)).await;
+        status
+    };
+    let status = drive(run_stepper(&mut &coils, rig.output()), script, Duration::from_secs(10));
+    assert_eq!(status.outcome, PumpOutcome::Completed);
+    assert!(!coils.enabled.get());
+
+    // The status is sent when the run ends, the steps and time of the ramp down are counted ahead.
+    let steps = coils.steps.get() as f64;
+    let stopped = coils.disabled_at.get().unwrap().duration_since(start);
+    assert!((status.volume_ml * 100.0 - steps).abs() <= 2.0, "{} ml for {} steps", status.volume_ml, steps);
+    assert!(status.runtime.as_millis().abs_diff(stopped.as_millis()) <= 20, "{:?} for a run that stopped after {:?}", status.runtime, stopped);
+    // Duty and volume describe the same steps.
+    let duty_steps = status.average_duty / 100.0 * PROFILE.max_speed * status.runtime.as_micros() as f64 / 1e6;
+    assert!((duty_steps - status.volume_ml * 100.0).abs() < 0.01, "{} % over {:?}", status.average_duty, status.runtime);
+    // 80 % for a continuous ramp, the discrete steps ramp up a little faster.
+    assert!((80.0..90.0).contains(&status.average_duty), "{} %", status.average_duty);
+}
+
+#[test]
+fn dose_stops_on_its_last_step() {
+    let _time = lock_time();
+    let rig = Rig::new();
+    let coils = Coils::default();
+    let status = drive(run_stepper(&mut &coils, rig.output()), rig.command(PumpCommand::Dose { ml: 1.5, duty: 100 }), Duration::from_secs(10));
+    assert_eq!(status.outcome, PumpOutcome::Completed);
+    assert_eq!(coils.steps.get(), 150);
+    assert_eq!(status.volume_ml, 1.5);
+    assert!(!coils.enabled.get());
+}
@@ -0,0 +1,128 @@
+use dewy_host::{events::{Event, EVENT_QUEUE}, tank::{run_tank, TankAlert, TankConfig, TankLevelSensor, TankLink, TankMonitor, TankReading, TankSensorError}, testing::{lock_time, run_for}};
+use embassy_futures::select::{select, Either};
+use embassy_sync::{blocking_mutex::raw::NoopRawMutex, channel::Channel};
+use embassy_time::{Duration, Instant, Timer};
+
+const CONFIG: TankConfig = TankConfig { capacity_ml: 10_000.0, low_ml: 2_000.0, empty_level: 0.05, sample_period: Duration::from_secs(60) };
+
+fn at(minutes: u64) -> Instant {
+    Instant::from_ticks(0) + Duration::from_secs(minutes * 60)
+}
+
+#[test]
+fn level_readings_set_the_remaining_volume() {
+    let mut monitor = TankMonitor::new(CONFIG, at(0));
+    assert_eq!(monitor.remaining_ml(), 10_000.0);
+    assert_eq!(monitor.update(TankReading::Level(0.5), at(1)), Some(TankAlert::Status { remaining_ml: 5_000.0, hours_to_empty: None }));
+    assert_eq!(monitor.add_dispensed(1_000.0, at(2)), None);
+    assert_eq!(monitor.remaining_ml(), 4_000.0);
+    // Within the status period only changes are reported.
+    assert_eq!(monitor.update(TankReading::Level(0.45), at(3)), None);
+    assert_eq!(monitor.remaining_ml(), 4_500.0);
+}
+
+#[test]
+fn low_water_is_reported_once_per_fill() {
+    let mut monitor = TankMonitor::new(CONFIG, at(0));
+    monitor.update(TankReading::Level(0.3), at(0));
+    assert!(matches!(monitor.add_dispensed(1_500.0, at(1)), Some(TankAlert::Low { remaining_ml, .. }) if remaining_ml == 1_500.0));
+    assert_eq!(monitor.add_dispensed(500.0, at(2)), None);
+    assert_eq!(monitor.update(TankReading::Level(0.9), at(3)), Some(TankAlert::Refilled { remaining_ml: 9_000.0 }));
+    assert!(matches!(monitor.update(TankReading::Level(0.15), at(4)), Some(TankAlert::Low { .. })));
+}
+
+#[test]
+fn empty_level_inhibits_until_refilled() {
+    let mut monitor = TankMonitor::new(CONFIG, at(0));
+    monitor.update(TankReading::Level(0.2), at(0));
+    assert_eq!(monitor.update(TankReading::Level(0.04), at(1)), Some(TankAlert::Empty));
+    assert!(monitor.is_empty());
+    assert!(matches!(monitor.update(TankReading::Level(0.04), at(2)), Some(TankAlert::Low { .. })));
+    assert_eq!(monitor.update(TankReading::Level(0.04), at(3)), None);
+    assert!(matches!(monitor.update(TankReading::Level(0.8), at(4)), Some(TankAlert::Refilled { .. })));
+    assert!(!monitor.is_empty());
+}
+
+#[test]
+fn float_switch_knows_only_empty_and_refilled() {
+    let mut monitor = TankMonitor::new(CONFIG, at(0));
+    monitor.update(TankReading::Switch { wet: true }, at(0));
+    monitor.add_dispensed(3_000.0, at(1));
+    assert_eq!(monitor.remaining_ml(), 7_000.0);
+    assert_eq!(monitor.update(TankReading::Switch { wet: false }, at(2)), Some(TankAlert::Empty));
+    assert_eq!(monitor.remaining_ml(), 0.0);
+    assert!(matches!(monitor.update(TankReading::Switch { wet: true }, at(3)), Some(TankAlert::Low { .. })));
+    assert!(monitor.is_empty());
+    assert_eq!(monitor.update(TankReading::Switch { wet: true }, at(8)), Some(TankAlert::Refilled { remaining_ml: 10_000.0 }));
+    assert!(!monitor.is_empty());
+}
+
+#[test]
+fn bouncing_float_switch_is_no_refill() {
+    let mut monitor = TankMonitor::new(CONFIG, at(0));
+    assert_eq!(monitor.update(TankReading::Switch { wet: false }, at(0)), Some(TankAlert::Empty));
+    monitor.update(TankReading::Switch { wet: true }, at(1));
+    for minute in 2..20 {
+        assert!(!matches!(monitor.update(TankReading::Switch { wet: minute % 3 != 0 }, at(minute)), Some(TankAlert::Refilled { .. })));
+        assert!(monitor.is_empty());
+        assert_eq!(monitor.remaining_ml(), 0.0);
+    }
+    // Wet from minute 19 on.
+    assert_eq!(monitor.update(TankReading::Switch { wet: true }, at(23)), None);
+    assert!(matches!(monitor.update(TankReading::Switch { wet: true }, at(24)), Some(TankAlert::Refilled { .. })));
+}
+
+#[test]
+fn refill_prediction_needs_half_a_day_of_usage() {
+    let mut monitor = TankMonitor::new(CONFIG, at(0));
+    for hour in 1..12 {
+        monitor.add_dispensed(200.0, at(hour * 60));
+        assert_eq!(monitor.hours_to_empty(at(hour * 60)), None);
+    }
+    monitor.add_dispensed(200.0, at(12 * 60));
+    // 2.4 l used in 12 h leaves 7.6 l for 38 h.
+    let hours = monitor.hours_to_empty(at(12 * 60)).unwrap();
+    assert!((hours - 38.0).abs() < 1e-9, "{} h", hours);
+}
+
+/// Reads the levels of a script, then keeps the last one.
+struct ScriptedSensor(Vec<Result<TankReading, TankSensorError>>);
+
+impl TankLevelSensor for ScriptedSensor {
+    async fn read(&mut self) -> Result<TankReading, TankSensorError> {
+        if self.0.len() > 1 {
+            self.0.remove(0)
+        } else {
+            self.0[0]
+        }
+    }
+}
+
+#[test]
+fn monitor_task_inhibits_the_pumps_of_an_empty_tank() {
+    let _time = lock_time();
+    let link = TankLink::new();
+    let events = Channel::<NoopRawMutex, Event, EVENT_QUEUE>::new();
+    let mut sensor = ScriptedSensor(vec![Ok(TankReading::Level(0.5)), Err(TankSensorError::NoEcho), Ok(TankReading::Level(0.02)), Ok(TankReading::Level(0.7))]);
+    let script = async {
+        Timer::after(Duration::from_secs(1)).await;
+        link.dispensed(500.0);
+        Timer::after(Duration::from_secs(1)).await;
+        let before = (link.is_empty(), events.try_receive().ok(), events.try_receive().ok());
+        Timer::after(CONFIG.sample_period * 2).await;
+        let empty = (link.is_empty(), events.try_receive().ok());
+        Timer::after(CONFIG.sample_period).await;
+        (before, empty, link.is_empty(), events.try_receive().ok())
+    };
+    let Some(Either::Second(outcome)) = run_for(select(run_tank(&mut sensor, CONFIG, &link, events.sender()), script), Duration::from_secs(600), Duration::from_secs(1)) else {
+        panic!("script finished in time");
+    };
+    let ((empty_at_start, status, dispensed), (empty, alert), refilled_empty, refilled) = outcome;
+    assert!(!empty_at_start);
+    assert!(matches!(status, Some(Event::Tank { alert: TankAlert::Status { remaining_ml, .. } }) if remaining_ml == 5_000.0));
+    assert!(dispensed.is_none());
+    assert!(empty);
+    assert!(matches!(alert, Some(Event::Tank { alert: TankAlert::Empty })));
+    assert!(!refilled_empty);
+    assert!(matches!(refilled, Some(Event::Tank { alert: TankAlert::Refilled { .. } })));
+}
@@ -0,0 +1,100 @@
+use dewy_host::{events::Event, manual_override::ManualCommand, sensor_fault::SensorFault, tank::TankAlert, temperature_guard::WateringDecision, pump_command::{AbortReason, PumpCommand, PumpOutcome, PumpStatus}, pump_safety::PumpFault, statistics::{AggregateRecord, AggregateSpan, Summary}, upload::{serialize_batch, Uptime, MAX_EVENTS, MAX_HISTORY, MAX_MESSUREMENTS}, watering_history::{Fluid, HistoryQuery, RunReason, WateringHistory, WateringRecord}};
+use embassy_time::{Duration, Instant};
+
+fn run(started_s: u64, outcome: PumpOutcome) -> PumpStatus {
+    PumpStatus { started: Instant::from_ticks(0) + Duration::from_secs(started_s), runtime: Duration::from_secs(12), average_duty: 80.0, volume_ml: 240.0, outcome, ..PumpStatus::skipped(PumpCommand::Off) }
+}
+
+const UPTIME: Uptime = Uptime { boot_id: 0xC0FFEE, uptime_ms: 86_400_000 };
+
+fn serialized(events: &[Event], history: Option<&[WateringRecord]>) -> String {
+    let mut buffer = [0u8; 8192];
+    let length = serialize_batch(UPTIME, &[], events, history, &mut buffer).unwrap();
+    String::from_utf8(buffer[..length].to_vec()).unwrap()
+}
+
+/// The JSON of `event` alone.
+fn event_json(event: Event) -> String {
+    let batch = serialized(&[event], None);
+    let prefix = r#"{"boot_id":12648430,"uptime_ms":86400000,"messurements":[],"events":["#;
+    batch.strip_prefix(prefix).and_then(|events| events.strip_suffix("]}")).unwrap_or_else(|| panic!("unexpected batch {}", batch)).to_string()
+}
+
+#[test]
+fn history_is_only_sent_when_asked_for() {
+    assert_eq!(serialized(&[], None), r#"{"boot_id":12648430,"uptime_ms":86400000,"messurements":[],"events":[]}"#);
+    assert_eq!(serialized(&[], Some(&[])), r#"{"boot_id":12648430,"uptime_ms":86400000,"messurements":[],"events":[],"history":[]}"#);
+}
+
+#[test]
+fn answers_a_history_query() {
+    let mut history = WateringHistory::<16>::new();
+    history.push(0, Fluid::Water, &run(60, PumpOutcome::Completed), RunReason::Auto);
+    history.push(1, Fluid::Water, &run(90, PumpOutcome::Completed), RunReason::Remote);
+    history.push(0, Fluid::Nutrient, &run(120, PumpOutcome::Completed), RunReason::Auto);
+    history.push(0, Fluid::Water, &run(600, PumpOutcome::Aborted(AbortReason::SafetyLimit(PumpFault::MaxOnTime))), RunReason::Manual);
+
+    let records = history.query::<MAX_HISTORY>(&HistoryQuery { zone: Some(0), fluid: Some(Fluid::Water), after_id: Some(0), ..Default::default() });
+    assert_eq!(serialized(&[], Some(&records)), concat!(
+        r#"{"boot_id":12648430,"uptime_ms":86400000,"messurements":[],"events":[],"history":[{"id":3,"zone":0,"fluid":"water","started_ms":600000,"duration_ms":12000,"#,
+        r#""average_duty":80.0,"volume_ml":240.0,"reason":"manual","end":{"safety_limit":"max_on_time"}}]}"#,
+    ));
+}
+
+#[test]
+fn history_pages_by_id() {
+    let mut history = WateringHistory::<64>::new();
+    for started in 0..20 {
+        history.push(0, Fluid::Water, &run(started, PumpOutcome::Completed), RunReason::Auto);
+    }
+    let first = history.query::<MAX_HISTORY>(&HistoryQuery::default());
+    assert_eq!(first.iter().map(|record| record.id).collect::<Vec<_>>(), (0..8).collect::<Vec<_>>());
+    let next = history.query::<MAX_HISTORY>(&HistoryQuery { after_id: first.last().map(|record| record.id), ..Default::default() });
+    assert_eq!(next.first().map(|record| record.id), Some(8));
+}
+
+#[test]
+fn events_are_sent_field_by_field() {
+    assert_eq!(event_json(Event::SensorRecovered { zone: 1 }), r#"{"kind":"sensor_recovered","zone":1}"#);
+    assert_eq!(event_json(Event::SensorFault { zone: 0, fault: SensorFault::Stuck { moisture: 512, samples: 40 } }), r#"{"kind":"sensor_fault","zone":0,"fault":{"stuck":{"moisture":512,"samples":40}}}"#);
+    assert_eq!(event_json(Event::SensorFault { zone: 0, fault: SensorFault::TemperatureOutOfRange(f32::NAN) }), r#"{"kind":"sensor_fault","zone":0,"fault":{"temperature_out_of_range":null}}"#);
+    assert_eq!(event_json(Event::Watering { zone: 2, decision: WateringDecision::FrostInhibit { temperature: 1.5 } }), r#"{"kind":"watering","zone":2,"decision":{"frost_inhibit":{"temperature":1.5}}}"#);
+    assert_eq!(
+        event_json(Event::Watered { zone: 0, requested_ml: 200.0, delivered_ml: 80.5, outcome: PumpOutcome::Aborted(AbortReason::Refused(PumpFault::Cooldown)) }),
+        r#"{"kind":"watered","zone":0,"requested_ml":200.0,"delivered_ml":80.5,"outcome":{"aborted":{"refused":"cooldown"}}}"#,
+    );
+    assert_eq!(event_json(Event::PumpFault { zone: 0, fault: PumpFault::NoFlow }), r#"{"kind":"pump_fault","zone":0,"fault":"no_flow"}"#);
+    assert_eq!(event_json(Event::Tank { alert: TankAlert::Empty }), r#"{"kind":"tank","alert":"empty"}"#);
+    assert_eq!(event_json(Event::Tank { alert: TankAlert::Low { remaining_ml: 400.0, hours_to_empty: None } }), r#"{"kind":"tank","alert":{"low":{"remaining_ml":400.0,"hours_to_empty":null}}}"#);
+    assert_eq!(event_json(Event::ManualControl { zone: 1, command: ManualCommand::RunFor(Duration::from_secs(30), 80) }), r#"{"kind":"manual_control","zone":1,"command":{"run_for":{"duration_ms":30000,"duty":80}}}"#);
+    assert_eq!(event_json(Event::ManualControl { zone: 1, command: ManualCommand::Prime }), r#"{"kind":"manual_control","zone":1,"command":"prime"}"#);
+    assert_eq!(event_json(Event::AutomaticControl { zone: 1 }), r#"{"kind":"automatic_control","zone":1}"#);
+}
+
+#[test]
+fn pump_runs_carry_their_record() {
+    let mut history = WateringHistory::<4>::new();
+    let record = history.push(1, Fluid::Nutrient, &run(5, PumpOutcome::Aborted(AbortReason::TankEmpty)), RunReason::Auto);
+    assert_eq!(event_json(Event::PumpRun { record }), concat!(
+        r#"{"kind":"pump_run","record":{"id":0,"zone":1,"fluid":"nutrient","started_ms":5000,"duration_ms":12000,"#,
+        r#""average_duty":80.0,"volume_ml":240.0,"reason":"auto","end":"tank_empty"}}"#,
+    ));
+}
+
+#[test]
+fn full_batch_fits_the_upload_buffer() {
+    // Long decimals throughout, the worst case for the length of the numbers.
+    let summary = Summary { min: 123.456789012345, max: 987.654321098765, mean: 555.555555555555, stddev: 12.3456789012345 };
+    let record = AggregateRecord {
+        zone: 3, span: AggregateSpan::Day, start: Instant::from_ticks(0) + Duration::from_secs(31_536_000), duration: Duration::from_secs(86_400), samples: 86_400,
+        moisture: summary, relative_moisture: summary, temperature: summary, water_delivered_ml: 1234.56789012345, pump_runtime: Duration::from_secs(600),
+        pump_current_ma: Some(summary), drying_rate: Some(0.0123456789012345), hours_to_watering: Some(47.123456789012345),
+    };
+    let events = [Event::Watered { zone: 0, requested_ml: 200.123456789012, delivered_ml: 180.987654321098, outcome: PumpOutcome::Aborted(AbortReason::SafetyLimit(PumpFault::DryRunning)) }; MAX_EVENTS];
+    let records: Vec<WateringRecord> = (0..MAX_HISTORY as u64).map(|started| {
+        WateringHistory::<1>::new().push(0, Fluid::Water, &run(started, PumpOutcome::Aborted(AbortReason::SafetyLimit(PumpFault::MaxOnTime))), RunReason::Remote)
+    }).collect();
+    // UPLOAD_BUFFER in networking.rs.
+    let mut buffer = [0u8; 12288];
+    assert!(serialize_batch(UPTIME, &[record; MAX_MESSUREMENTS], &events, Some(&records), &mut buffer).is_ok());
+}
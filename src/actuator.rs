@@ -0,0 +1,186 @@
+use heapless::Vec;
+
+use crate::{expander::{ExpanderConfig, MAX_EXPANDERS}, stepper::StepperProfile, zone::{Actuator, NutrientPump, PwmOutput, ValveOutput, ZoneConfig, FLOW_SENSOR_INPUTS, MAX_ZONES, SHUNT_INPUTS, VALVE_PINS}};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ActuatorError {
+    TooManyZones,
+    TooManyExpanders,
+    /// A pump or stepper output of one zone is also used by another zone.
+    PumpShared(PwmOutput),
+    ValveShared(ValveOutput),
+    FlowSensorShared(usize),
+    CurrentSenseShared(usize),
+    /// The output has no pin wired up in `main`.
+    NoPwmPin(PwmOutput),
+    NoValvePin(usize),
+    NoFlowSensorInput(usize),
+    NoShuntInput(usize),
+    NoExpander(usize),
+    NoExpanderPin { expander: usize, pin: u8 },
+}
+
+/// A pump and the zones it waters, directly or through the valves of a manifold.
+#[derive(Debug, Clone)]
+pub struct PumpAssignment {
+    pub output: PwmOutput,
+    /// Zone and its manifold valve, the first zone configures the pump.
+    pub zones: Vec<(usize, Option<ValveOutput>), MAX_ZONES>,
+}
+
+impl PumpAssignment {
+    pub fn owner(&self) -> usize {
+        self.zones[0].0
+    }
+
+    pub fn is_manifold(&self) -> bool {
+        self.zones.iter().any(|(_, valve)| valve.is_some())
+    }
+}
+
+/// Which zone drives which output, checked for outputs claimed twice or not wired up.
+pub struct ActuatorRegistry {
+    pumps: Vec<PumpAssignment, MAX_ZONES>,
+    /// Valves on pressurised lines, each serving one zone.
+    valves: Vec<(usize, ValveOutput), MAX_ZONES>,
+    /// Stepper pumps, each serving one zone.
+    steppers: Vec<(usize, PwmOutput, StepperProfile), MAX_ZONES>,
+    /// Nutrient pumps, each serving one zone.
+    nutrients: Vec<(usize, NutrientPump), MAX_ZONES>,
+}
+
+impl ActuatorRegistry {
+    pub fn new(zones: &[ZoneConfig], expanders: &[ExpanderConfig]) -> Result<Self, ActuatorError> {
+        if zones.len() > MAX_ZONES {
+            return Err(ActuatorError::TooManyZones);
+        }
+        if expanders.len() > MAX_EXPANDERS {
+            return Err(ActuatorError::TooManyExpanders);
+        }
+        let mut registry = Self { pumps: Vec::new(), valves: Vec::new(), steppers: Vec::new(), nutrients: Vec::new() };
+        for (zone, config) in zones.iter().enumerate() {
+            check_inputs(zones, zone, config)?;
+            match config.actuator {
+                Actuator::Pump(output) => registry.add_pump(zone, output, None)?,
+                Actuator::Valve(valve) => {
+                    registry.claim_valve(valve, expanders)?;
+                    registry.valves.push((zone, valve)).map_err(|_| ActuatorError::TooManyZones)?;
+                },
+                Actuator::Manifold { pump, valve } => {
+                    registry.claim_valve(valve, expanders)?;
+                    registry.add_pump(zone, pump, Some(valve))?;
+                },
+                Actuator::Stepper { step, profile } => {
+                    check_wired(step)?;
+                    if registry.claims_output(step) {
+                        return Err(ActuatorError::PumpShared(step));
+                    }
+                    registry.steppers.push((zone, step, profile)).map_err(|_| ActuatorError::TooManyZones)?;
+                },
+            }
+            if let Some(nutrient) = config.nutrient {
+                let (NutrientPump::Pump(output) | NutrientPump::Stepper { step: output, .. }) = nutrient.pump;
+                check_wired(output)?;
+                if registry.claims_output(output) {
+                    return Err(ActuatorError::PumpShared(output));
+                }
+                registry.nutrients.push((zone, nutrient.pump)).map_err(|_| ActuatorError::TooManyZones)?;
+            }
+        }
+        Ok(registry)
+    }
+
+    pub fn pumps(&self) -> &[PumpAssignment] {
+        &self.pumps
+    }
+
+    pub fn valves(&self) -> &[(usize, ValveOutput)] {
+        &self.valves
+    }
+
+    pub fn steppers(&self) -> &[(usize, PwmOutput, StepperProfile)] {
+        &self.steppers
+    }
+
+    pub fn nutrients(&self) -> &[(usize, NutrientPump)] {
+        &self.nutrients
+    }
+
+    fn claims_output(&self, output: PwmOutput) -> bool {
+        self.pumps.iter().any(|pump| pump.output == output) || self.claims_alone(output)
+    }
+
+    /// The output drives a stepper or nutrient pump, which can not be shared.
+    fn claims_alone(&self, output: PwmOutput) -> bool {
+        let nutrient_outputs = self.nutrients.iter().map(|(_, pump)| match *pump {
+            NutrientPump::Pump(output) | NutrientPump::Stepper { step: output, .. } => output,
+        });
+        self.steppers.iter().map(|(_, step, _)| *step).chain(nutrient_outputs).any(|claimed| claimed == output)
+    }
+
+    fn add_pump(&mut self, zone: usize, output: PwmOutput, valve: Option<ValveOutput>) -> Result<(), ActuatorError> {
+        check_wired(output)?;
+        if self.claims_alone(output) {
+            return Err(ActuatorError::PumpShared(output));
+        }
+        match self.pumps.iter_mut().find(|pump| pump.output == output) {
+            // A pump without manifold valve serves its zone alone.
+            Some(pump) if valve.is_none() || !pump.is_manifold() => Err(ActuatorError::PumpShared(output)),
+            Some(pump) => pump.zones.push((zone, valve)).map_err(|_| ActuatorError::TooManyZones),
+            None => {
+                let mut zones = Vec::new();
+                let _ = zones.push((zone, valve));
+                self.pumps.push(PumpAssignment { output, zones }).map_err(|_| ActuatorError::TooManyZones)
+            },
+        }
+    }
+
+    fn claim_valve(&self, valve: ValveOutput, expanders: &[ExpanderConfig]) -> Result<(), ActuatorError> {
+        match valve {
+            ValveOutput::Gpio(index) if index >= VALVE_PINS => return Err(ActuatorError::NoValvePin(index)),
+            ValveOutput::Gpio(_) => (),
+            ValveOutput::Expander { expander, pin } => {
+                let config = expanders.get(expander).ok_or(ActuatorError::NoExpander(expander))?;
+                if pin >= config.kind.pins() {
+                    return Err(ActuatorError::NoExpanderPin { expander, pin });
+                }
+            },
+        }
+        let manifold_valves = self.pumps.iter().flat_map(|pump| pump.zones.iter().filter_map(|(_, valve)| *valve));
+        let mut claimed = self.valves.iter().map(|(_, valve)| *valve).chain(manifold_valves);
+        if claimed.any(|claimed| claimed == valve) {
+            return Err(ActuatorError::ValveShared(valve));
+        }
+        Ok(())
+    }
+}
+
+fn check_wired(output: PwmOutput) -> Result<(), ActuatorError> {
+    if output.is_wired() {
+        Ok(())
+    } else {
+        Err(ActuatorError::NoPwmPin(output))
+    }
+}
+
+/// Flow sensor and shunt inputs of a zone exist and are not used by an earlier zone.
+fn check_inputs(zones: &[ZoneConfig], zone: usize, config: &ZoneConfig) -> Result<(), ActuatorError> {
+    let earlier = &zones[..zone];
+    if let Some(sensor) = config.flow_sensor {
+        if sensor.input >= FLOW_SENSOR_INPUTS {
+            return Err(ActuatorError::NoFlowSensorInput(sensor.input));
+        }
+        if earlier.iter().any(|other| other.flow_sensor.map(|other| other.input) == Some(sensor.input)) {
+            return Err(ActuatorError::FlowSensorShared(sensor.input));
+        }
+    }
+    if let Some(sense) = config.current_sense {
+        if sense.input >= SHUNT_INPUTS {
+            return Err(ActuatorError::NoShuntInput(sense.input));
+        }
+        if earlier.iter().any(|other| other.current_sense.map(|other| other.input) == Some(sense.input)) {
+            return Err(ActuatorError::CurrentSenseShared(sense.input));
+        }
+    }
+    Ok(())
+}
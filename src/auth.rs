@@ -0,0 +1,100 @@
+use core::fmt::Write as _;
+
+use heapless::String;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// The handshake is a GET of this path with a `HANDSHAKE_SCHEME` authorization.
+pub const HANDSHAKE_PATH: &str = "/auth";
+pub const NONCE_HEADER: &str = "X-Dewy-Nonce";
+pub const SIGNATURE_HEADER: &str = "X-Dewy-Signature";
+const HANDSHAKE_SCHEME: &str = "Dewy-Handshake";
+const REQUEST_SCHEME: &str = "Dewy-HMAC-SHA256";
+
+/// Authorization header values, enough for device ids of up to 64 characters.
+pub type AuthorizationHeader = String<192>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AuthError {
+    /// The handshake response lacks the server nonce or its signature.
+    MissingHeader,
+    Malformed,
+    /// The server does not know the secret or the response belongs to another handshake.
+    BadSignature,
+}
+
+/// Identity of the device, the secret is only shared with the server.
+pub struct DeviceKey {
+    pub id: &'static str,
+    pub secret: &'static [u8],
+}
+
+impl DeviceKey {
+    /// MAC over `role` and the `parts`, each on its own line.
+    fn mac(&self, role: &str, parts: &[&[u8]]) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(self.secret).expect("HMAC takes keys of any length");
+        mac.update(role.as_bytes());
+        for part in parts {
+            mac.update(b"\n");
+            mac.update(part);
+        }
+        mac
+    }
+
+    /// Opens a handshake with the device nonce `local_nonce`, which must never repeat.
+    pub fn handshake_header(&self, local_nonce: u64) -> AuthorizationHeader {
+        let mut header = String::new();
+        let _ = write!(header, "{} id={},nonce={:016x}", HANDSHAKE_SCHEME, self.id, local_nonce);
+        header
+    }
+}
+
+/// Server nonce of one authenticated exchange with the server.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Authentication {
+    /// Single use, the server refuses a second request signed over it.
+    pub server_nonce: u64,
+}
+
+impl Authentication {
+    /// Checks the server nonce and signature of a handshake response. The signature covers the
+    /// fresh local nonce, so a recorded response of an earlier handshake is refused.
+    pub fn accept(key: &DeviceKey, local_nonce: u64, server_nonce: Option<&[u8]>, signature: Option<&[u8]>) -> Result<Self, AuthError> {
+        let (server_nonce, signature) = server_nonce.zip(signature).ok_or(AuthError::MissingHeader)?;
+        let server_nonce = core::str::from_utf8(server_nonce).ok().and_then(|nonce| u64::from_str_radix(nonce.trim(), 16).ok()).ok_or(AuthError::Malformed)?;
+        let signature = decode_signature(signature).ok_or(AuthError::Malformed)?;
+        let mut local: String<16> = String::new();
+        let mut server: String<16> = String::new();
+        let _ = write!(local, "{:016x}", local_nonce);
+        let _ = write!(server, "{:016x}", server_nonce);
+        key.mac("server", &[key.id.as_bytes(), local.as_bytes(), server.as_bytes()]).verify_slice(&signature).map_err(|_| AuthError::BadSignature)?;
+        Ok(Self { server_nonce })
+    }
+
+    /// Authorization header that signs a request with the server nonce.
+    pub fn sign_request(&self, key: &DeviceKey, method: &str, path: &str, body: &[u8]) -> AuthorizationHeader {
+        let mut nonce: String<16> = String::new();
+        let _ = write!(nonce, "{:016x}", self.server_nonce);
+        let signature = key.mac("device", &[key.id.as_bytes(), method.as_bytes(), path.as_bytes(), nonce.as_bytes(), body]).finalize().into_bytes();
+        let mut header = String::new();
+        let _ = write!(header, "{} id={},nonce={},signature=", REQUEST_SCHEME, key.id, nonce);
+        for byte in signature {
+            let _ = write!(header, "{:02x}", byte);
+        }
+        header
+    }
+}
+
+fn decode_signature(hex: &[u8]) -> Option<[u8; 32]> {
+    let hex = core::str::from_utf8(hex).ok()?.trim();
+    if hex.len() != 64 {
+        return None;
+    }
+    let mut signature = [0; 32];
+    for (index, byte) in signature.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(hex.get(2 * index..2 * index + 2)?, 16).ok()?;
+    }
+    Some(signature)
+}
@@ -0,0 +1,116 @@
+/// Raw Seesaw readings of the probe in dry and in saturated soil.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Calibration {
+    pub dry: f64,
+    pub wet: f64,
+}
+
+impl Calibration {
+    pub const fn new(dry: f64, wet: f64) -> Self {
+        Self { dry, wet }
+    }
+
+    /// Maps a raw reading to 0.0 (dry) .. 1.0 (saturated), readings outside the span are clamped.
+    pub fn relative(&self, raw: f64) -> f64 {
+        if self.wet <= self.dry {
+            return 0.0;
+        }
+        ((raw - self.dry) / (self.wet - self.dry)).clamp(0.0, 1.0)
+    }
+
+    /// Raw reading that corresponds to a relative moisture.
+    pub fn raw(&self, relative: f64) -> f64 {
+        self.dry + relative * (self.wet - self.dry)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CalibrationMode {
+    /// Endpoints follow the observed watering cycles.
+    Learning,
+    /// Endpoints were set by hand and are never adjusted.
+    Manual,
+}
+
+/// How fast the learned endpoints may move per watering cycle.
+#[derive(Debug, Clone, Copy)]
+pub struct LearningRates {
+    /// Fraction of the difference between observation and endpoint applied per cycle.
+    pub rate: f64,
+    /// Largest change of an endpoint per cycle in raw counts.
+    pub max_step: f64,
+}
+
+impl Default for LearningRates {
+    fn default() -> Self {
+        Self { rate: 0.2, max_step: 25.0 }
+    }
+}
+
+/// Refines the probe calibration from watering cycles.
+///
+/// The wet endpoint tracks the peak moisture reached after a watering has soaked in (field capacity).
+/// The dry endpoint only ever moves down to readings lower than it, pulling it up towards the
+/// pre-watering low would drag the watering threshold along with it.
+pub struct CalibrationLearner {
+    calibration: Calibration,
+    mode: CalibrationMode,
+    rates: LearningRates,
+    lowest: f64,
+    peak: Option<f64>,
+}
+
+impl CalibrationLearner {
+    pub fn new(calibration: Calibration, mode: CalibrationMode, rates: LearningRates) -> Self {
+        Self { calibration, mode, rates, lowest: f64::MAX, peak: None }
+    }
+
+    pub fn calibration(&self) -> Calibration {
+        self.calibration
+    }
+
+    pub fn mode(&self) -> CalibrationMode {
+        self.mode
+    }
+
+    /// Pins the calibration to hand measured endpoints.
+    pub fn set_manual(&mut self, calibration: Calibration) {
+        self.calibration = calibration;
+        self.mode = CalibrationMode::Manual;
+    }
+
+    pub fn resume_learning(&mut self) {
+        self.mode = CalibrationMode::Learning;
+    }
+
+    /// Tracks the lowest moisture while the pot is drying.
+    pub fn observe_drying(&mut self, raw: f64) {
+        self.lowest = self.lowest.min(raw);
+    }
+
+    /// Tracks the highest moisture while a watering soaks in.
+    pub fn observe_soaking(&mut self, raw: f64) {
+        self.peak = Some(self.peak.map_or(raw, |peak| peak.max(raw)));
+    }
+
+    /// Applies the observations of a finished watering cycle, returns true if the calibration changed.
+    pub fn cycle_finished(&mut self) -> bool {
+        let lowest = core::mem::replace(&mut self.lowest, f64::MAX);
+        let peak = self.peak.take();
+        if self.mode == CalibrationMode::Manual {
+            return false;
+        }
+        let previous = self.calibration;
+        if lowest < self.calibration.dry {
+            self.calibration.dry += self.bounded_step(lowest - self.calibration.dry);
+        }
+        if let Some(peak) = peak.filter(|peak| *peak > self.calibration.dry) {
+            self.calibration.wet += self.bounded_step(peak - self.calibration.wet);
+        }
+        self.calibration != previous
+    }
+
+    fn bounded_step(&self, error: f64) -> f64 {
+        (self.rates.rate * error).clamp(-self.rates.max_step, self.rates.max_step)
+    }
+}
@@ -0,0 +1,138 @@
+use embassy_time::{Duration, Instant};
+use libm::log;
+
+/// Minimum number of samples and time span before a drying curve fit is trusted.
+const MIN_SAMPLES: u32 = 20;
+const MIN_SPAN_HOURS: f64 = 1.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DryingEstimate {
+    /// Exponential decay constant of the moisture above the dry floor per hour.
+    pub rate_per_hour: f64,
+    /// Hours until the fitted curve crosses the watering threshold.
+    pub hours_to_threshold: f64,
+}
+
+/// Regression sums of a `DryingModel`, with the time axis relative to the last sample.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DryingSnapshot {
+    pub lowest: f64,
+    pub n: u32,
+    pub sum_t: f64,
+    pub sum_y: f64,
+    pub sum_tt: f64,
+    pub sum_ty: f64,
+    pub last_t: f64,
+}
+
+/// Least squares fit of `moisture(t) = floor + a * exp(-k * t)` over the samples since the last watering.
+pub struct DryingModel {
+    floor: f64,
+    rewet_step: f64,
+    start: Option<Instant>,
+    lowest: f64,
+    n: u32,
+    sum_t: f64,
+    sum_y: f64,
+    sum_tt: f64,
+    sum_ty: f64,
+    last_t: f64,
+}
+
+impl DryingModel {
+    /// `floor` is the moisture a bone dry pot converges to, a rise of `rewet_step` above the lowest
+    /// moisture seen since the last watering restarts the fit.
+    pub fn new(floor: f64, rewet_step: f64) -> Self {
+        Self {
+            floor,
+            rewet_step,
+            start: None,
+            lowest: f64::MAX,
+            n: 0,
+            sum_t: 0.0,
+            sum_y: 0.0,
+            sum_tt: 0.0,
+            sum_ty: 0.0,
+            last_t: 0.0,
+        }
+    }
+
+    pub fn restart(&mut self) {
+        *self = Self::new(self.floor, self.rewet_step);
+    }
+
+    /// Moves the asymptote, e.g. after a calibration update, and restarts the fit.
+    pub fn set_floor(&mut self, floor: f64) {
+        self.floor = floor;
+        self.restart();
+    }
+
+    pub fn snapshot(&self) -> DryingSnapshot {
+        DryingSnapshot {
+            lowest: self.lowest,
+            n: self.n,
+            sum_t: self.sum_t,
+            sum_y: self.sum_y,
+            sum_tt: self.sum_tt,
+            sum_ty: self.sum_ty,
+            last_t: self.last_t,
+        }
+    }
+
+    /// Continues a fit from a snapshot as if its last sample was taken at `now`.
+    pub fn restore(&mut self, snapshot: &DryingSnapshot, now: Instant) {
+        let elapsed = Duration::from_millis((snapshot.last_t * 3_600_000.0) as u64);
+        self.start = (snapshot.n > 0).then(|| now.checked_sub(elapsed).unwrap_or(Instant::from_ticks(0)));
+        self.lowest = snapshot.lowest;
+        self.n = snapshot.n;
+        self.sum_t = snapshot.sum_t;
+        self.sum_y = snapshot.sum_y;
+        self.sum_tt = snapshot.sum_tt;
+        self.sum_ty = snapshot.sum_ty;
+        self.last_t = snapshot.last_t;
+    }
+
+    pub fn update(&mut self, moisture: f64, at: Instant) {
+        if moisture - self.lowest > self.rewet_step {
+            self.restart();
+        }
+        self.lowest = self.lowest.min(moisture);
+        if moisture <= self.floor {
+            return;
+        }
+        let start = *self.start.get_or_insert(at);
+        let t = hours_between(start, at);
+        let y = log(moisture - self.floor);
+        self.n += 1;
+        self.sum_t += t;
+        self.sum_y += y;
+        self.sum_tt += t * t;
+        self.sum_ty += t * y;
+        self.last_t = t;
+    }
+
+    /// Fitted decay and the time until the curve reaches `threshold`, once enough drying has been observed.
+    pub fn estimate(&self, threshold: f64) -> Option<DryingEstimate> {
+        if self.n < MIN_SAMPLES || self.last_t < MIN_SPAN_HOURS || threshold <= self.floor {
+            return None;
+        }
+        let n = self.n as f64;
+        let denominator = n * self.sum_tt - self.sum_t * self.sum_t;
+        if denominator <= 0.0 {
+            return None;
+        }
+        let slope = (n * self.sum_ty - self.sum_t * self.sum_y) / denominator;
+        let intercept = (self.sum_y - slope * self.sum_t) / n;
+        if slope >= 0.0 {
+            // Not drying, e.g. still soaking in or a sensor drifting upwards.
+            return None;
+        }
+        let fitted_now = intercept + slope * self.last_t;
+        let hours_to_threshold = ((log(threshold - self.floor) - fitted_now) / slope).max(0.0);
+        Some(DryingEstimate { rate_per_hour: -slope, hours_to_threshold })
+    }
+}
+
+fn hours_between(start: Instant, end: Instant) -> f64 {
+    end.checked_duration_since(start).map(|d| d.as_millis() as f64 / 3_600_000.0).unwrap_or(0.0)
+}
@@ -0,0 +1,25 @@
+use embassy_sync::{blocking_mutex::raw::NoopRawMutex, channel::Sender};
+
+use crate::{manual_override::ManualCommand, pump_command::PumpOutcome, pump_safety::PumpFault, sensor_fault::SensorFault, tank::TankAlert, temperature_guard::WateringDecision, watering_history::WateringRecord};
+
+pub const EVENT_QUEUE: usize = 16;
+pub type EventSender<'a> = Sender<'a, NoopRawMutex, Event, EVENT_QUEUE>;
+
+/// Notable state changes that are reported alongside the periodic messurement uploads.
+#[derive(Debug, Clone, Copy)]
+pub enum Event {
+    SensorFault { zone: usize, fault: SensorFault },
+    SensorRecovered { zone: usize },
+    Watering { zone: usize, decision: WateringDecision },
+    /// A dose ended, `delivered_ml` is estimated from the run time and the flow calibration.
+    Watered { zone: usize, requested_ml: f64, delivered_ml: f64, outcome: PumpOutcome },
+    /// A pump safety limit was hit, the pump stays off until the fault is reset.
+    PumpFault { zone: usize, fault: PumpFault },
+    PumpFaultReset { zone: usize },
+    /// The pump of a zone ran, also kept in the watering history.
+    PumpRun { record: WateringRecord },
+    Tank { alert: TankAlert },
+    /// The pump of the zone was taken over by hand, automatic commands are refused.
+    ManualControl { zone: usize, command: ManualCommand },
+    AutomaticControl { zone: usize },
+}
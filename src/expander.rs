@@ -0,0 +1,111 @@
+use core::sync::atomic::{AtomicU16, Ordering};
+
+use embassy_sync::{blocking_mutex::raw::NoopRawMutex, signal::Signal};
+use embedded_hal_async::i2c::I2c;
+
+use crate::pump_driver::PumpDriver;
+
+pub const MAX_EXPANDERS: usize = 2;
+
+/// MCP23017 registers, with IOCON.BANK = 0 the B register follows the A register.
+const MCP23017_IODIRA: u8 = 0x00;
+const MCP23017_OLATA: u8 = 0x14;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExpanderKind {
+    /// 8 quasi-bidirectional pins, a high pin only sources a weak pull-up.
+    Pcf8574,
+    /// 16 push-pull pins on ports A and B.
+    Mcp23017,
+}
+
+impl ExpanderKind {
+    pub fn pins(&self) -> u8 {
+        match self {
+            Self::Pcf8574 => 8,
+            Self::Mcp23017 => 16,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ExpanderConfig {
+    pub kind: ExpanderKind,
+    pub address: u8,
+    /// Relay boards usually switch on with a low pin.
+    pub active_low: bool,
+}
+
+impl ExpanderConfig {
+    /// Sets up the pins as outputs, all switched off.
+    pub async fn configure<I: I2c>(&self, i2c: &mut I) -> Result<(), I::Error> {
+        if self.kind == ExpanderKind::Mcp23017 {
+            i2c.write(self.address, &[MCP23017_IODIRA, 0x00, 0x00]).await?;
+        }
+        self.write(i2c, 0).await
+    }
+
+    /// Writes the output latch, bit n switches pin n on.
+    pub async fn write<I: I2c>(&self, i2c: &mut I, outputs: u16) -> Result<(), I::Error> {
+        let levels = if self.active_low { !outputs } else { outputs };
+        match self.kind {
+            ExpanderKind::Pcf8574 => i2c.write(self.address, &[levels as u8]).await,
+            ExpanderKind::Mcp23017 => {
+                let [a, b] = levels.to_le_bytes();
+                i2c.write(self.address, &[MCP23017_OLATA, a, b]).await
+            },
+        }
+    }
+}
+
+/// Output state of the expanders, set by the valve drivers and written out by the task owning the bus.
+pub struct ExpanderLink {
+    outputs: [AtomicU16; MAX_EXPANDERS],
+    changed: Signal<NoopRawMutex, ()>,
+}
+
+impl ExpanderLink {
+    pub const fn new() -> Self {
+        Self { outputs: [AtomicU16::new(0), AtomicU16::new(0)], changed: Signal::new() }
+    }
+
+    pub fn set(&self, expander: usize, pin: u8, on: bool) {
+        let mask = 1 << pin;
+        let previous = if on { self.outputs[expander].fetch_or(mask, Ordering::Relaxed) } else { self.outputs[expander].fetch_and(!mask, Ordering::Relaxed) };
+        if (previous & mask != 0) != on {
+            self.changed.signal(());
+        }
+    }
+
+    pub fn outputs(&self, expander: usize) -> u16 {
+        self.outputs[expander].load(Ordering::Relaxed)
+    }
+
+    /// Waits until an output changed since the last call.
+    pub async fn changed(&self) {
+        self.changed.wait().await
+    }
+}
+
+impl Default for ExpanderLink {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Valve on an expander pin, switched once the bus task writes the expander.
+pub struct ExpanderValve<'a> {
+    pub link: &'a ExpanderLink,
+    pub expander: usize,
+    pub pin: u8,
+}
+
+impl PumpDriver for ExpanderValve<'_> {
+    fn enable(&mut self) {}
+
+    fn disable(&mut self) {}
+
+    fn set_duty(&mut self, percent: f64) {
+        self.link.set(self.expander, self.pin, percent > 0.0);
+    }
+}
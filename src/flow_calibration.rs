@@ -0,0 +1,107 @@
+use embassy_time::Duration;
+
+/// Points kept per pump, enough to follow the nonlinear low duty range of a small DC pump.
+pub const MAX_FLOW_POINTS: usize = 6;
+
+/// Flow of a pump at one duty.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FlowPoint {
+    /// Duty in percent.
+    pub duty: u8,
+    pub ml_per_s: f64,
+}
+
+impl FlowPoint {
+    /// Point from a timed run at `duty` that filled a measuring cup with `ml`.
+    pub fn measured(duty: u8, run: Duration, ml: f64) -> Option<Self> {
+        let seconds = run.as_millis() as f64 / 1000.0;
+        (seconds > 0.0).then(|| Self { duty, ml_per_s: ml / seconds })
+    }
+}
+
+/// Flow rate over duty of one pump, interpolated linearly between measured points.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FlowCalibration {
+    /// Sorted by duty, without duplicates.
+    points: [FlowPoint; MAX_FLOW_POINTS],
+    len: usize,
+}
+
+impl FlowCalibration {
+    pub fn new(points: &[FlowPoint]) -> Self {
+        let mut calibration = Self { points: [FlowPoint { duty: 0, ml_per_s: 0.0 }; MAX_FLOW_POINTS], len: 0 };
+        for point in points {
+            calibration.record(*point);
+        }
+        calibration
+    }
+
+    pub fn points(&self) -> &[FlowPoint] {
+        &self.points[..self.len]
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Adds a measurement, replacing an earlier one at the same duty or, once full, the closest one.
+    /// Returns `false` for measurements that can not be used.
+    pub fn record(&mut self, point: FlowPoint) -> bool {
+        if point.duty == 0 || point.duty > 100 || !point.ml_per_s.is_finite() || point.ml_per_s <= 0.0 {
+            return false;
+        }
+        let index = self.points().partition_point(|known| known.duty < point.duty);
+        if index < self.len && self.points[index].duty == point.duty {
+            self.points[index] = point;
+        } else if self.len < MAX_FLOW_POINTS {
+            self.points.copy_within(index..self.len, index + 1);
+            self.points[index] = point;
+            self.len += 1;
+        } else {
+            let closest = if index == self.len || (index > 0 && point.duty - self.points[index - 1].duty < self.points[index].duty - point.duty) { index - 1 } else { index };
+            self.points[closest] = point;
+        }
+        true
+    }
+
+    /// Flow in ml/s at `duty` percent, extrapolated towards zero flow at zero duty below the lowest
+    /// point and proportionally above the highest one.
+    pub fn flow_at(&self, duty: u8) -> Option<f64> {
+        let points = self.points();
+        let (first, last) = (points.first()?, points.last()?);
+        let duty = duty.min(100) as f64;
+        if duty <= first.duty as f64 {
+            return Some(first.ml_per_s * duty / first.duty as f64);
+        }
+        if duty >= last.duty as f64 {
+            return Some(last.ml_per_s * duty / last.duty as f64);
+        }
+        points.windows(2).find(|pair| duty <= pair[1].duty as f64).map(|pair| {
+            let (low, high) = (pair[0], pair[1]);
+            let t = (duty - low.duty as f64) / (high.duty - low.duty) as f64;
+            low.ml_per_s + t * (high.ml_per_s - low.ml_per_s)
+        })
+    }
+
+    /// Volume in ml delivered by running at `duty` for `runtime`, 0 without a calibration.
+    pub fn volume(&self, duty: u8, runtime: Duration) -> f64 {
+        self.flow_at(duty).unwrap_or(0.0) * runtime.as_millis() as f64 / 1000.0
+    }
+
+    /// Duty and run time to deliver `ml`, at `duty` unless that would take longer than `max_run`,
+    /// then at the lowest duty that fits.
+    pub fn plan(&self, ml: f64, duty: u8, max_run: Duration) -> Option<(u8, Duration)> {
+        if ml.is_nan() || ml <= 0.0 {
+            return None;
+        }
+        let run_at = |duty: u8| {
+            let flow = self.flow_at(duty).filter(|flow| *flow > 0.0)?;
+            Some(Duration::from_millis((ml / flow * 1000.0) as u64))
+        };
+        let duty = duty.clamp(1, 100);
+        (duty..=100)
+            .filter_map(|duty| run_at(duty).map(|run| (duty, run)))
+            .find(|(_, run)| *run <= max_run)
+            .or_else(|| run_at(100).map(|run| (100, run)))
+    }
+}
@@ -0,0 +1,46 @@
+/// Counts the water passing a flow sensor.
+pub trait FlowMeter {
+    /// Volume in ml that passed since the previous call.
+    fn take_volume(&mut self) -> f64;
+}
+
+#[cfg(feature = "simulation")]
+pub use mock::MockFlowMeter;
+
+#[cfg(feature = "simulation")]
+mod mock {
+    use super::FlowMeter;
+
+    /// Hands out the volume added by the test since it was last read.
+    pub struct MockFlowMeter {
+        pending_ml: f64,
+        total_ml: f64,
+    }
+
+    impl MockFlowMeter {
+        pub fn new() -> Self {
+            Self { pending_ml: 0.0, total_ml: 0.0 }
+        }
+
+        pub fn add(&mut self, ml: f64) {
+            self.pending_ml += ml;
+            self.total_ml += ml;
+        }
+
+        pub fn total(&self) -> f64 {
+            self.total_ml
+        }
+    }
+
+    impl Default for MockFlowMeter {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl FlowMeter for MockFlowMeter {
+        fn take_volume(&mut self) -> f64 {
+            core::mem::take(&mut self.pending_ml)
+        }
+    }
+}
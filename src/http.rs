@@ -0,0 +1,197 @@
+use core::{fmt::Write as _, ops::Range};
+
+use embedded_io_async::{Read, Write};
+use heapless::String;
+use httparse::Status;
+
+/// Response headers parsed, further ones fail the response.
+const MAX_HEADERS: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Method {
+    Get,
+    Post,
+}
+
+impl Method {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Get => "GET",
+            Self::Post => "POST",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum HttpError<E> {
+    Io(E),
+    /// The server closed the connection before the response was complete.
+    Closed,
+    /// The response does not fit the buffer.
+    TooLarge,
+    Parse(httparse::Error),
+    InvalidContentLength,
+    InvalidChunk,
+}
+
+/// Extra request header, name and value.
+pub type Header<'a> = (&'a str, &'a [u8]);
+
+/// How the end of a response body is found.
+enum Framing {
+    Empty,
+    Length(usize),
+    Chunked,
+    /// Everything up to the end of the connection.
+    Close,
+}
+
+impl Framing {
+    fn of<E>(response: &httparse::Response<'_, '_>) -> Result<Self, HttpError<E>> {
+        let status = response.code.unwrap_or(0);
+        if (100..200).contains(&status) || status == 204 || status == 304 {
+            return Ok(Self::Empty);
+        }
+        let header = |name: &str| response.headers.iter().find(|header| header.name.eq_ignore_ascii_case(name)).map(|header| header.value);
+        if header("Transfer-Encoding").is_some_and(|value| value.windows(7).any(|word| word.eq_ignore_ascii_case(b"chunked"))) {
+            return Ok(Self::Chunked);
+        }
+        match header("Content-Length") {
+            Some(value) => core::str::from_utf8(value).ok().and_then(|value| value.trim().parse().ok()).map(Self::Length).ok_or(HttpError::InvalidContentLength),
+            None => Ok(Self::Close),
+        }
+    }
+}
+
+/// A complete response, borrowed from the buffer of the client.
+pub struct Response<'b> {
+    pub status: u16,
+    head: &'b [u8],
+    pub body: &'b [u8],
+}
+
+impl<'b> Response<'b> {
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    /// Value of the first header called `name`, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&'b [u8]> {
+        let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+        let mut response = httparse::Response::new(&mut headers);
+        response.parse(self.head).ok()?;
+        response.headers.iter().find(|header| header.name.eq_ignore_ascii_case(name)).map(|header| header.value)
+    }
+}
+
+/// HTTP/1.1 client on one connection, requests are sent one after the other and each response is
+/// read completely into `buffer` before the next request.
+pub struct HttpClient<'a, T> {
+    io: T,
+    host: &'a str,
+    buffer: &'a mut [u8],
+}
+
+impl<'a, T: Read + Write> HttpClient<'a, T> {
+    pub fn new(io: T, host: &'a str, buffer: &'a mut [u8]) -> Self {
+        Self { io, host, buffer }
+    }
+
+    pub async fn request(&mut self, method: Method, path: &str, headers: &[Header<'_>], body: &[u8]) -> Result<Response<'_>, HttpError<T::Error>> {
+        self.write_head(method, path, headers, body.len()).await.map_err(HttpError::Io)?;
+        self.io.write_all(body).await.map_err(HttpError::Io)?;
+        self.io.flush().await.map_err(HttpError::Io)?;
+        self.read_response().await
+    }
+
+    async fn write_head(&mut self, method: Method, path: &str, headers: &[Header<'_>], content_length: usize) -> Result<(), T::Error> {
+        let mut length: String<20> = String::new();
+        let _ = write!(length, "{}", content_length);
+        let line = [method.as_str().as_bytes(), b" ", path.as_bytes(), b" HTTP/1.1\r\nHost: ", self.host.as_bytes(), b"\r\nUser-Agent: Dewy\r\nContent-Length: ", length.as_bytes(), b"\r\n"];
+        for part in line {
+            self.io.write_all(part).await?;
+        }
+        for (name, value) in headers {
+            for part in [name.as_bytes(), b": ", value, b"\r\n"] {
+                self.io.write_all(part).await?;
+            }
+        }
+        self.io.write_all(b"\r\n").await
+    }
+
+    async fn read_response(&mut self) -> Result<Response<'_>, HttpError<T::Error>> {
+        let mut filled = 0;
+        let (head_len, status, framing) = loop {
+            filled += self.fill(filled).await?;
+            let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+            let mut response = httparse::Response::new(&mut headers);
+            if let Status::Complete(head_len) = response.parse(&self.buffer[..filled]).map_err(HttpError::Parse)? {
+                break (head_len, response.code.unwrap_or(0), Framing::of(&response)?);
+            }
+        };
+        let body = match framing {
+            Framing::Empty => head_len..head_len,
+            Framing::Length(length) => {
+                let end = head_len.checked_add(length).filter(|end| *end <= self.buffer.len()).ok_or(HttpError::TooLarge)?;
+                while filled < end {
+                    filled += self.fill(filled).await?;
+                }
+                head_len..end
+            },
+            Framing::Chunked => self.read_chunked(head_len, filled).await?,
+            Framing::Close => {
+                loop {
+                    match self.fill(filled).await {
+                        Ok(read) => filled += read,
+                        Err(HttpError::Closed) => break,
+                        Err(err) => return Err(err),
+                    }
+                }
+                head_len..filled
+            },
+        };
+        Ok(Response { status, head: &self.buffer[..head_len], body: &self.buffer[body] })
+    }
+
+    /// Decodes a chunked body in place, returns where it ended up in the buffer.
+    async fn read_chunked(&mut self, start: usize, mut filled: usize) -> Result<Range<usize>, HttpError<T::Error>> {
+        // Decoded data ends at `end`, the undecoded data starts there too once a chunk was moved down.
+        let mut end = start;
+        loop {
+            let (size_len, size) = loop {
+                match httparse::parse_chunk_size(&self.buffer[end..filled]).map_err(|_| HttpError::InvalidChunk)? {
+                    Status::Complete(parsed) => break parsed,
+                    Status::Partial => filled += self.fill(filled).await?,
+                }
+            };
+            let data = end + size_len;
+            let size = usize::try_from(size).map_err(|_| HttpError::TooLarge)?;
+            let chunk_end = data.checked_add(size).and_then(|data_end| data_end.checked_add(2)).ok_or(HttpError::TooLarge)?;
+            while filled < chunk_end {
+                filled += self.fill(filled).await?;
+            }
+            // Trailers are not supported, the last chunk is followed by an empty line.
+            if &self.buffer[chunk_end - 2..chunk_end] != b"\r\n" {
+                return Err(HttpError::InvalidChunk);
+            }
+            if size == 0 {
+                return Ok(start..end);
+            }
+            self.buffer.copy_within(data..data + size, end);
+            self.buffer.copy_within(chunk_end..filled, end + size);
+            filled -= chunk_end - (end + size);
+            end += size;
+        }
+    }
+
+    /// Reads more of the response after the first `filled` bytes of the buffer.
+    async fn fill(&mut self, filled: usize) -> Result<usize, HttpError<T::Error>> {
+        if filled == self.buffer.len() {
+            return Err(HttpError::TooLarge);
+        }
+        match self.io.read(&mut self.buffer[filled..]).await.map_err(HttpError::Io)? {
+            0 => Err(HttpError::Closed),
+            read => Ok(read),
+        }
+    }
+}
@@ -4,29 +4,115 @@
 
 
 
+use core::cell::RefCell;
+
 use embassy_executor::Spawner;
 use embassy_futures::select;
 use embassy_net::{Stack, StackResources};
 use embassy_time::{Duration, Instant, Timer};
 use esp_backtrace as _;
+use esp_storage::FlashStorage;
 use esp32_hal as hal;
 use esp_wifi::{wifi::{WifiController, WifiDevice, WifiEvent, WifiStaDevice, WifiState}, EspWifiInitFor};
-use hal::{clock::ClockControl, embassy, gpio::{GpioPin, Output, PushPull}, i2c::I2C, peripherals::{Peripherals, I2C0}, prelude::*, timer::TimerGroup};
-use pump_control::PumpController;
+use hal::{clock::ClockControl, embassy, gpio::{AnyPin, Floating, GpioPin, Input, Output, PullUp, PushPull}, i2c::I2C, peripherals::{Peripherals, I2C0, I2C1, MCPWM0, MCPWM1}, prelude::*, timer::TimerGroup};
+use heapless::Vec;
+use pump_control::{PumpOutput, ValveControl};
+use pump_hal::PumpController;
+use persistence::{PersistQueue, PersistRequest, StoredCalibration};
 use static_cell::make_static;
 use embedded_svc::wifi::Wifi;
-use embassy_sync::{blocking_mutex::raw::NoopRawMutex, channel::{Channel, Sender}, signal::Signal};
-use log::{error, info};
+use embedded_hal_async::digital::Wait;
+use embassy_sync::{blocking_mutex::raw::NoopRawMutex, channel::Channel, signal::Signal};
+use log::{error, info, warn};
 
 mod seesaw;
 mod networking;
+mod http;
+mod auth;
+mod upload;
 mod pump_control;
+mod pump_driver;
+mod pump_hal;
 mod soil_estimator;
-
-
-const SOIL_SENSOR_ADDR: u8 = 0x36;
-
-
+mod sensor_fault;
+mod events;
+mod drying;
+mod calibration;
+mod plant_profile;
+mod watering;
+mod persistence;
+mod zone;
+mod statistics;
+mod temperature_guard;
+mod pump_safety;
+mod pump_command;
+mod flow_calibration;
+mod ramp;
+mod flow_meter;
+mod tank;
+mod tank_sensors;
+mod motor_current;
+mod expander;
+mod actuator;
+mod manifold;
+mod manual_override;
+mod watering_history;
+mod stepper;
+mod nutrient;
+mod server_command;
+
+/// Choices of the configuration tables below, public so the ones a build does not pick stay supported.
+pub use expander::ExpanderKind;
+pub use nutrient::NutrientSchedule;
+pub use ramp::RampProfile;
+pub use zone::{Actuator, McpwmUnit, NutrientPump, Operator, PwmPin, TankSensor, ValveOutput};
+
+/// Pumps are wired to pins A and B of the MCPWM0 operators: operator0 GPIO21/GPIO13, operator1 GPIO22/GPIO14,
+/// operator2 GPIO23/GPIO15, and to pin A of the MCPWM1 operators: GPIO2, GPIO5 and GPIO12. A stepper pump takes
+/// the GPIO of one of these outputs as its step pin.
+/// Valves are wired to GPIO25 and GPIO26, flow sensors to GPIO27 and GPIO32, pump current shunts to GPIO35, GPIO36 and GPIO39.
+/// Seesaw soil probes can be strapped to addresses 0x36 to 0x39.
+const ZONES: [zone::ZoneConfig; 1] = [
+    zone::ZoneConfig {
+        name: "main",
+        sensor_address: 0x36,
+        profile: "houseplant",
+        actuator: zone::Actuator::Pump(zone::PwmOutput::new(zone::McpwmUnit::Mcpwm0, zone::Operator::Operator0, zone::PwmPin::A)),
+        pump_limits: pump_safety::SafetyLimits {
+            max_on_time: Duration::from_secs(30),
+            max_daily_runtime: Duration::from_secs(5 * 60),
+            min_cooldown: Duration::from_secs(60),
+            no_flow_timeout: Duration::from_secs(5),
+        },
+        pump_ramp: ramp::RampConfig {
+            up: ramp::RampProfile::SCurve { full_scale: Duration::from_millis(1500) },
+            down: ramp::RampProfile::Slew { percent_per_s: 400.0 },
+        },
+        flow_sensor: None,
+        current_sense: None,
+        pump_flow: &[flow_calibration::FlowPoint { duty: 100, ml_per_s: 20.0 }],
+        prime_time: Duration::from_secs(8),
+        manual_calibration: None,
+        nutrient: None,
+    },
+];
+
+/// I2C port expanders switching valves, on the bus of the soil probes, e.g.
+/// `ExpanderConfig { kind: ExpanderKind::Mcp23017, address: 0x20, active_low: false }`.
+const EXPANDERS: [expander::ExpanderConfig; 0] = [];
+
+/// Reservoir of all pumps and its level sensor, `None` without a tank sensor. A float switch is wired
+/// to GPIO33, an ultrasonic sensor to GPIO4 (trigger) and GPIO34 (echo), a Seesaw probe to the
+/// second I2C bus on GPIO16 (SDA) and GPIO17 (SCL).
+const TANK: Option<(tank::TankConfig, zone::TankSensor)> = Some((
+    tank::TankConfig {
+        capacity_ml: 10_000.0,
+        low_ml: 2_000.0,
+        empty_level: 0.05,
+        sample_period: Duration::from_secs(60),
+    },
+    zone::TankSensor::FloatSwitch { wet_high: true },
+));
 
 #[main]
 async fn main(spawner: Spawner) {
@@ -76,50 +162,419 @@ async fn main(spawner: Spawner) {
         clocks,
     );
 
-    let soil_mesurement: &mut Channel::<NoopRawMutex, seesaw::Messurement, 64> = make_static!(Channel::new());
-    let messurement_log: &mut Channel::<NoopRawMutex, soil_estimator::FilteredMessurement, 64> = make_static!(Channel::new());
-    let pump_target = make_static!(Signal::new());
-    let pump_controler = pump_control::PumpController::new(peripherals.MCPWM0, clocks, io.pins.gpio21.into_push_pull_output(), pump_target).unwrap();
+    let messurement_log: &'static Channel::<NoopRawMutex, statistics::AggregateRecord, 64> = make_static!(Channel::new());
+    let event_log: &'static Channel::<NoopRawMutex, events::Event, { events::EVENT_QUEUE }> = make_static!(Channel::new());
+    let persist_queue: &'static PersistQueue = make_static!(Channel::new());
+    let tank_link: &'static tank::TankLink = make_static!(tank::TankLink::new());
+    let watering_log: &'static watering_history::WateringLog = make_static!(watering_history::WateringLog::new());
+    let zone_io: &'static [zone::ZoneIo; zone::MAX_ZONES] = make_static!(core::array::from_fn(|_| zone::ZoneIo::new()));
+    let server_commands: &'static server_command::ServerCommandQueue = make_static!(Channel::new());
+    let mut store = persistence::Store::new(FlashStorage::new(), persistence::DEFAULT_OFFSET);
+
+    let expander_link: &'static expander::ExpanderLink = make_static!(expander::ExpanderLink::new());
+    let manifold_channels: &'static [pump_command::PumpChannel; zone::MAX_ZONES] = make_static!(core::array::from_fn(|_| pump_command::PumpChannel::new()));
+    let registry = match actuator::ActuatorRegistry::new(&ZONES, &EXPANDERS) {
+        Ok(registry) => registry,
+        Err(err) => panic!("Invalid actuator configuration {:?}", err),
+    };
+
+    let mut pwm_pins = [
+        [
+            [Some(io.pins.gpio21.into_push_pull_output().degrade()), Some(io.pins.gpio13.into_push_pull_output().degrade())],
+            [Some(io.pins.gpio22.into_push_pull_output().degrade()), Some(io.pins.gpio14.into_push_pull_output().degrade())],
+            [Some(io.pins.gpio23.into_push_pull_output().degrade()), Some(io.pins.gpio15.into_push_pull_output().degrade())],
+        ],
+        [
+            [Some(io.pins.gpio2.into_push_pull_output().degrade()), None],
+            [Some(io.pins.gpio5.into_push_pull_output().degrade()), None],
+            [Some(io.pins.gpio12.into_push_pull_output().degrade()), None],
+        ],
+    ];
+    let mut valve_pins: [_; zone::VALVE_PINS] = [
+        Some(io.pins.gpio25.into_push_pull_output().degrade()),
+        Some(io.pins.gpio26.into_push_pull_output().degrade()),
+    ];
+    let pcnt = hal::pcnt::PCNT::new(peripherals.PCNT);
+    let mut flow_sensor_inputs: [_; zone::FLOW_SENSOR_INPUTS] = [
+        Some((pcnt.get_unit(hal::pcnt::unit::Number::Unit0), io.pins.gpio27.into_pull_up_input().degrade())),
+        Some((pcnt.get_unit(hal::pcnt::unit::Number::Unit1), io.pins.gpio32.into_pull_up_input().degrade())),
+    ];
+    let analog = peripherals.SENS.split();
+    let mut adc_config = hal::analog::adc::AdcConfig::new();
+    let mut shunt_inputs: [Option<&'static mut dyn pump_hal::ShuntInput>; zone::SHUNT_INPUTS] = [
+        Some(make_static!(adc_config.enable_pin(io.pins.gpio35.into_analog(), hal::analog::adc::Attenuation::Attenuation11dB))),
+        Some(make_static!(adc_config.enable_pin(io.pins.gpio36.into_analog(), hal::analog::adc::Attenuation::Attenuation11dB))),
+        Some(make_static!(adc_config.enable_pin(io.pins.gpio39.into_analog(), hal::analog::adc::Attenuation::Attenuation11dB))),
+    ];
+    let adc: &'static RefCell<hal::analog::adc::ADC<'static, hal::analog::ADC1>> = make_static!(RefCell::new(hal::analog::adc::ADC::new(analog.adc1, adc_config)));
+    let mut motor_outputs: [pump_hal::MotorOutputs<'static, AnyPin<Output<PushPull>>>; 2] = Default::default();
+    let mut soil_sensors = Vec::new();
+    let mut flows: Vec<flow_calibration::FlowCalibration, { zone::MAX_ZONES }> = Vec::new();
+
+    for (index, (config, io)) in ZONES.iter().zip(zone_io.iter()).enumerate() {
+        let Some(profile) = config.plant_profile() else {
+            panic!("Zone {} has no plant profile named '{}'", index, config.profile);
+        };
+        let calibration = restore_calibration(&mut store, index, config, profile, persist_queue);
+        let fault_detector = sensor_fault::SensorFaultDetector::new(Default::default());
+        let channels = soil_estimator::EstimatorChannels { messurements: io.messurements.receiver(), pump: &io.pump, aggregates: messurement_log.sender(), events: event_log.sender(), persist: persist_queue };
+        let mut estimator = soil_estimator::SoilEstimator::new(index, channels, fault_detector, profile, calibration);
+        match store.load_estimator(index) {
+            Ok(snapshot) => {
+                info!("Zone {} restored estimator {:?}", index, snapshot);
+                estimator.restore(&snapshot, Instant::now());
+            },
+            Err(err) => warn!("No estimator snapshot for zone {}: {:?}", index, err),
+        }
+        spawner.spawn(estimator_task(estimator, &io.estimator)).unwrap();
+        spawner.spawn(override_task(index, io, config.prime_time, event_log.sender(), watering_log, config.nutrient.is_some())).unwrap();
+
+        let sensor = seesaw::SoilSensor::new(config.sensor_address, io.messurements.sender());
+        if soil_sensors.push((sensor, profile.sample_period)).is_err() {
+            panic!("More than {} zones configured", zone::MAX_ZONES);
+        }
+
+        let _ = flows.push(restore_flow(&mut store, index, config));
+        info!("Zone {} '{}' waters a {} with {:?}", index, config.name, profile.name, config.actuator);
+    }
 
-    let estimator = soil_estimator::SoilEstimator::new(soil_mesurement.receiver(), pump_target, messurement_log.sender());
+    let mut take_valve = |valve: zone::ValveOutput| match valve {
+        zone::ValveOutput::Gpio(index) => pump_hal::Valve::Gpio(pump_hal::GpioSwitch(valve_pins[index].take().expect("Valve pin not wired"))),
+        zone::ValveOutput::Expander { expander, pin } => pump_hal::Valve::Expander(expander::ExpanderValve { link: expander_link, expander, pin }),
+    };
+    for &(index, valve) in registry.valves() {
+        spawner.spawn(valve_task(take_valve(valve), ValveControl {
+            zone: index,
+            channel: &zone_io[index].actuator,
+            reset: &zone_io[index].pump_reset,
+            limits: ZONES[index].pump_limits,
+            flow: flows[index],
+            events: event_log.sender(),
+            persist: persist_queue,
+            tank: TANK.is_some().then_some(tank_link),
+        })).unwrap();
+    }
+    for &(index, step, profile) in registry.steppers() {
+        let config = &ZONES[index];
+        let io = &zone_io[index];
+        let pin = pwm_pins[step.unit as usize][step.operator as usize][step.pin as usize].take().expect("PWM output not wired");
+        spawner.spawn(stepper_task(pump_hal::StepPin(pin), stepper::StepperOutput {
+            zone: index,
+            channel: &io.actuator,
+            reset: &io.pump_reset,
+            limits: config.pump_limits,
+            profile,
+            flow: flows[index],
+            events: event_log.sender(),
+            persist: persist_queue,
+            tank: TANK.is_some().then_some(tank_link),
+        })).unwrap();
+    }
+    for (pump, manifold_channel) in registry.pumps().iter().zip(manifold_channels.iter()) {
+        let index = pump.owner();
+        let config = &ZONES[index];
+        let io = &zone_io[index];
+        let channel = if pump.is_manifold() {
+            let zones = pump.zones.iter()
+                .filter_map(|&(zone, valve)| valve.map(|valve| manifold::ManifoldZone { zone, channel: &zone_io[zone].actuator, valve: take_valve(valve) }))
+                .collect();
+            spawner.spawn(manifold_task(manifold_channel, zones, config.pump_ramp.down.duration(100.0), config.pump_limits.min_cooldown)).unwrap();
+            manifold_channel
+        } else {
+            &io.actuator
+        };
+        let output = pump.output;
+        let pin = pwm_pins[output.unit as usize][output.operator as usize][output.pin as usize].take().expect("PWM output not wired");
+        let meter = config.flow_sensor.and_then(|sensor| {
+            let (unit, input) = flow_sensor_inputs[sensor.input].take().expect("Flow sensor assigned to two zones");
+            pump_hal::PcntFlowMeter::new(unit, input, sensor.pulses_per_litre)
+                .map_err(|err| error!("Zone {} flow sensor unavailable: {:?}", index, err))
+                .ok()
+        });
+        let shunt = config.current_sense.map(|sense| pump_hal::Shunt {
+            adc,
+            input: shunt_inputs[sense.input].take().expect("Current sense assigned to two zones"),
+            ma_per_count: sense.ma_per_count,
+        });
+        motor_outputs[output.unit as usize][output.operator as usize][output.pin as usize] = Some(pump_hal::MotorOutput { pin, meter, shunt, output: PumpOutput {
+            zone: index,
+            channel,
+            reset: &io.pump_reset,
+            limits: config.pump_limits,
+            ramp: config.pump_ramp,
+            flow: flows[index],
+            events: event_log.sender(),
+            persist: persist_queue,
+            tank: TANK.is_some().then_some(tank_link),
+            current: config.current_sense.map(|sense| sense.limits),
+        }});
+    }
+    for &(index, pump) in registry.nutrients() {
+        let Some(config) = ZONES[index].nutrient else {
+            continue;
+        };
+        let io = &zone_io[index];
+        let flow = flow_calibration::FlowCalibration::new(config.flow);
+        match pump {
+            zone::NutrientPump::Pump(output) => {
+                let pin = pwm_pins[output.unit as usize][output.operator as usize][output.pin as usize].take().expect("PWM output not wired");
+                motor_outputs[output.unit as usize][output.operator as usize][output.pin as usize] = Some(pump_hal::MotorOutput { pin, meter: None, shunt: None, output: PumpOutput {
+                    zone: index,
+                    channel: &io.nutrient,
+                    reset: &io.nutrient_reset,
+                    limits: config.pump_limits,
+                    ramp: Default::default(),
+                    flow,
+                    events: event_log.sender(),
+                    persist: persist_queue,
+                    tank: None,
+                    current: None,
+                }});
+            },
+            zone::NutrientPump::Stepper { step, profile } => {
+                let pin = pwm_pins[step.unit as usize][step.operator as usize][step.pin as usize].take().expect("PWM output not wired");
+                spawner.spawn(stepper_task(pump_hal::StepPin(pin), stepper::StepperOutput {
+                    zone: index,
+                    channel: &io.nutrient,
+                    reset: &io.nutrient_reset,
+                    limits: config.pump_limits,
+                    profile,
+                    flow,
+                    events: event_log.sender(),
+                    persist: persist_queue,
+                    tank: None,
+                })).unwrap();
+            },
+        }
+        let doser = nutrient::NutrientDoser::new(config.schedule, config.limits, Instant::now());
+        spawner.spawn(nutrient_task(index, doser, config.duty, io, watering_log, event_log.sender())).unwrap();
+    }
+    let [mcpwm0_outputs, mcpwm1_outputs] = motor_outputs;
+    let pump_controler0 = PumpController::new(peripherals.MCPWM0, clocks, mcpwm0_outputs).unwrap();
+    let pump_controler1 = PumpController::new(peripherals.MCPWM1, clocks, mcpwm1_outputs).unwrap();
 
     let upload_sources = networking::UploadDataSource {
         messurements: messurement_log.receiver(),
+        events: event_log.receiver(),
     };
 
     spawner.spawn(connection_task(controller)).unwrap();
     spawner.spawn(net_task(&stack)).unwrap();
-    spawner.spawn(net_app_task(&stack, upload_sources, rng)).unwrap();
-    spawner.spawn(i2c_task(i2c, soil_mesurement.sender())).unwrap();
-    spawner.spawn(pump_task(pump_controler)).unwrap();
-    spawner.spawn(estimator_task(estimator)).unwrap();
+    spawner.spawn(net_app_task(&stack, upload_sources, rng, server_commands, watering_log)).unwrap();
+    spawner.spawn(server_command_task(server_commands, &zone_io[..ZONES.len()])).unwrap();
+    spawner.spawn(i2c_task(i2c, soil_sensors, expander_link)).unwrap();
+    spawner.spawn(pump0_task(pump_controler0)).unwrap();
+    spawner.spawn(pump1_task(pump_controler1)).unwrap();
+    spawner.spawn(persistence_task(store, persist_queue)).unwrap();
+    match TANK {
+        Some((config, zone::TankSensor::FloatSwitch { wet_high })) => {
+            let sensor = tank_sensors::FloatSwitch::new(io.pins.gpio33.into_pull_up_input(), wet_high);
+            spawner.spawn(float_switch_task(sensor, config, tank_link, event_log.sender())).unwrap();
+        },
+        Some((config, zone::TankSensor::Ultrasonic { empty_mm, full_mm })) => {
+            let sensor = tank_sensors::UltrasonicLevel::new(io.pins.gpio4.into_push_pull_output(), io.pins.gpio34.into_floating_input(), empty_mm, full_mm);
+            spawner.spawn(ultrasonic_task(sensor, config, tank_link, event_log.sender())).unwrap();
+        },
+        Some((config, zone::TankSensor::SeesawProbe { address, channel, empty_raw, full_raw })) => {
+            let i2c = I2C::new(peripherals.I2C1, io.pins.gpio16, io.pins.gpio17, 100u32.kHz(), clocks);
+            let sensor = tank_sensors::SeesawLevelProbe::new(seesaw::I2CInterfaces::new(i2c), address, channel, empty_raw, full_raw);
+            spawner.spawn(seesaw_probe_task(sensor, config, tank_link, event_log.sender())).unwrap();
+        },
+        None => info!("No tank level sensor, pumps are not protected against running dry"),
+    }
+    spawner.spawn(maintenance_button_task(io.pins.gpio0.into_pull_up_input(), zone_io)).unwrap();
     
     loop {
         Timer::after(Duration::from_millis(500)).await;
     }
 }
 
+/// Stored calibration of a zone, learned or set by the server, unless the configuration pins it or
+/// nothing valid was stored yet.
+fn restore_calibration(store: &mut persistence::Store<FlashStorage>, zone: usize, config: &zone::ZoneConfig, profile: &plant_profile::PlantProfile, persist: &PersistQueue) -> StoredCalibration {
+    let stored = store.load_calibration(zone);
+    let calibration = match (config.manual_calibration, &stored) {
+        (Some(calibration), _) => StoredCalibration { calibration, mode: calibration::CalibrationMode::Manual },
+        (None, Ok(stored)) => *stored,
+        (None, stored) => {
+            if let Err(err) = stored {
+                warn!("No stored calibration for zone {}, using profile defaults: {:?}", zone, err);
+            }
+            StoredCalibration { calibration: profile.calibration, mode: calibration::CalibrationMode::Learning }
+        },
+    };
+    info!("Zone {} soil probe calibration {:?}", zone, calibration);
+    if stored.ok() != Some(calibration) {
+        if let Err(err) = persist.try_send(PersistRequest::Calibration { zone, calibration }) {
+            error!("Failed to queue calibration for storage {:?}", err);
+        }
+    }
+    calibration
+}
+
+/// Flow calibration measured on this device, or the nominal flow from the configuration.
+fn restore_flow(store: &mut persistence::Store<FlashStorage>, zone: usize, config: &zone::ZoneConfig) -> flow_calibration::FlowCalibration {
+    let flow = match store.load_flow(zone) {
+        Ok(flow) => flow,
+        Err(err) => {
+            warn!("No stored flow calibration for zone {}, using configured flow: {:?}", zone, err);
+            flow_calibration::FlowCalibration::new(config.pump_flow)
+        },
+    };
+    info!("Zone {} flow calibration {:?}", zone, flow.points());
+    flow
+}
+
+#[embassy_executor::task(pool_size = zone::MAX_ZONES)]
+async fn estimator_task(mut estimator: soil_estimator::SoilEstimator<'static, { zone::MESSUREMENT_QUEUE }, 64, 16>, inputs: &'static soil_estimator::EstimatorInputs) {
+    estimator.run(inputs).await;
+}
+
 #[embassy_executor::task]
-async fn estimator_task(mut estimator: soil_estimator::SoilEstimator<'static, 64, 64>) {
+async fn persistence_task(mut store: persistence::Store<FlashStorage>, queue: &'static PersistQueue) {
     loop {
-        estimator.update_estimator().await;
+        let request = queue.receive().await;
+        if let Err(err) = store.save(&request) {
+            error!("Failed to persist {:?} for {:?}", request, err);
+        }
     }
 }
 
 #[embassy_executor::task]
-async fn pump_task(controler: PumpController<'static, GpioPin<Output<PushPull>, 21>>) {
+async fn pump0_task(controler: PumpController<'static, MCPWM0, AnyPin<Output<PushPull>>>) {
     controler.run_motor_control().await;
 }
 
 #[embassy_executor::task]
-async fn i2c_task(i2c: I2C<'static, I2C0>, soil_messurement: Sender<'static, NoopRawMutex, seesaw::Messurement, 64>) {
+async fn pump1_task(controler: PumpController<'static, MCPWM1, AnyPin<Output<PushPull>>>) {
+    controler.run_motor_control().await;
+}
+
+#[embassy_executor::task(pool_size = 2)]
+async fn manifold_task(pump: &'static pump_command::PumpChannel, mut zones: Vec<manifold::ManifoldZone<'static, pump_hal::Valve<'static, AnyPin<Output<PushPull>>>>, { zone::MAX_ZONES }>, ramp_down: Duration, cooldown: Duration) {
+    manifold::run_manifold(pump, &mut zones, ramp_down, cooldown).await;
+}
+
+/// Holding the BOOT button this long primes the pumps instead of resetting faults.
+const PRIME_HOLD: Duration = Duration::from_secs(3);
+
+/// A press of the BOOT button clears latched pump faults of all zones, holding it primes the lines
+/// of all zones.
+#[embassy_executor::task]
+async fn maintenance_button_task(mut button: GpioPin<Input<PullUp>, 0>, zone_io: &'static [zone::ZoneIo; zone::MAX_ZONES]) {
+    loop {
+        if let Err(err) = button.wait_for_falling_edge().await {
+            error!("Maintenance button failed {:?}", err);
+            return;
+        }
+        match select::select(button.wait_for_rising_edge(), Timer::after(PRIME_HOLD)).await {
+            select::Either::First(_) => {
+                info!("Resetting pump faults");
+                for io in zone_io {
+                    io.pump_reset.signal(());
+                    io.nutrient_reset.signal(());
+                }
+            },
+            select::Either::Second(()) => {
+                info!("Priming pumps");
+                for (zone, io) in zone_io[..ZONES.len()].iter().enumerate() {
+                    if let Err(err) = io.manual.try_send((manual_override::ManualCommand::Prime, watering_history::RunReason::Manual)) {
+                        warn!("Zone {} has too many manual commands queued, not priming {:?}", zone, err);
+                    }
+                }
+                if let Err(err) = button.wait_for_rising_edge().await {
+                    error!("Maintenance button failed {:?}", err);
+                    return;
+                }
+            },
+        }
+        Timer::after(Duration::from_millis(500)).await;
+    }
+}
+
+#[embassy_executor::task(pool_size = zone::MAX_ZONES)]
+async fn override_task(zone: usize, io: &'static zone::ZoneIo, prime_time: Duration, events: events::EventSender<'static>, watering_log: &'static watering_history::WateringLog, nutrient: bool) {
+    manual_override::run_override(manual_override::OverrideConfig {
+        zone,
+        auto: &io.pump,
+        manual: &io.manual,
+        output: &io.actuator,
+        prime: prime_time,
+        events,
+        history: watering_log,
+        watered: nutrient.then_some(&io.watered),
+    }).await;
+}
+
+#[embassy_executor::task]
+async fn server_command_task(commands: &'static server_command::ServerCommandQueue, zones: &'static [zone::ZoneIo]) {
+    server_command::run_server_commands(commands, zones).await;
+}
+
+#[embassy_executor::task]
+async fn float_switch_task(mut sensor: tank_sensors::FloatSwitch<GpioPin<Input<PullUp>, 33>>, config: tank::TankConfig, link: &'static tank::TankLink, events: events::EventSender<'static>) {
+    tank::run_tank(&mut sensor, config, link, events).await;
+}
+
+#[embassy_executor::task]
+async fn ultrasonic_task(mut sensor: tank_sensors::UltrasonicLevel<GpioPin<Output<PushPull>, 4>, GpioPin<Input<Floating>, 34>>, config: tank::TankConfig, link: &'static tank::TankLink, events: events::EventSender<'static>) {
+    tank::run_tank(&mut sensor, config, link, events).await;
+}
+
+#[embassy_executor::task]
+async fn seesaw_probe_task(mut sensor: tank_sensors::SeesawLevelProbe<I2C<'static, I2C1>>, config: tank::TankConfig, link: &'static tank::TankLink, events: events::EventSender<'static>) {
+    tank::run_tank(&mut sensor, config, link, events).await;
+}
+
+#[embassy_executor::task(pool_size = zone::MAX_ZONES)]
+async fn valve_task(mut valve: pump_hal::Valve<'static, AnyPin<Output<PushPull>>>, control: ValveControl<'static>) {
+    pump_control::run_valve(&mut valve, control).await;
+}
+
+#[embassy_executor::task(pool_size = zone::MAX_ZONES)]
+async fn nutrient_task(zone: usize, doser: nutrient::NutrientDoser, duty: u8, io: &'static zone::ZoneIo, watering_log: &'static watering_history::WateringLog, events: events::EventSender<'static>) {
+    nutrient::run_nutrient(zone, doser, duty, &io.watered, &io.nutrient, watering_log, events).await;
+}
+
+/// Stepper pumps of the zones and of their nutrient channels.
+const STEPPER_TASKS: usize = 2 * zone::MAX_ZONES;
+
+#[embassy_executor::task(pool_size = STEPPER_TASKS)]
+async fn stepper_task(mut driver: pump_hal::StepPin<AnyPin<Output<PushPull>>>, output: stepper::StepperOutput<'static>) {
+    stepper::run_stepper(&mut driver, output).await;
+}
+
+/// Polls the soil probes of all zones on the shared bus, each at the sample period of its plant profile,
+/// and writes the valve outputs to the port expanders on the same bus whenever they change.
+#[embassy_executor::task]
+async fn i2c_task(i2c: I2C<'static, I2C0>, mut soil_sensors: Vec<(seesaw::SoilSensor<'static, NoopRawMutex, { zone::MESSUREMENT_QUEUE }>, Duration), { zone::MAX_ZONES }>, expanders: &'static expander::ExpanderLink) {
     let mut i2c_interface = seesaw::I2CInterfaces::new(i2c);
-    let mut soil_sensor = seesaw::SoilSensor::new(SOIL_SENSOR_ADDR, soil_messurement);
-    let mut run_at = Instant::now();
+    for (index, config) in EXPANDERS.iter().enumerate() {
+        if let Err(err) = config.configure(i2c_interface.bus()).await {
+            error!("Failed to set up port expander {}: {:?}", index, err);
+        }
+    }
+    let start = Instant::now();
+    let mut run_at: Vec<Instant, { zone::MAX_ZONES }> = soil_sensors.iter().map(|_| start).collect();
     loop {
-        soil_sensor.run(&mut i2c_interface).await;
-        run_at += Duration::from_secs(2);
-        Timer::at(run_at).await;
+        let next = run_at.iter().copied().enumerate().min_by_key(|(_, at)| *at);
+        match select::select(Timer::at(next.map_or(Instant::MAX, |(_, at)| at)), expanders.changed()).await {
+            select::Either::First(()) => {
+                let Some((index, _)) = next else {
+                    continue;
+                };
+                let (soil_sensor, sample_period) = &mut soil_sensors[index];
+                soil_sensor.run(&mut i2c_interface).await;
+                run_at[index] += *sample_period;
+            },
+            select::Either::Second(()) => {
+                for (index, config) in EXPANDERS.iter().enumerate() {
+                    if let Err(err) = config.write(i2c_interface.bus(), expanders.outputs(index)).await {
+                        error!("Failed to switch the valves on port expander {}: {:?}", index, err);
+                    }
+                }
+            },
+        }
     }
 }
 
@@ -161,18 +616,26 @@ async fn net_task(stack: &'static Stack<WifiDevice<'static, WifiStaDevice>>) {
 const URL: &str = &"www.mobile-j.de";
 const DNS_TTL: Duration = Duration::from_secs(60 * 60);
 const PORT: u16 = 80;
+const UPLOAD_PATH: &str = "/api/upload";
+/// Identity the uploads are signed with, set at build time like the wifi credentials. The
+/// server keeps the same secret for the device id.
+const DEVICE_KEY: auth::DeviceKey = auth::DeviceKey { id: env!("DEWY_DEVICE_ID"), secret: env!("DEWY_DEVICE_SECRET").as_bytes() };
+/// Pause after a failed upload before the batch is sent again.
+const UPLOAD_RETRY: Duration = Duration::from_secs(60);
 
 #[embassy_executor::task]
-async fn net_app_task(stack: &'static Stack<WifiDevice<'static, WifiStaDevice>>, upload: networking::UploadDataSource, rng: hal::Rng) {
+async fn net_app_task(stack: &'static Stack<WifiDevice<'static, WifiStaDevice>>, upload: networking::UploadDataSource, rng: hal::Rng, commands: &'static server_command::ServerCommandQueue, watering_log: &'static watering_history::WateringLog) {
 
     let mut dns_address = networking::DNSAddress::new(URL, DNS_TTL, PORT);
     let mut upload_data = networking::UploadData::new();
-    let mut client = networking::WebClient::<4096, 4096>::new(rng);
+    let mut client = networking::WebClient::<4096, 4096>::new(rng, DEVICE_KEY, UPLOAD_PATH, commands, watering_log);
 
     loop {
         stack.wait_config_up().await;
         select::select(upload_data.ready_to_tx(&upload), Timer::after(Duration::from_secs(60*5))).await;
-        client.update_server(&stack, &mut dns_address, &mut upload_data).await;
+        if !client.update_server(&stack, &mut dns_address, &mut upload_data).await {
+            Timer::after(UPLOAD_RETRY).await;
+        }
     }
 }
 
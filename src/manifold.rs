@@ -0,0 +1,184 @@
+use core::{future::{poll_fn, Future}, pin::pin, task::Poll};
+
+use embassy_futures::select::{select4, Either4};
+use embassy_sync::{blocking_mutex::raw::NoopRawMutex, signal::Signal};
+use embassy_time::{Duration, Instant, Timer};
+use heapless::Vec;
+use log::{info, warn};
+
+use crate::{pump_command::{AbortReason, PumpChannel, PumpCommand, PumpOutcome, PumpStatus}, pump_driver::PumpDriver, zone::MAX_ZONES};
+
+/// Time a valve gets to open before the pump starts against it.
+const VALVE_OPEN_DELAY: Duration = Duration::from_millis(300);
+/// Time after the pump ramped down before its valve closes, so it never pushes against a closed manifold.
+const VALVE_CLOSE_DELAY: Duration = Duration::from_millis(500);
+
+/// A zone on a manifold and the valve that feeds it.
+pub struct ManifoldZone<'a, V> {
+    pub zone: usize,
+    pub channel: &'a PumpChannel,
+    pub valve: V,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Phase {
+    Idle,
+    /// The valve of the zone in `slot` opens, the command starts once it is open.
+    Opening { slot: usize, command: PumpCommand, until: Instant },
+    Running { slot: usize },
+    /// The pump stopped, the valve closes once the pump has ramped down.
+    Closing { slot: usize, until: Instant },
+}
+
+struct Manifold<'m, 'a, V> {
+    pump: &'m PumpChannel,
+    zones: &'m mut [ManifoldZone<'a, V>],
+    close_delay: Duration,
+    cooldown: Duration,
+    phase: Phase,
+    /// The pump refuses starts before this, the end of its last run plus the cooldown.
+    cooled: Instant,
+    /// Slot the pump ran for last, the round robin continues after it.
+    last: usize,
+    /// Commands of zones waiting for the pump, by slot.
+    waiting: [Option<PumpCommand>; MAX_ZONES],
+}
+
+impl<V: PumpDriver> Manifold<'_, '_, V> {
+    fn command(&mut self, slot: usize, command: PumpCommand, now: Instant) {
+        let stops = matches!(command, PumpCommand::Off | PumpCommand::Duty(0));
+        let replaced = if stops { AbortReason::Stopped } else { AbortReason::Superseded };
+        match self.phase {
+            Phase::Running { slot: active } if active == slot => {
+                self.pump.command.signal(command);
+                if stops {
+                    self.phase = Phase::Closing { slot, until: now + self.close_delay };
+                }
+            },
+            Phase::Closing { slot: active, .. } if active == slot && !stops => {
+                self.pump.command.signal(command);
+                self.phase = Phase::Running { slot };
+            },
+            Phase::Opening { slot: active, command: opening, until } if active == slot => {
+                self.report(slot, PumpStatus::aborted(opening, replaced));
+                if stops {
+                    self.zones[slot].valve.set_duty(0.0);
+                    self.phase = Phase::Idle;
+                    self.serve_next(now);
+                } else {
+                    self.phase = Phase::Opening { slot, command, until };
+                }
+            },
+            Phase::Idle if !stops && now >= self.cooled => self.open(slot, command, now),
+            _ => {
+                if let Some(waiting) = core::mem::replace(&mut self.waiting[slot], (!stops).then_some(command)) {
+                    self.report(slot, PumpStatus::aborted(waiting, replaced));
+                }
+                if !stops {
+                    info!("Zone {} waits for the manifold pump", self.zones[slot].zone);
+                }
+            },
+        }
+    }
+
+    /// Passes a status of the pump on to the zone it ran for.
+    fn status(&mut self, status: PumpStatus, now: Instant) {
+        let slot = match self.phase {
+            Phase::Running { slot } | Phase::Closing { slot, .. } => slot,
+            Phase::Idle | Phase::Opening { .. } => {
+                warn!("Manifold pump status {:?} without a zone", status);
+                return;
+            },
+        };
+        self.report(slot, status);
+        if status.runtime > Duration::from_ticks(0) && status.outcome != PumpOutcome::Aborted(AbortReason::Superseded) {
+            self.cooled = now + self.cooldown;
+        }
+        if matches!(self.phase, Phase::Running { .. }) && status.outcome != PumpOutcome::Aborted(AbortReason::Superseded) {
+            self.phase = Phase::Closing { slot, until: now + self.close_delay };
+        }
+    }
+
+    fn deadline(&self) -> Instant {
+        match self.phase {
+            Phase::Opening { until, .. } | Phase::Closing { until, .. } => until,
+            Phase::Idle if self.waiting.iter().any(Option::is_some) => self.cooled,
+            Phase::Idle | Phase::Running { .. } => Instant::MAX,
+        }
+    }
+
+    fn timeout(&mut self, now: Instant) {
+        match self.phase {
+            Phase::Opening { slot, command, until } if until <= now => {
+                self.pump.command.signal(command);
+                self.phase = Phase::Running { slot };
+            },
+            Phase::Closing { slot, until } if until <= now => {
+                self.zones[slot].valve.set_duty(0.0);
+                self.phase = Phase::Idle;
+                self.serve_next(now);
+            },
+            Phase::Idle => self.serve_next(now),
+            _ => {},
+        }
+    }
+
+    fn open(&mut self, slot: usize, command: PumpCommand, now: Instant) {
+        info!("Manifold opens the valve of zone {}", self.zones[slot].zone);
+        self.zones[slot].valve.set_duty(100.0);
+        self.last = slot;
+        self.phase = Phase::Opening { slot, command, until: now + VALVE_OPEN_DELAY };
+    }
+
+    /// Serves the next waiting zone once the pump cooled down, round robin so no zone starves.
+    fn serve_next(&mut self, now: Instant) {
+        if now < self.cooled {
+            return;
+        }
+        let count = self.zones.len();
+        let last = self.last;
+        let next = (1..=count).map(|offset| (last + offset) % count).find_map(|slot| self.waiting[slot].take().map(|command| (slot, command)));
+        if let Some((slot, command)) = next {
+            self.open(slot, command, now);
+        }
+    }
+
+    fn report(&self, slot: usize, status: PumpStatus) {
+        if let Err(err) = self.zones[slot].channel.status.try_send(status) {
+            warn!("Failed to report pump status to zone {} {:?}", self.zones[slot].zone, err);
+        }
+    }
+}
+
+/// Shares one pump between the zones of a manifold. Only the valve of the zone the pump runs for is
+/// open: it opens before the pump starts and closes after the pump stopped and ramped down within
+/// `ramp_down`. Zones that command the pump meanwhile wait and are served in turn, each once the
+/// pump is past the `cooldown` after the previous run.
+pub async fn run_manifold<V: PumpDriver>(pump: &PumpChannel, zones: &mut [ManifoldZone<'_, V>], ramp_down: Duration, cooldown: Duration) {
+    let commands: Vec<&Signal<NoopRawMutex, PumpCommand>, MAX_ZONES> = zones.iter().map(|zone| zone.channel).map(|channel| &channel.command).collect();
+    let calibrations: Vec<_, MAX_ZONES> = zones.iter().map(|zone| zone.channel).map(|channel| &channel.calibrate).collect();
+    let mut manifold = Manifold { pump, zones, close_delay: ramp_down + VALVE_CLOSE_DELAY, cooldown, phase: Phase::Idle, cooled: Instant::MIN, last: 0, waiting: [None; MAX_ZONES] };
+    loop {
+        let event = select4(any_signal(&commands), pump.status.receive(), Timer::at(manifold.deadline()), any_signal(&calibrations)).await;
+        let now = Instant::now();
+        match event {
+            Either4::First((slot, command)) => manifold.command(slot, command, now),
+            Either4::Second(status) => manifold.status(status, now),
+            Either4::Third(()) => manifold.timeout(now),
+            Either4::Fourth((_, point)) => pump.calibrate.signal(point),
+        }
+    }
+}
+
+/// Waits for any of `signals`, returns its index and value.
+async fn any_signal<T: Send>(signals: &[&Signal<NoopRawMutex, T>]) -> (usize, T) {
+    poll_fn(|cx| {
+        for (index, signal) in signals.iter().enumerate() {
+            if let Poll::Ready(value) = pin!(signal.wait()).poll(cx) {
+                return Poll::Ready((index, value));
+            }
+        }
+        Poll::Pending
+    })
+    .await
+}
@@ -0,0 +1,192 @@
+use embassy_futures::select::{select, select4, Either, Either4};
+use embassy_sync::{blocking_mutex::raw::NoopRawMutex, channel::Channel};
+use embassy_time::{Duration, Instant, Timer};
+use heapless::Deque;
+use log::{info, warn};
+
+use crate::{events::{Event, EventSender}, pump_command::{AbortReason, PumpChannel, PumpCommand, PumpOutcome, PumpStatus}, watering_history::{Fluid, RunReason, WateringLog, WateringRecord}, zone::WateredChannel};
+
+/// Manual control ends this long after the last manual command.
+pub const OVERRIDE_TIMEOUT: Duration = Duration::from_secs(15 * 60);
+const PRIME_DUTY: u8 = 100;
+
+/// Manual commands of one zone and who gave them, from the maintenance button or the server.
+pub type ManualQueue = Channel<NoopRawMutex, (ManualCommand, RunReason), 4>;
+
+/// Maintenance command for the pump of one zone, it takes priority over the automatic controller.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ManualCommand {
+    /// Run at a duty in percent until switched off or the override times out.
+    On(u8),
+    /// Keep the pump off.
+    Off,
+    RunFor(Duration, u8),
+    /// Fill the line at full duty for the prime time of the zone.
+    Prime,
+    /// Hand the pump back to the automatic controller.
+    Auto,
+}
+
+/// The channels of one zone the override layer sits between and where it records the runs.
+pub struct OverrideConfig<'a> {
+    pub zone: usize,
+    /// Commands of the automatic controller.
+    pub auto: &'a PumpChannel,
+    pub manual: &'a ManualQueue,
+    /// Commands that reach the actuator.
+    pub output: &'a PumpChannel,
+    /// Run time of `ManualCommand::Prime`.
+    pub prime: Duration,
+    pub events: EventSender<'a>,
+    pub history: &'a WateringLog,
+    /// Automatic water runs, passed on to the nutrient dosing of the zone if it has one.
+    pub watered: Option<&'a WateredChannel>,
+}
+
+/// Who sent a command that still owes its status.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Source {
+    /// `overridden` once a manual command took over while it ran.
+    Auto { overridden: bool },
+    Manual(RunReason),
+}
+
+struct Override<'a> {
+    zone: usize,
+    auto: &'a PumpChannel,
+    output: &'a PumpChannel,
+    events: EventSender<'a>,
+    history: &'a WateringLog,
+    /// Automatic water runs, passed on to the nutrient dosing of the zone if it has one.
+    watered: Option<&'a WateredChannel>,
+    prime: Duration,
+    /// Manual control is active until then.
+    manual_until: Option<Instant>,
+    /// Sources of the commands sent to the output whose status is due, oldest first.
+    pending: Deque<Source, 8>,
+    /// Last command sent to the output, it is lost if the output has not taken it before the next.
+    sent: Option<PumpCommand>,
+}
+
+impl Override<'_> {
+    fn forward(&mut self, command: PumpCommand, source: Source) {
+        if let Some(dropped) = self.sent.filter(|dropped| dropped.reports_status() && self.output.command.signaled()) {
+            // The output never sees the replaced command, so it owes no status for it.
+            let replaced = if command.reports_status() { AbortReason::Superseded } else { AbortReason::Stopped };
+            let source = self.pending.pop_back();
+            self.settle(PumpStatus::aborted(dropped, replaced), source);
+        }
+        if command.reports_status() && self.pending.push_back(source).is_err() {
+            warn!("Zone {} lost track of pump statuses", self.zone);
+        }
+        self.sent = Some(command);
+        self.output.command.signal(command);
+    }
+
+    fn auto_command(&mut self, command: PumpCommand) {
+        if self.manual_until.is_none() {
+            self.forward(command, Source::Auto { overridden: false });
+        } else if command.reports_status() {
+            info!("Zone {} ignored {:?}, the pump is under manual control", self.zone, command);
+            self.send_auto(PumpStatus::aborted(command, AbortReason::Overridden));
+        }
+    }
+
+    fn manual_command(&mut self, command: ManualCommand, reason: RunReason, now: Instant) {
+        let pump_command = match command {
+            ManualCommand::On(duty) => PumpCommand::Duty(duty),
+            ManualCommand::Off => PumpCommand::Off,
+            ManualCommand::RunFor(duration, duty) => PumpCommand::RunFor(duration, duty),
+            ManualCommand::Prime => PumpCommand::RunFor(self.prime, PRIME_DUTY),
+            ManualCommand::Auto => return self.resume_auto(),
+        };
+        info!("Zone {} manual {:?}", self.zone, command);
+        self.report(Event::ManualControl { zone: self.zone, command });
+        for source in self.pending.iter_mut() {
+            if let Source::Auto { overridden } = source {
+                *overridden = true;
+            }
+        }
+        self.manual_until = Some(now + OVERRIDE_TIMEOUT);
+        self.forward(pump_command, Source::Manual(reason));
+    }
+
+    /// Stops what was started by hand and hands the pump back.
+    fn resume_auto(&mut self) {
+        if self.manual_until.take().is_none() {
+            return;
+        }
+        info!("Zone {} back under automatic control", self.zone);
+        self.forward(PumpCommand::Off, Source::Manual(RunReason::Manual));
+        self.report(Event::AutomaticControl { zone: self.zone });
+    }
+
+    fn status(&mut self, status: PumpStatus) {
+        let source = self.pending.pop_front();
+        self.settle(status, source);
+    }
+
+    /// Records a run and returns its status to the automatic controller, which counts the water of
+    /// every run in its records and only acts on the statuses of its own commands.
+    fn settle(&mut self, mut status: PumpStatus, source: Option<Source>) {
+        if let Some(Source::Auto { overridden: true }) = source {
+            if matches!(status.outcome, PumpOutcome::Aborted(AbortReason::Superseded | AbortReason::Stopped)) {
+                status.outcome = PumpOutcome::Aborted(AbortReason::Overridden);
+            }
+        }
+        let reason = match source {
+            Some(Source::Manual(reason)) => reason,
+            Some(Source::Auto { .. }) | None => RunReason::Auto,
+        };
+        if status.runtime.as_ticks() > 0 {
+            let record = self.history.record(self.zone, Fluid::Water, &status, reason);
+            info!("Zone {} pump ran {:?}", self.zone, record);
+            self.report(Event::PumpRun { record });
+            if reason == RunReason::Auto {
+                self.send_watered(record);
+            }
+        }
+        if source.is_none() {
+            warn!("Zone {} pump status {:?} without a command", self.zone, status);
+        }
+        self.send_auto(status);
+    }
+
+    fn send_auto(&self, status: PumpStatus) {
+        if let Err(err) = self.auto.status.try_send(status) {
+            warn!("Failed to report pump status {:?}", err);
+        }
+    }
+
+    fn send_watered(&self, record: WateringRecord) {
+        if let Err(err) = self.watered.map_or(Ok(()), |watered| watered.try_send(record)) {
+            warn!("Failed to pass water run on to nutrient dosing {:?}", err);
+        }
+    }
+
+    fn report(&self, event: Event) {
+        if let Err(err) = self.events.try_send(event) {
+            warn!("Failed to report event {:?}", err);
+        }
+    }
+}
+
+/// Sits between the automatic controller of a zone on `auto` and its pump on `output`. Manual
+/// commands take over the pump, automatic commands are refused meanwhile and the pump returns to
+/// automatic control on `ManualCommand::Auto` or `OVERRIDE_TIMEOUT` after the last manual command.
+/// Every run of the pump is recorded in `history` and its status goes to `auto`, the automatic ones
+/// are passed on to `watered`.
+pub async fn run_override(config: OverrideConfig<'_>) {
+    let OverrideConfig { zone, auto, manual, output, prime, events, history, watered } = config;
+    let mut state = Override { zone, auto, output, events, history, watered, prime, manual_until: None, pending: Deque::new(), sent: None };
+    loop {
+        let timeout = state.manual_until.unwrap_or(Instant::MAX);
+        match select4(select(auto.command.wait(), auto.calibrate.wait()), manual.receive(), output.status.receive(), Timer::at(timeout)).await {
+            Either4::First(Either::First(command)) => state.auto_command(command),
+            Either4::First(Either::Second(point)) => output.calibrate.signal(point),
+            Either4::Second((command, reason)) => state.manual_command(command, reason, Instant::now()),
+            Either4::Third(status) => state.status(status),
+            Either4::Fourth(()) => state.resume_auto(),
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use embassy_time::{Duration, Instant};
+
+use crate::pump_safety::PumpFault;
+
+/// Current bands of a pump motor measured at a steady duty, in mA through the shunt.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CurrentLimits {
+    /// Below this the motor or its wiring is disconnected.
+    pub open_circuit_ma: f64,
+    /// Below this the pump spins without load, it runs dry.
+    pub dry_running_ma: f64,
+    /// Above this the rotor is blocked or the line is clogged.
+    pub stall_ma: f64,
+    /// Time after a start during which the inrush current is not classified.
+    pub inrush: Duration,
+    /// How long an abnormal state has to persist before the pump is shut down.
+    pub confirm: Duration,
+}
+
+impl Default for CurrentLimits {
+    fn default() -> Self {
+        Self {
+            open_circuit_ma: 20.0,
+            dry_running_ma: 120.0,
+            stall_ma: 900.0,
+            inrush: Duration::from_millis(500),
+            confirm: Duration::from_secs(1),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MotorState {
+    Off,
+    /// Inrush or ramping, the current says nothing about the load yet.
+    Starting,
+    Normal,
+    Stalled,
+    DryRunning,
+    OpenCircuit,
+}
+
+impl MotorState {
+    /// Fault the pump is shut down for once the state persists.
+    pub fn fault(&self) -> Option<PumpFault> {
+        match self {
+            Self::Stalled => Some(PumpFault::Stalled),
+            Self::DryRunning => Some(PumpFault::DryRunning),
+            Self::OpenCircuit => Some(PumpFault::OpenCircuit),
+            Self::Off | Self::Starting | Self::Normal => None,
+        }
+    }
+}
+
+/// Classifies the motor from its current and reports states that persist for the confirm time.
+pub struct MotorMonitor {
+    limits: CurrentLimits,
+    state: MotorState,
+    running_since: Option<Instant>,
+    abnormal_since: Option<Instant>,
+}
+
+impl MotorMonitor {
+    pub fn new(limits: CurrentLimits) -> Self {
+        Self { limits, state: MotorState::Off, running_since: None, abnormal_since: None }
+    }
+
+    pub fn state(&self) -> MotorState {
+        self.state
+    }
+
+    pub fn start(&mut self, now: Instant) {
+        self.state = MotorState::Starting;
+        self.running_since = Some(now);
+        self.abnormal_since = None;
+    }
+
+    pub fn stop(&mut self) {
+        self.state = MotorState::Off;
+        self.running_since = None;
+        self.abnormal_since = None;
+    }
+
+    pub fn classify(&self, ma: f64) -> MotorState {
+        if ma < self.limits.open_circuit_ma {
+            MotorState::OpenCircuit
+        } else if ma < self.limits.dry_running_ma {
+            MotorState::DryRunning
+        } else if ma > self.limits.stall_ma {
+            MotorState::Stalled
+        } else {
+            MotorState::Normal
+        }
+    }
+
+    /// Takes a current sample, `steady` while the duty is not ramping. Returns the new state if it
+    /// changed and whether the sample was classified.
+    pub fn update(&mut self, ma: f64, steady: bool, now: Instant) -> (Option<MotorState>, bool) {
+        let Some(running_since) = self.running_since else {
+            return (None, false);
+        };
+        let settled = steady && now.checked_duration_since(running_since).is_some_and(|running| running >= self.limits.inrush);
+        let state = if settled { self.classify(ma) } else { MotorState::Starting };
+        let changed = state != self.state;
+        if changed || state.fault().is_none() {
+            self.abnormal_since = state.fault().map(|_| now);
+        }
+        self.state = state;
+        (changed.then_some(state), settled)
+    }
+
+    /// Fault of an abnormal state that lasted for the confirm time.
+    pub fn fault(&self, now: Instant) -> Option<PumpFault> {
+        let since = self.abnormal_since?;
+        let lasted = now.checked_duration_since(since).is_some_and(|lasted| lasted >= self.limits.confirm);
+        self.state.fault().filter(|_| lasted)
+    }
+}
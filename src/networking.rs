@@ -1,15 +1,14 @@
 use embassy_net::{dns::DnsQueryType, tcp::TcpSocket, IpAddress, Stack};
 use embassy_sync::{blocking_mutex::raw::NoopRawMutex, channel::Receiver};
+use embassy_futures::select::{select, Either};
 use embassy_time::{Duration, Instant, Timer};
-use embedded_svc::http::{self, client::Request};
 use esp32_hal::Rng;
-use esp_println::print;
 use esp_wifi::wifi::{WifiDevice, WifiStaDevice};
 use heapless::Vec;
-use log::{info, error};
+use log::{info, error, warn};
 use esp_backtrace as _;
 
-use crate::soil_estimator;
+use crate::{auth::{self, Authentication, DeviceKey}, events::Event, http::{HttpClient, Method}, server_command::{self, ServerCommandQueue}, statistics::AggregateRecord, upload::{self, Uptime, MAX_EVENTS, MAX_HISTORY, MAX_MESSUREMENTS}, watering_history::{HistoryQuery, WateringLog, WateringRecord}};
 
 pub struct DNSAddress<'a> {
     url: &'a str,
@@ -52,91 +51,175 @@ impl<'a> DNSAddress<'a> {
 }
 
 pub struct UploadDataSource {
-    pub messurements: Receiver<'static, NoopRawMutex, soil_estimator::FilteredMessurement, 64>,
+    pub messurements: Receiver<'static, NoopRawMutex, AggregateRecord, 64>,
+    pub events: Receiver<'static, NoopRawMutex, Event, 16>,
 }
 
+/// The batch of the next upload, kept until the server acknowledged it.
 pub struct UploadData {
-    messurements: Vec<soil_estimator::FilteredMessurement, 10>,
+    messurements: Vec<AggregateRecord, MAX_MESSUREMENTS>,
+    events: Vec<Event, MAX_EVENTS>,
+    /// Watering records the server asked for, sent with the next batch.
+    history: Option<HistoryQuery>,
 }
 
 impl UploadData {
     pub fn new() -> Self {
-        Self { messurements: Vec::new() }
+        Self { messurements: Vec::new(), events: Vec::new(), history: None }
     }
+    /// Waits until a full batch of messurements is collected, events are sent right away.
     pub async fn ready_to_tx(&mut self, sources: &UploadDataSource) {
         while !self.messurements.is_full() {
-            self.messurements.push(sources.messurements.receive().await).unwrap();
+            match select(sources.messurements.receive(), sources.events.receive()).await {
+                Either::First(messurement) => self.messurements.push(messurement).unwrap(),
+                Either::Second(event) => {
+                    if let Err(event) = self.events.push(event) {
+                        error!("Upload event queue full, dropping {:?}", event);
+                    }
+                    return;
+                },
+            }
         }
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.messurements.is_empty() && self.events.is_empty() && self.history.is_none()
+    }
+
+    /// Drops the batch once the server has it.
+    fn clear(&mut self) {
+        self.messurements.clear();
+        self.events.clear();
+        self.history = None;
+    }
 }
 
-pub struct Authentication {
-    pub local_nonce: u64,
-    pub server_noce: u64,
+impl Default for UploadData {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
+/// Largest HTTP response the client reads, head and body.
+const HTTP_BUFFER: usize = 2048;
+/// Largest JSON body of an upload, a full batch with history takes up to 10.5 kB.
+const UPLOAD_BUFFER: usize = 12288;
+
 pub struct WebClient<const TX_N:usize, const RX_N:usize> {
     tx_buffer: [u8; TX_N],
     rx_buffer: [u8; RX_N],
+    http_buffer: [u8; HTTP_BUFFER],
+    upload_buffer: [u8; UPLOAD_BUFFER],
+    /// Path on the server the batches are posted to.
+    upload_path: &'static str,
     rng: Rng,
-    auth: Option<Authentication>,
+    /// Signs the uploads, the server refuses unsigned ones.
+    key: DeviceKey,
+    /// Receives the commands the server sends back with its acknowledgement.
+    commands: &'static ServerCommandQueue,
+    /// Answers the history requests of the server.
+    history: &'static WateringLog,
+    /// Sent with every batch, so the server notices a reset of the times since boot.
+    boot_id: u32,
 }
 
 impl<const TX_N:usize, const RX_N:usize> WebClient<TX_N, RX_N> {
-    pub fn new(rng: Rng) -> Self {
-        Self { tx_buffer: [0x0 ; TX_N], rx_buffer: [0x0 ; RX_N], auth: None, rng}
+    pub fn new(mut rng: Rng, key: DeviceKey, upload_path: &'static str, commands: &'static ServerCommandQueue, history: &'static WateringLog) -> Self {
+        let boot_id = rng.random();
+        Self { tx_buffer: [0x0 ; TX_N], rx_buffer: [0x0 ; RX_N], http_buffer: [0x0 ; HTTP_BUFFER], upload_buffer: [0x0 ; UPLOAD_BUFFER], upload_path, rng, key, commands, history, boot_id}
     }
 
+    /// Nonce of a new handshake, from the hardware random number generator.
+    fn nonce(&mut self) -> u64 {
+        (self.rng.random() as u64) << 32 | self.rng.random() as u64
+    }
+
+
+    /// Posts the collected data as JSON to the upload path, signed with the nonce of a handshake
+    /// on the same connection. It is dropped once the server acknowledged it with a 2xx and kept
+    /// for the next attempt otherwise. Commands in the acknowledgement are queued for their tasks,
+    /// a history request is answered with the next batch. Returns whether the server has the data.
+    pub async fn update_server(&mut self, stack: &Stack<WifiDevice<'_, WifiStaDevice>>, dns_address: &mut DNSAddress<'_>, upload_data: &mut UploadData) -> bool {
+        if upload_data.is_empty() {
+            return true;
+        }
+        let history: Option<Vec<WateringRecord, MAX_HISTORY>> = upload_data.history.map(|query| self.history.query(&query));
+        let uptime = Uptime { boot_id: self.boot_id, uptime_ms: Instant::now().as_millis() };
+        let length = match upload::serialize_batch(uptime, &upload_data.messurements, &upload_data.events, history.as_deref(), &mut self.upload_buffer) {
+            Ok(length) => length,
+            Err(err) => {
+                // It would not fit on the next attempt either.
+                error!("Dropping upload batch that does not serialize for {:?}.", err);
+                upload_data.clear();
+                return false;
+            },
+        };
 
-    pub async fn update_server(&mut self, stack: &Stack<WifiDevice<'_, WifiStaDevice>>, dns_address: &mut DNSAddress<'_>, upload_data: &mut UploadData) {
-        use embedded_io_async::Write;
         let mut socket = TcpSocket::new(&stack, &mut self.rx_buffer, &mut self.tx_buffer);
         socket.set_timeout(Some(embassy_time::Duration::from_secs(10)));
         let end_point = dns_address.querry_endpoint(stack, Duration::from_secs(60*5)).await;
 
-
         if let Err(err) = socket.connect(end_point).await {
             error!("Failed to connect to {} at {:?} for {:?}.", dns_address.url, end_point, err);
-            return;
+            return false;
         }
         info!("Connected to {} at {:?}.", dns_address.url, end_point);
 
-        socket.write_all(b"GET /auth HTTP/1.0\r\nHost: ").await;
-        socket.write_all(dns_address.url.as_bytes()).await;
-        socket.write_all(b"\r\nAuthorization: HANDSHAKE\r\nConnection: keep-alive\r\nUser-Agent: Dewy\r\n\r\n").await;
-
-
-        let c = http::client::Client::wrap(socket);
-
-
-
-
-
-
-
-
-        if let Err(err) = socket.write_all(
-        b"GET / HTTP/1.0\r\nHost: www.mobile-j.de\r\n\r\n"
-        ).await {
-            error!("Get to {} failed at {:?} for {:?}.", dns_address.url, end_point, err);
-            return;
-        }
-
-        upload_data.messurements.clear();
-
-        if let Err(err) = socket.read_with(|rx_buf|{
-            if rx_buf.len() == 0 {
-                info!("read EOF");
-            } else {
-                match core::str::from_utf8(rx_buf) {
-                    Ok(read_str) => print!("{}", read_str),
-                    Err(err) => error!("Could not decode read to utf-8  from {} at {:?} for {:?}.", dns_address.url, end_point, err),
-                };
-            };
-            (rx_buf.len(), ())
-        }).await {
-            error!("Socket read from {} failed at {:?} for {:?}.", dns_address.url, end_point, err);
-        }
+        let local_nonce = self.nonce();
+        let mut client = HttpClient::new(&mut socket, dns_address.url, &mut self.http_buffer);
+        let handshake = self.key.handshake_header(local_nonce);
+        let authentication = match client.request(Method::Get, auth::HANDSHAKE_PATH, &[("Authorization", handshake.as_bytes())], &[]).await {
+            Ok(response) if response.is_success() => Authentication::accept(&self.key, local_nonce, response.header(auth::NONCE_HEADER), response.header(auth::SIGNATURE_HEADER)),
+            Ok(response) => {
+                warn!("{} refused the handshake with {}.", dns_address.url, response.status);
+                socket.close();
+                return false;
+            },
+            Err(err) => {
+                error!("Handshake with {} at {:?} failed for {:?}.", dns_address.url, end_point, err);
+                socket.close();
+                return false;
+            },
+        };
+        let authentication = match authentication {
+            Ok(authentication) => authentication,
+            Err(err) => {
+                error!("{} failed to authenticate for {:?}.", dns_address.url, err);
+                socket.close();
+                return false;
+            },
+        };
+        let body = &self.upload_buffer[..length];
+        let authorization = authentication.sign_request(&self.key, "POST", self.upload_path, body);
+        let headers = [("Content-Type", b"application/json".as_slice()), ("Authorization", authorization.as_bytes())];
+        let delivered = match client.request(Method::Post, self.upload_path, &headers, body).await {
+            Ok(response) if response.is_success() => {
+                info!("Uploaded {} messurements and {} events to {}.", upload_data.messurements.len(), upload_data.events.len(), dns_address.url);
+                upload_data.clear();
+                match server_command::parse(response.body) {
+                    Ok(response) => {
+                        for command in response.commands {
+                            if let Err(err) = self.commands.try_send(command) {
+                                warn!("Server command queue full, dropping {:?}", err);
+                            }
+                        }
+                        upload_data.history = response.history;
+                    },
+                    Err(err) => error!("Failed to read the commands of {} for {:?}.", dns_address.url, err),
+                }
+                true
+            },
+            Ok(response) => {
+                warn!("{} refused the upload with {}, keeping it for the next attempt.", dns_address.url, response.status);
+                false
+            },
+            Err(err) => {
+                error!("Upload to {} at {:?} failed for {:?}.", dns_address.url, end_point, err);
+                false
+            },
+        };
+        socket.close();
+        delivered
     }
 }
     // let mut socket = TcpSocket::new(&stack, &mut rx_buffer, &mut tx_buffer);
\ No newline at end of file
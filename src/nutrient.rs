@@ -0,0 +1,116 @@
+use embassy_time::{Duration, Instant};
+use heapless::Deque;
+use log::{info, warn};
+
+use crate::{events::{Event, EventSender}, pump_command::{PumpChannel, PumpCommand}, watering_history::{EndCause, Fluid, RunReason, WateringLog}, zone::WateredChannel};
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+const WEEK: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// How much fertilizer goes with the water of a zone.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NutrientSchedule {
+    /// Fixed concentration, every watering gets fertilizer in proportion to its water.
+    PerLitre { ml_per_litre: f64 },
+    /// Fixed amount per week, accrued over time and given with the next watering.
+    Weekly { ml_per_week: f64 },
+}
+
+/// Caps on the fertilizer of one zone.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NutrientLimits {
+    /// Largest single dose.
+    pub max_dose_ml: f64,
+    /// Total within any 24 h window.
+    pub max_daily_ml: f64,
+    /// Smaller doses are skipped, the pump can not deliver them accurately.
+    pub min_dose_ml: f64,
+}
+
+/// Works out the fertilizer dose for each watering within the caps.
+pub struct NutrientDoser {
+    schedule: NutrientSchedule,
+    limits: NutrientLimits,
+    /// Doses of the last 24 h.
+    doses: Deque<(Instant, f64), 32>,
+    /// Weekly fertilizer accrued but not given yet.
+    owed_ml: f64,
+    accrued_at: Instant,
+}
+
+impl NutrientDoser {
+    pub fn new(schedule: NutrientSchedule, limits: NutrientLimits, now: Instant) -> Self {
+        Self { schedule, limits, doses: Deque::new(), owed_ml: 0.0, accrued_at: now }
+    }
+
+    /// Dose for a watering that delivered `water_ml`, `None` if it is skipped.
+    pub fn dose_for(&mut self, water_ml: f64, now: Instant) -> Option<f64> {
+        if water_ml <= 0.0 {
+            return None;
+        }
+        let wanted = match self.schedule {
+            NutrientSchedule::PerLitre { ml_per_litre } => water_ml / 1000.0 * ml_per_litre,
+            NutrientSchedule::Weekly { ml_per_week } => {
+                let elapsed = now.checked_duration_since(self.accrued_at).unwrap_or(Duration::from_ticks(0));
+                // Missed weeks are not made up, that would overdose.
+                self.owed_ml = (self.owed_ml + ml_per_week * elapsed.as_millis() as f64 / WEEK.as_millis() as f64).min(ml_per_week);
+                self.accrued_at = now;
+                self.owed_ml
+            },
+        };
+        let budget = self.limits.max_daily_ml - self.daily_ml(now);
+        let dose = wanted.min(self.limits.max_dose_ml).min(budget);
+        if dose < wanted {
+            info!("Nutrient dose capped from {} ml to {} ml", wanted, dose);
+        }
+        (dose >= self.limits.min_dose_ml && dose > 0.0).then_some(dose)
+    }
+
+    /// Accounts a dose that was delivered.
+    pub fn dosed(&mut self, ml: f64, now: Instant) {
+        if self.doses.is_full() {
+            self.doses.pop_front();
+        }
+        let _ = self.doses.push_back((now, ml));
+        self.owed_ml = (self.owed_ml - ml).max(0.0);
+    }
+
+    fn daily_ml(&mut self, now: Instant) -> f64 {
+        while let Some((at, _)) = self.doses.front() {
+            if now.checked_duration_since(*at).is_some_and(|age| age > DAY) {
+                self.doses.pop_front();
+            } else {
+                break;
+            }
+        }
+        self.doses.iter().map(|(_, ml)| ml).sum()
+    }
+}
+
+/// Doses fertilizer after each completed automatic watering of a zone from `watered` with its
+/// nutrient `pump`. The doses are recorded in `history` like the water runs.
+pub async fn run_nutrient(zone: usize, mut doser: NutrientDoser, duty: u8, watered: &WateredChannel, pump: &PumpChannel, history: &WateringLog, events: EventSender<'_>) {
+    loop {
+        let water = watered.receive().await;
+        if water.reason != RunReason::Auto || water.end != EndCause::Completed {
+            // A cut short run left the soil short of water, fertilizer would be too concentrated.
+            info!("Zone {} skips the nutrient after a {:?} run that ended {:?}", zone, water.reason, water.end);
+            continue;
+        }
+        let Some(ml) = doser.dose_for(water.volume_ml, Instant::now()) else {
+            continue;
+        };
+        info!("Zone {} doses {} ml of nutrient after {} ml of water", zone, ml, water.volume_ml);
+        pump.command.signal(PumpCommand::Dose { ml, duty });
+        let status = pump.status.receive().await;
+        doser.dosed(status.volume_ml, Instant::now());
+        if status.runtime.as_ticks() == 0 {
+            warn!("Zone {} nutrient dose not delivered {:?}", zone, status.outcome);
+            continue;
+        }
+        let record = history.record(zone, Fluid::Nutrient, &status, water.reason);
+        if let Err(err) = events.try_send(Event::PumpRun { record }) {
+            warn!("Failed to report event {:?}", err);
+        }
+    }
+}
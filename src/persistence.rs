@@ -0,0 +1,339 @@
+use embassy_sync::{blocking_mutex::raw::NoopRawMutex, channel::Channel};
+use embassy_time::Duration;
+use embedded_storage::Storage;
+use log::error;
+
+use crate::{calibration::{Calibration, CalibrationMode}, drying::DryingSnapshot, flow_calibration::{FlowCalibration, FlowPoint, MAX_FLOW_POINTS}, soil_estimator::EstimatorSnapshot, watering::WateringSnapshot, zone::MAX_ZONES};
+
+/// Start of the `dewy` data partition in `partitions.csv`.
+pub const DEFAULT_OFFSET: u32 = 0x10000;
+/// Every record has an erase sector of its own, so rewriting the periodic estimator snapshot can
+/// not take a calibration down with it when power is lost.
+const SECTOR_SIZE: u32 = 0x1000;
+const CALIBRATION_SECTOR: u32 = 0;
+const ESTIMATOR_SECTOR: u32 = 1;
+const FLOW_SECTOR: u32 = 2;
+const SECTORS_PER_ZONE: u32 = 3;
+/// Size of the `dewy` partition.
+pub const REGION_SIZE: u32 = MAX_ZONES as u32 * SECTORS_PER_ZONE * SECTOR_SIZE;
+
+const MAGIC: u32 = 0x5957_4544; // "DEWY"
+const HEADER_LEN: usize = 8;
+const CRC_LEN: usize = 4;
+const MAX_RECORD_LEN: usize = HEADER_LEN + max_len(&[StoredCalibration::LEN, EstimatorSnapshot::LEN, FlowCalibration::LEN]) + CRC_LEN;
+
+#[derive(Debug)]
+pub enum PersistError<E> {
+    Storage(E),
+    /// The slot was never written or holds a record of another kind or version.
+    Missing,
+    /// The checksum does not match, e.g. power was lost while writing.
+    Corrupt,
+    /// The record does not fit the buffer it is read into.
+    TooLarge,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StoredCalibration {
+    pub calibration: Calibration,
+    pub mode: CalibrationMode,
+}
+
+impl StoredCalibration {
+    const KIND: u8 = 1;
+    const VERSION: u8 = 1;
+    const LEN: usize = 17;
+
+    fn encode(&self, buf: &mut [u8]) -> usize {
+        let mut encoder = Encoder::new(buf);
+        encoder.u8((self.mode == CalibrationMode::Manual) as u8);
+        encoder.f64(self.calibration.dry);
+        encoder.f64(self.calibration.wet);
+        encoder.len()
+    }
+
+    fn decode(buf: &[u8]) -> Option<Self> {
+        let mut decoder = Decoder::new(buf);
+        let mode = if decoder.u8()? != 0 { CalibrationMode::Manual } else { CalibrationMode::Learning };
+        let calibration = Calibration::new(decoder.f64()?, decoder.f64()?);
+        decoder.finish(Self { calibration, mode })
+    }
+}
+
+impl EstimatorSnapshot {
+    const KIND: u8 = 2;
+    const VERSION: u8 = 1;
+    const LEN: usize = 8 * 2 + 8 + 4 + 8 * 5 + 8 * 2;
+
+    fn encode(&self, buf: &mut [u8]) -> usize {
+        let mut encoder = Encoder::new(buf);
+        encoder.f64(self.moisture);
+        encoder.f64(self.temperature);
+        encoder.f64(self.drying.lowest);
+        encoder.u32(self.drying.n);
+        encoder.f64(self.drying.sum_t);
+        encoder.f64(self.drying.sum_y);
+        encoder.f64(self.drying.sum_tt);
+        encoder.f64(self.drying.sum_ty);
+        encoder.f64(self.drying.last_t);
+        encoder.duration(self.watering.soak_remaining);
+        encoder.duration(self.watering.since_watering);
+        encoder.len()
+    }
+
+    fn decode(buf: &[u8]) -> Option<Self> {
+        let mut decoder = Decoder::new(buf);
+        let snapshot = Self {
+            moisture: decoder.f64()?,
+            temperature: decoder.f64()?,
+            drying: DryingSnapshot {
+                lowest: decoder.f64()?,
+                n: decoder.u32()?,
+                sum_t: decoder.f64()?,
+                sum_y: decoder.f64()?,
+                sum_tt: decoder.f64()?,
+                sum_ty: decoder.f64()?,
+                last_t: decoder.f64()?,
+            },
+            watering: WateringSnapshot {
+                soak_remaining: decoder.duration()?,
+                since_watering: decoder.duration()?,
+            },
+        };
+        decoder.finish(snapshot)
+    }
+}
+
+impl FlowCalibration {
+    const KIND: u8 = 3;
+    const VERSION: u8 = 1;
+    const LEN: usize = 1 + 9 * MAX_FLOW_POINTS;
+
+    fn encode(&self, buf: &mut [u8]) -> usize {
+        let mut encoder = Encoder::new(buf);
+        encoder.u8(self.points().len() as u8);
+        for point in self.points() {
+            encoder.u8(point.duty);
+            encoder.f64(point.ml_per_s);
+        }
+        encoder.len()
+    }
+
+    fn decode(buf: &[u8]) -> Option<Self> {
+        let mut decoder = Decoder::new(buf);
+        let len = decoder.u8()? as usize;
+        let mut points = [FlowPoint { duty: 0, ml_per_s: 0.0 }; MAX_FLOW_POINTS];
+        for point in points.get_mut(..len)? {
+            *point = FlowPoint { duty: decoder.u8()?, ml_per_s: decoder.f64()? };
+        }
+        let calibration = FlowCalibration::new(&points[..len]);
+        if calibration.points().len() != len {
+            return None;
+        }
+        decoder.finish(calibration)
+    }
+}
+
+struct Encoder<'b> {
+    buf: &'b mut [u8],
+    len: usize,
+}
+
+impl<'b> Encoder<'b> {
+    fn new(buf: &'b mut [u8]) -> Self {
+        Self { buf, len: 0 }
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.buf[self.len..self.len + bytes.len()].copy_from_slice(bytes);
+        self.len += bytes.len();
+    }
+
+    fn u8(&mut self, value: u8) {
+        self.bytes(&[value]);
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn f64(&mut self, value: f64) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    /// Milliseconds, `u64::MAX` encodes `None`.
+    fn duration(&mut self, value: Option<Duration>) {
+        self.bytes(&value.map_or(u64::MAX, |duration| duration.as_millis()).to_le_bytes());
+    }
+}
+
+struct Decoder<'b> {
+    buf: &'b [u8],
+}
+
+impl<'b> Decoder<'b> {
+    fn new(buf: &'b [u8]) -> Self {
+        Self { buf }
+    }
+
+    fn bytes<const N: usize>(&mut self) -> Option<[u8; N]> {
+        if self.buf.len() < N {
+            return None;
+        }
+        let (head, tail) = self.buf.split_at(N);
+        self.buf = tail;
+        head.try_into().ok()
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.bytes::<1>().map(|[value]| value)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.bytes().map(u32::from_le_bytes)
+    }
+
+    fn f64(&mut self) -> Option<f64> {
+        self.bytes().map(f64::from_le_bytes)
+    }
+
+    fn duration(&mut self) -> Option<Option<Duration>> {
+        let millis = u64::from_le_bytes(self.bytes()?);
+        Some((millis != u64::MAX).then(|| Duration::from_millis(millis)))
+    }
+
+    /// Only accepts records that were consumed completely.
+    fn finish<T>(self, value: T) -> Option<T> {
+        self.buf.is_empty().then_some(value)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum PersistRequest {
+    Calibration { zone: usize, calibration: StoredCalibration },
+    Estimator { zone: usize, snapshot: EstimatorSnapshot },
+    Flow { zone: usize, calibration: FlowCalibration },
+}
+
+/// Writes are queued to the persistence task so the estimators never block on flash.
+pub type PersistQueue = Channel<NoopRawMutex, PersistRequest, 4>;
+
+/// Checksummed records at fixed slots of a flash region.
+pub struct Store<S> {
+    storage: S,
+    offset: u32,
+}
+
+impl<S: Storage> Store<S> {
+    pub fn new(storage: S, offset: u32) -> Self {
+        if (storage.capacity() as u64) < offset as u64 + REGION_SIZE as u64 {
+            error!("Flash of {} bytes ends within the store at {:#x}, saving will fail", storage.capacity(), offset);
+        }
+        Self { storage, offset }
+    }
+
+    pub fn load_calibration(&mut self, zone: usize) -> Result<StoredCalibration, PersistError<S::Error>> {
+        let mut buf = [0u8; StoredCalibration::LEN];
+        let payload = self.read_record(sector(zone, CALIBRATION_SECTOR), StoredCalibration::KIND, StoredCalibration::VERSION, &mut buf)?;
+        StoredCalibration::decode(payload).ok_or(PersistError::Corrupt)
+    }
+
+    pub fn save_calibration(&mut self, zone: usize, calibration: &StoredCalibration) -> Result<(), PersistError<S::Error>> {
+        self.write_record(sector(zone, CALIBRATION_SECTOR), StoredCalibration::KIND, StoredCalibration::VERSION, |buf| calibration.encode(buf))
+    }
+
+    pub fn load_estimator(&mut self, zone: usize) -> Result<EstimatorSnapshot, PersistError<S::Error>> {
+        let mut buf = [0u8; EstimatorSnapshot::LEN];
+        let payload = self.read_record(sector(zone, ESTIMATOR_SECTOR), EstimatorSnapshot::KIND, EstimatorSnapshot::VERSION, &mut buf)?;
+        EstimatorSnapshot::decode(payload).ok_or(PersistError::Corrupt)
+    }
+
+    pub fn save_estimator(&mut self, zone: usize, snapshot: &EstimatorSnapshot) -> Result<(), PersistError<S::Error>> {
+        self.write_record(sector(zone, ESTIMATOR_SECTOR), EstimatorSnapshot::KIND, EstimatorSnapshot::VERSION, |buf| snapshot.encode(buf))
+    }
+
+    pub fn load_flow(&mut self, zone: usize) -> Result<FlowCalibration, PersistError<S::Error>> {
+        let mut buf = [0u8; FlowCalibration::LEN];
+        let payload = self.read_record(sector(zone, FLOW_SECTOR), FlowCalibration::KIND, FlowCalibration::VERSION, &mut buf)?;
+        FlowCalibration::decode(payload).ok_or(PersistError::Corrupt)
+    }
+
+    pub fn save_flow(&mut self, zone: usize, calibration: &FlowCalibration) -> Result<(), PersistError<S::Error>> {
+        self.write_record(sector(zone, FLOW_SECTOR), FlowCalibration::KIND, FlowCalibration::VERSION, |buf| calibration.encode(buf))
+    }
+
+    pub fn save(&mut self, request: &PersistRequest) -> Result<(), PersistError<S::Error>> {
+        match request {
+            PersistRequest::Calibration { zone, calibration } => self.save_calibration(*zone, calibration),
+            PersistRequest::Estimator { zone, snapshot } => self.save_estimator(*zone, snapshot),
+            PersistRequest::Flow { zone, calibration } => self.save_flow(*zone, calibration),
+        }
+    }
+
+    fn read_record<'b>(&mut self, address: u32, kind: u8, version: u8, payload: &'b mut [u8]) -> Result<&'b [u8], PersistError<S::Error>> {
+        let mut header = [0u8; HEADER_LEN];
+        self.storage.read(self.offset + address, &mut header).map_err(PersistError::Storage)?;
+        let magic = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+        if magic != MAGIC || header[4] != kind || header[5] != version {
+            return Err(PersistError::Missing);
+        }
+        let len = u16::from_le_bytes([header[6], header[7]]) as usize;
+        if len > payload.len() {
+            return Err(PersistError::TooLarge);
+        }
+        let payload = &mut payload[..len];
+        self.storage.read(self.offset + address + HEADER_LEN as u32, payload).map_err(PersistError::Storage)?;
+        let mut crc = [0u8; CRC_LEN];
+        self.storage.read(self.offset + address + (HEADER_LEN + len) as u32, &mut crc).map_err(PersistError::Storage)?;
+        if u32::from_le_bytes(crc) != crc32(crc32(0, &header), payload) {
+            return Err(PersistError::Corrupt);
+        }
+        Ok(payload)
+    }
+
+    /// Writes header, payload and checksum at once, every write of the flash driver erases the sector.
+    fn write_record(&mut self, address: u32, kind: u8, version: u8, encode: impl FnOnce(&mut [u8]) -> usize) -> Result<(), PersistError<S::Error>> {
+        let mut record = [0u8; MAX_RECORD_LEN];
+        let len = encode(&mut record[HEADER_LEN..MAX_RECORD_LEN - CRC_LEN]);
+        record[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+        record[4] = kind;
+        record[5] = version;
+        record[6..8].copy_from_slice(&(len as u16).to_le_bytes());
+        let crc = crc32(0, &record[..HEADER_LEN + len]);
+        record[HEADER_LEN + len..HEADER_LEN + len + CRC_LEN].copy_from_slice(&crc.to_le_bytes());
+        self.storage.write(self.offset + address, &record[..HEADER_LEN + len + CRC_LEN]).map_err(PersistError::Storage)
+    }
+}
+
+/// Address of the sector of one record of a zone, relative to the start of the store.
+fn sector(zone: usize, record: u32) -> u32 {
+    (zone as u32 * SECTORS_PER_ZONE + record) * SECTOR_SIZE
+}
+
+const fn max_len(lens: &[usize]) -> usize {
+    let mut max = 0;
+    let mut index = 0;
+    while index < lens.len() {
+        if lens[index] > max {
+            max = lens[index];
+        }
+        index += 1;
+    }
+    max
+}
+
+/// CRC-32 (IEEE), `crc` is the value of the preceding chunk or 0.
+fn crc32(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
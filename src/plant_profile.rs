@@ -0,0 +1,88 @@
+use embassy_time::Duration;
+
+use crate::{calibration::Calibration, temperature_guard::{HeatAction, TemperatureLimits}};
+
+/// Watering and sampling parameters for one kind of plant.
+#[derive(Debug, Clone, Copy)]
+pub struct PlantProfile {
+    pub name: &'static str,
+    /// Initial probe calibration for the substrate this plant is usually potted in.
+    pub calibration: Calibration,
+    /// Relative moisture (0.0 dry .. 1.0 saturated) below which a watering is started.
+    pub water_below: f64,
+    /// Water given per watering in ml.
+    pub dose_ml: f64,
+    /// Pump duty in percent used while dosing, raised when the dose would exceed the on-time limit.
+    pub dose_duty: u8,
+    /// Time given to the water to spread through the pot before the moisture is judged again.
+    pub soak_time: Duration,
+    /// Frost and heat rules for watering.
+    pub temperature: TemperatureLimits,
+    /// Time between two soil messurements.
+    pub sample_period: Duration,
+    /// Samples the filter needs to settle before the estimate is acted upon.
+    pub warmup_samples: u64,
+    /// Span of the aggregated records uploaded for this zone.
+    pub aggregation_window: Duration,
+}
+
+pub const HOUSEPLANT: PlantProfile = PlantProfile {
+    name: "houseplant",
+    calibration: Calibration::new(320.0, 1015.0),
+    water_below: 0.35,
+    dose_ml: 120.0,
+    dose_duty: 75,
+    soak_time: Duration::from_secs(30 * 60),
+    temperature: TemperatureLimits { freeze_below: 5.0, heat_above: 35.0, heat_action: HeatAction::Defer { max: Duration::from_secs(6 * 60 * 60) } },
+    sample_period: Duration::from_secs(2),
+    warmup_samples: 50,
+    aggregation_window: Duration::from_secs(15 * 60),
+};
+
+/// Lets the substrate dry out almost completely and soaks it briefly.
+pub const SUCCULENT: PlantProfile = PlantProfile {
+    name: "succulent",
+    calibration: Calibration::new(300.0, 900.0),
+    water_below: 0.1,
+    dose_ml: 60.0,
+    dose_duty: 62,
+    soak_time: Duration::from_secs(2 * 60 * 60),
+    temperature: TemperatureLimits { freeze_below: 8.0, heat_above: 40.0, heat_action: HeatAction::Defer { max: Duration::from_secs(12 * 60 * 60) } },
+    sample_period: Duration::from_secs(10),
+    warmup_samples: 20,
+    aggregation_window: Duration::from_secs(60 * 60),
+};
+
+/// Keeps the soil evenly moist.
+pub const FERN: PlantProfile = PlantProfile {
+    name: "fern",
+    calibration: Calibration::new(340.0, 1100.0),
+    water_below: 0.55,
+    dose_ml: 150.0,
+    dose_duty: 75,
+    soak_time: Duration::from_secs(20 * 60),
+    temperature: TemperatureLimits { freeze_below: 5.0, heat_above: 32.0, heat_action: HeatAction::ExtraDose { factor: 1.5 } },
+    sample_period: Duration::from_secs(2),
+    warmup_samples: 50,
+    aggregation_window: Duration::from_secs(15 * 60),
+};
+
+/// Small, frequent and gentle doses so the seeds are not washed out.
+pub const SEEDLING: PlantProfile = PlantProfile {
+    name: "seedling",
+    calibration: Calibration::new(320.0, 1000.0),
+    water_below: 0.5,
+    dose_ml: 25.0,
+    dose_duty: 38,
+    soak_time: Duration::from_secs(10 * 60),
+    temperature: TemperatureLimits { freeze_below: 10.0, heat_above: 30.0, heat_action: HeatAction::Defer { max: Duration::from_secs(3 * 60 * 60) } },
+    sample_period: Duration::from_secs(2),
+    warmup_samples: 50,
+    aggregation_window: Duration::from_secs(10 * 60),
+};
+
+pub const PRESETS: [&PlantProfile; 4] = [&HOUSEPLANT, &SUCCULENT, &FERN, &SEEDLING];
+
+pub fn by_name(name: &str) -> Option<&'static PlantProfile> {
+    PRESETS.into_iter().find(|profile| profile.name == name)
+}
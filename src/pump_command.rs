@@ -0,0 +1,104 @@
+use embassy_sync::{blocking_mutex::raw::NoopRawMutex, channel::Channel, signal::Signal};
+use embassy_time::{Duration, Instant};
+use serde::Serialize;
+
+use crate::{flow_calibration::FlowPoint, pump_safety::PumpFault, statistics::RunningStats};
+
+/// What a pump output should do. A new command replaces the one in progress.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PumpCommand {
+    Off,
+    /// Run at a duty in percent until the next command.
+    Duty(u8),
+    /// Run at a duty in percent for a fixed time.
+    RunFor(Duration, u8),
+    /// Deliver `ml`, at `duty` percent unless a higher duty is needed to stay within the on-time limit.
+    Dose { ml: f64, duty: u8 },
+}
+
+impl PumpCommand {
+    /// Everything but `Off` and `Duty(0)` ends with a status.
+    pub fn reports_status(&self) -> bool {
+        !matches!(self, Self::Off | Self::Duty(0))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AbortReason {
+    /// Ended early by `PumpCommand::Off`.
+    Stopped,
+    /// Replaced by another command before it finished.
+    Superseded,
+    /// Not started because a safety fault is latched or the start would violate a limit.
+    Refused(PumpFault),
+    /// Cut short by a safety limit.
+    SafetyLimit(PumpFault),
+    /// Not started or cut short because the tank the pump draws from is empty.
+    TankEmpty,
+    /// Not started or cut short because the pump is under manual control.
+    Overridden,
+    /// The output can not carry out this kind of command.
+    Unsupported,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PumpOutcome {
+    Completed,
+    Aborted(AbortReason),
+}
+
+/// Result of one command, reported once it ended.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PumpStatus {
+    pub command: PumpCommand,
+    pub outcome: PumpOutcome,
+    pub started: Instant,
+    pub runtime: Duration,
+    /// Duty in percent averaged over the run time, ramps included.
+    pub average_duty: f64,
+    /// Volume delivered while the command ran, estimated from the flow calibration.
+    pub volume_ml: f64,
+    /// Motor current in mA at a steady duty, empty without a current sense.
+    pub current_ma: RunningStats,
+}
+
+impl PumpStatus {
+    /// Status of a command that was not carried out.
+    pub fn aborted(command: PumpCommand, reason: AbortReason) -> Self {
+        Self::not_run(command, PumpOutcome::Aborted(reason))
+    }
+
+    /// Status of a command that asks for no water, e.g. a timed run at 0 %, it is done at once.
+    pub fn skipped(command: PumpCommand) -> Self {
+        Self::not_run(command, PumpOutcome::Completed)
+    }
+
+    fn not_run(command: PumpCommand, outcome: PumpOutcome) -> Self {
+        Self { command, outcome, started: Instant::now(), runtime: Duration::from_ticks(0), average_duty: 0.0, volume_ml: 0.0, current_ma: RunningStats::new() }
+    }
+}
+
+/// Command and status path between a controller and one pump output.
+///
+/// Every command except `Off`, and `Duty(0)` which acts like it, reports exactly one status, see
+/// `PumpCommand::reports_status`.
+pub struct PumpChannel {
+    pub command: Signal<NoopRawMutex, PumpCommand>,
+    pub status: Channel<NoopRawMutex, PumpStatus, 4>,
+    /// A timed run measured into a cup, merged into the flow calibration of the output.
+    pub calibrate: Signal<NoopRawMutex, FlowPoint>,
+}
+
+impl PumpChannel {
+    pub const fn new() -> Self {
+        Self { command: Signal::new(), status: Channel::new(), calibrate: Signal::new() }
+    }
+}
+
+impl Default for PumpChannel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,64 +1,506 @@
-
-use embassy_futures::select::select;
+use embassy_futures::select::{select4, Either4};
 use embassy_sync::{blocking_mutex::raw::NoopRawMutex, signal::Signal};
 use embassy_time::{Duration, Instant, Timer};
-use esp32_hal::{clock::Clocks, gpio::OutputPin, mcpwm::{operator::PwmPinConfig, timer::{PwmWorkingMode, TimerClockConfig}, FrequencyError, PeripheralClockConfig, MCPWM}, peripheral::Peripheral, peripherals::MCPWM0, prelude::_fugit_RateExtU32};
-pub struct PumpController<'a, P: Peripheral> {
-    peripheral: MCPWM0,
-    pwm_config: PeripheralClockConfig<'a>,
-    timer_config: TimerClockConfig<'a>,
-    pin: P,
-    target: &'a Signal<NoopRawMutex, u8>,
+use log::{error, info, warn};
+
+use crate::{events::{Event, EventSender}, flow_calibration::{FlowCalibration, FlowPoint}, persistence::{PersistQueue, PersistRequest}, flow_meter::FlowMeter, motor_current::{CurrentLimits, MotorMonitor}, pump_command::{AbortReason, PumpChannel, PumpCommand, PumpOutcome, PumpStatus}, pump_driver::PumpDriver, pump_safety::{PumpFault, SafetyLimits, SafetyMonitor}, ramp::{Ramp, RampConfig}, statistics::RunningStats, tank::TankLink};
+
+/// Time the driver stays enabled after the pump was switched off.
+const TIMER_STOP_DELAY: Duration = Duration::from_secs(2);
+/// Interval between duty updates while ramping.
+const RAMP_STEP: Duration = Duration::from_millis(10);
+/// Interval between flow meter readings while the pump runs.
+const METER_POLL: Duration = Duration::from_millis(250);
+/// Shortest run at a steady duty that is used to correct the flow calibration.
+const MIN_CALIBRATION_RUN: Duration = Duration::from_secs(5);
+/// Weight of a metered run against the flow calibration.
+const FLOW_LEARNING_RATE: f64 = 0.3;
+
+/// The command channel of a pump and the limits it runs under.
+pub struct PumpOutput<'a> {
+    pub zone: usize,
+    pub channel: &'a PumpChannel,
+    /// Clears a latched safety fault.
+    pub reset: &'a Signal<NoopRawMutex, ()>,
+    pub limits: SafetyLimits,
+    pub ramp: RampConfig,
+    /// Turns doses into duty and run time and run time into delivered volume.
+    pub flow: FlowCalibration,
+    pub events: EventSender<'a>,
+    /// Stores the flow calibration when a measurement was added.
+    pub persist: &'a PersistQueue,
+    /// Reservoir the pump draws from, it is kept off while the tank is empty.
+    pub tank: Option<&'a TankLink>,
+    /// Current bands of the motor, `None` without a current sense.
+    pub current: Option<CurrentLimits>,
+}
+
+impl<'a> PumpOutput<'a> {
+    fn into_state(self, metered: bool) -> OutputState<'a> {
+        OutputState {
+            zone: self.zone,
+            channel: self.channel,
+            reset: self.reset,
+            events: self.events,
+            safety: SafetyMonitor::new(self.limits),
+            flow: self.flow,
+            persist: self.persist,
+            ramp: Ramp::new(self.ramp),
+            applied: 0.0,
+            applied_at: Instant::now(),
+            metered,
+            last_flow: Instant::now(),
+            tank: self.tank,
+            motor: self.current.map(MotorMonitor::new),
+            active: None,
+        }
+    }
+}
+
+/// The command an output is currently carrying out.
+struct ActiveCommand {
+    command: PumpCommand,
+    started: Instant,
+    until: Option<Instant>,
+    /// Delivered so far, integrated over the ramped duty.
+    volume_ml: f64,
+    /// Delivered so far according to the flow meter.
+    metered_ml: Option<f64>,
+    /// Metered dose, ends once the meter counted this volume.
+    target_ml: Option<f64>,
+    /// Metered volume and time while the duty was not ramping.
+    steady_ml: f64,
+    steady_time: Duration,
+    /// Classified motor current samples.
+    current_ma: RunningStats,
+    /// Applied duty integrated over time, in percent times ms.
+    duty_integral: f64,
 }
 
-impl<'a, P:Peripheral + 'a> PumpController<'a, P>
-where <P as Peripheral>::P: OutputPin
-{
-    pub fn new<'b: 'a>(peripheral: MCPWM0, clocks: &'b Clocks<'a>, pin: P, target: &'a Signal<NoopRawMutex, u8>) -> Result<Self, FrequencyError> {
-        PeripheralClockConfig::with_frequency(clocks, 5400u32.kHz()).and_then(|pwm_config|{
-            pwm_config.timer_clock_with_frequency(256, PwmWorkingMode::Increase, 20u32.kHz()).map(move |timer_config| {
-                Self{
-                    peripheral,
-                    pwm_config,
-                    timer_config,
-                    pin,
-                    target,
+impl ActiveCommand {
+    fn new(command: PumpCommand, started: Instant, until: Option<Instant>, target_ml: Option<f64>) -> Self {
+        Self { command, started, until, volume_ml: 0.0, metered_ml: None, target_ml, steady_ml: 0.0, steady_time: Duration::from_ticks(0), current_ma: RunningStats::new(), duty_integral: 0.0 }
+    }
+}
+
+struct OutputState<'a> {
+    zone: usize,
+    channel: &'a PumpChannel,
+    reset: &'a Signal<NoopRawMutex, ()>,
+    events: EventSender<'a>,
+    safety: SafetyMonitor,
+    flow: FlowCalibration,
+    persist: &'a PersistQueue,
+    ramp: Ramp,
+    /// Duty in percent written to the PWM since `applied_at`.
+    applied: f64,
+    applied_at: Instant,
+    /// A flow meter is attached to the output.
+    metered: bool,
+    /// Last time water was metered, or the pump was off or ramping.
+    last_flow: Instant,
+    tank: Option<&'a TankLink>,
+    motor: Option<MotorMonitor>,
+    active: Option<ActiveCommand>,
+}
+
+impl OutputState<'_> {
+    fn report(&self, event: Event) {
+        if let Err(err) = self.events.try_send(event) {
+            warn!("Failed to report event {:?}", err);
+        }
+    }
+
+    /// Duty in percent and the command to carry out, `None` if the output can not run it.
+    fn plan(&self, command: PumpCommand, now: Instant) -> Option<(u8, ActiveCommand)> {
+        match command {
+            PumpCommand::Off => Some((0, ActiveCommand::new(command, now, None, None))),
+            PumpCommand::Duty(duty) => Some((duty.min(100), ActiveCommand::new(command, now, None, None))),
+            PumpCommand::RunFor(duration, duty) => Some((duty.min(100), ActiveCommand::new(command, now, Some(now + duration), None))),
+            PumpCommand::Dose { ml, duty } => {
+                let (duty, run) = self.flow.plan(ml, duty, self.safety.limits().max_on_time)?;
+                if self.metered {
+                    return Some((duty, ActiveCommand::new(command, now, None, Some(ml))));
+                }
+                let (ramp_up, ramp_down) = self.ramp.config().run_compensation(duty);
+                let run = (run + ramp_up).checked_sub(ramp_down).unwrap_or(Duration::from_ticks(0));
+                Some((duty, ActiveCommand::new(command, now, Some(now + run), None)))
+            },
+        }
+    }
+
+    /// Adds the water delivered since the last call to the active command, `metered_ml` is what
+    /// the flow meter counted meanwhile.
+    fn integrate(&mut self, now: Instant, metered_ml: Option<f64>) {
+        let elapsed = now.checked_duration_since(self.applied_at).unwrap_or(Duration::from_ticks(0));
+        let steady = self.applied > 0.0 && self.ramp.is_settled(self.applied_at);
+        if let Some(active) = self.active.as_mut() {
+            active.volume_ml += self.flow.volume(self.applied.round() as u8, elapsed);
+            active.duty_integral += self.applied * elapsed.as_millis() as f64;
+            if let Some(ml) = metered_ml {
+                *active.metered_ml.get_or_insert(0.0) += ml;
+                if steady {
+                    active.steady_ml += ml;
+                    active.steady_time += elapsed;
                 }
-            })
-        })
-    }
-
-    pub async fn run_motor_control(self)
-    {
-        let mut mcpwm = MCPWM::new(self.peripheral, self.pwm_config);
-        mcpwm.operator0.set_timer(&mcpwm.timer0);
-        let mut pwm_pin = mcpwm
-        .operator0
-        .with_pin_a(self.pin, PwmPinConfig::UP_ACTIVE_HIGH);
-        loop {
-            let mut target = self.target.wait().await;
-            if target < 1 {
-                let timeout = Instant::now() + Duration::from_secs(2);
-                loop {
-                    match select(self.target.wait(), Timer::at(timeout)).await{
-                        embassy_futures::select::Either::First(new_target) => {
-                            target = new_target;
-                            if target > 1 {
-                                break;
+            }
+        }
+        if !steady || !metered_ml.is_some_and(|ml| ml <= 0.0) {
+            self.last_flow = now;
+        }
+        self.applied_at = now;
+    }
+
+    /// Classifies a motor current sample taken at `now`.
+    fn sense_current(&mut self, ma: f64, now: Instant) {
+        let steady = self.ramp.is_settled(now);
+        let Some(motor) = self.motor.as_mut() else {
+            return;
+        };
+        let (changed, classified) = motor.update(ma, steady, now);
+        if let Some(state) = changed {
+            info!("Zone {} pump motor {:?} at {} mA", self.zone, state, ma);
+        }
+        if let Some(active) = self.active.as_mut().filter(|_| classified) {
+            active.current_ma.push(ma);
+        }
+    }
+
+    /// Checks the safety limits, the motor current and whether the meter has counted no water for
+    /// too long although the pump runs at a steady duty.
+    fn check_safety(&mut self, now: Instant) -> Result<(), PumpFault> {
+        if let Some(fault) = self.motor.as_ref().and_then(|motor| motor.fault(now)) {
+            return Err(self.safety.trip(fault, now));
+        }
+        let dry = now.checked_duration_since(self.last_flow).is_some_and(|dry| dry >= self.safety.limits().no_flow_timeout);
+        if self.metered && self.safety.is_running() && dry {
+            return Err(self.safety.trip(PumpFault::NoFlow, now));
+        }
+        self.safety.check(now)
+    }
+
+    fn tank_empty(&self) -> bool {
+        self.tank.is_some_and(TankLink::is_empty)
+    }
+
+    /// The active command delivered what it was asked for.
+    fn completed(&self, now: Instant) -> bool {
+        let Some(active) = self.active.as_ref() else {
+            return false;
+        };
+        match (active.target_ml, active.metered_ml) {
+            (Some(target), metered) => {
+                let (_, ramp_down) = self.ramp.config().run_compensation(self.applied.round() as u8);
+                metered.unwrap_or(0.0) + self.flow.volume(self.applied.round() as u8, ramp_down) >= target
+            },
+            (None, _) => active.until.is_some_and(|until| until <= now),
+        }
+    }
+
+    /// Ends the active command and reports how it went, counting the water of the ramp down.
+    fn finish(&mut self, outcome: PumpOutcome, now: Instant) {
+        let Some(active) = self.active.take() else {
+            return;
+        };
+        let duty = self.applied.round() as u8;
+        let (_, ramp_down) = self.ramp.config().run_compensation(duty);
+        let runtime = now.checked_duration_since(active.started).unwrap_or(Duration::from_ticks(0));
+        let status = PumpStatus {
+            command: active.command,
+            outcome,
+            started: active.started,
+            runtime,
+            average_duty: if runtime.as_millis() > 0 { active.duty_integral / runtime.as_millis() as f64 } else { 0.0 },
+            volume_ml: active.metered_ml.unwrap_or(active.volume_ml) + self.flow.volume(duty, ramp_down),
+            current_ma: active.current_ma,
+        };
+        self.send_status(status);
+        if let Some(tank) = self.tank {
+            tank.dispensed(status.volume_ml);
+        }
+        let flow_blocked = matches!(outcome, PumpOutcome::Aborted(AbortReason::SafetyLimit(fault)) if fault.blocks_flow());
+        if !flow_blocked && active.steady_time >= MIN_CALIBRATION_RUN && active.steady_ml > 0.0 {
+            let measured = active.steady_ml * 1000.0 / active.steady_time.as_millis() as f64;
+            let ml_per_s = self.flow.flow_at(duty).map_or(measured, |known| known + FLOW_LEARNING_RATE * (measured - known));
+            record_flow(self.zone, &mut self.flow, FlowPoint { duty, ml_per_s }, self.persist);
+        }
+    }
+
+    fn send_status(&self, status: PumpStatus) {
+        info!("Zone {} pump {:?}", self.zone, status);
+        if let Err(err) = self.channel.status.try_send(status) {
+            warn!("Failed to report pump status {:?}", err);
+        }
+    }
+}
+
+/// Carries out the commands of one output within its safety limits, ramps the duty towards each
+/// new target and disables the driver once the pump has been off for `TIMER_STOP_DELAY`. With a flow
+/// meter doses are metered, a dry line stops the pump and steady runs correct the flow calibration.
+/// With a current sense a stalled, dry running or disconnected motor stops the pump.
+pub async fn run_output<D: PumpDriver, M: FlowMeter>(driver: &mut D, mut meter: Option<&mut M>, output: PumpOutput<'_>) {
+    let mut state = output.into_state(meter.is_some());
+    let mut enabled = false;
+    let mut off_since = Instant::now();
+    loop {
+        let now = Instant::now();
+        let mut deadline = match (state.safety.cutoff(now), state.active.as_ref().and_then(|active| active.until)) {
+            (Some(cutoff), Some(until)) => cutoff.min(until),
+            (Some(cutoff), None) => cutoff,
+            (None, _) if enabled => off_since + TIMER_STOP_DELAY,
+            (None, _) => Instant::MAX,
+        };
+        if !state.ramp.is_settled(now) {
+            deadline = deadline.min(now + RAMP_STEP);
+        } else if (state.metered || state.tank.is_some() || state.motor.is_some()) && state.safety.is_running() {
+            deadline = deadline.min(now + METER_POLL);
+        }
+        let event = select4(state.channel.command.wait(), state.reset.wait(), Timer::at(deadline), state.channel.calibrate.wait()).await;
+        let now = Instant::now();
+        state.integrate(now, meter.as_mut().map(|meter| meter.take_volume()));
+        if state.safety.is_running() {
+            if let Some(ma) = driver.sample_current().await {
+                state.sense_current(ma, now);
+            }
+        }
+        let stop = match event {
+            Either4::First(command) => {
+                let replaced_by = if command == PumpCommand::Off { AbortReason::Stopped } else { AbortReason::Superseded };
+                let replaced = match state.active.as_ref().map(|active| active.command) {
+                    Some(PumpCommand::Duty(_)) if command == PumpCommand::Off => PumpOutcome::Completed,
+                    _ => PumpOutcome::Aborted(replaced_by),
+                };
+                state.finish(replaced, now);
+                match state.plan(command, now) {
+                    Some((duty, _)) if duty > 0 && state.tank_empty() => {
+                        warn!("Zone {} pump inhibited, the tank is empty", state.zone);
+                        state.send_status(PumpStatus::aborted(command, AbortReason::TankEmpty));
+                        true
+                    },
+                    Some((duty, active)) if duty > 0 => {
+                        if !state.safety.is_running() {
+                            if let Err(fault) = state.safety.start(now) {
+                                if fault.latches() {
+                                    error!("Zone {} pump refused to start: {:?}", state.zone, fault);
+                                    state.report(Event::PumpFault { zone: state.zone, fault });
+                                } else {
+                                    warn!("Zone {} pump refused to start: {:?}", state.zone, fault);
+                                }
+                                state.send_status(PumpStatus::aborted(command, AbortReason::Refused(fault)));
+                                continue;
                             }
-                        },
-                        embassy_futures::select::Either::Second(_) => {
-                            mcpwm.timer0.stop();
-                            while target < 1 {
-                                target = self.target.wait().await;
+                            if let Some(motor) = state.motor.as_mut() {
+                                motor.start(now);
                             }
-                            mcpwm.timer0.start(self.timer_config);
-                            break;
-                        },
+                        }
+                        if !enabled {
+                            driver.enable();
+                            enabled = true;
+                        }
+                        state.ramp.set_target(duty, now);
+                        state.active = Some(active);
+                        false
+                    },
+                    Some(_) => {
+                        if command.reports_status() {
+                            state.send_status(PumpStatus::skipped(command));
+                        }
+                        true
+                    },
+                    None => {
+                        state.send_status(PumpStatus::aborted(command, AbortReason::Unsupported));
+                        true
+                    },
+                }
+            },
+            Either4::Second(()) => {
+                if let Some(fault) = state.safety.fault() {
+                    info!("Zone {} pump fault {:?} reset", state.zone, fault);
+                    state.safety.reset();
+                    state.report(Event::PumpFaultReset { zone: state.zone });
+                }
+                false
+            },
+            Either4::Third(()) => {
+                if state.tank_empty() && state.safety.is_running() {
+                    // An empty tank also stops the flow, checked first so it does not latch a fault.
+                    warn!("Zone {} pump stopped, the tank is empty", state.zone);
+                    state.finish(PumpOutcome::Aborted(AbortReason::TankEmpty), now);
+                    true
+                } else if let Err(fault) = state.check_safety(now) {
+                    error!("Zone {} pump stopped by safety limit: {:?}", state.zone, fault);
+                    state.report(Event::PumpFault { zone: state.zone, fault });
+                    state.finish(PumpOutcome::Aborted(AbortReason::SafetyLimit(fault)), now);
+                    true
+                } else if state.completed(now) {
+                    state.finish(PumpOutcome::Completed, now);
+                    true
+                } else {
+                    if !state.safety.is_running() && enabled && state.ramp.is_settled(now) && now >= off_since + TIMER_STOP_DELAY {
+                        driver.disable();
+                        enabled = false;
                     }
+                    false
+                }
+            },
+            Either4::Fourth(point) => {
+                record_flow(state.zone, &mut state.flow, point, state.persist);
+                false
+            },
+        };
+        if stop {
+            state.safety.stop(now);
+            if let Some(motor) = state.motor.as_mut() {
+                motor.stop();
+            }
+            state.ramp.set_target(0, now);
+            off_since = now;
+        }
+        state.applied = state.ramp.duty_at(now);
+        driver.set_duty(state.applied);
+    }
+}
+
+/// Adds a measured flow point to a calibration and queues the result for storage.
+pub fn record_flow(zone: usize, flow: &mut FlowCalibration, point: FlowPoint, persist: &PersistQueue) {
+    if !flow.record(point) {
+        warn!("Zone {} ignored flow measurement {:?}", zone, point);
+        return;
+    }
+    info!("Zone {} flow calibration {:?}", zone, flow.points());
+    if let Err(err) = persist.try_send(PersistRequest::Flow { zone, calibration: *flow }) {
+        error!("Failed to queue flow calibration for storage {:?}", err);
+    }
+}
+
+/// The command channel of a solenoid valve and the limits it stays open under.
+pub struct ValveControl<'a> {
+    pub zone: usize,
+    pub channel: &'a PumpChannel,
+    /// Clears a latched safety fault.
+    pub reset: &'a Signal<NoopRawMutex, ()>,
+    pub limits: SafetyLimits,
+    /// Flow with the valve open, stored as the 100 % point.
+    pub flow: FlowCalibration,
+    pub events: EventSender<'a>,
+    /// Stores the flow calibration when a measurement was added.
+    pub persist: &'a PersistQueue,
+    /// Reservoir the line is fed from, the valve is kept closed while the tank is empty.
+    pub tank: Option<&'a TankLink>,
+}
+
+/// Opens a solenoid valve while a command runs, within the same safety limits as a pump. Doses are
+/// timed from the flow with the valve open, stored as the 100 % point of the flow calibration.
+pub async fn run_valve<D: PumpDriver>(valve: &mut D, output: ValveControl<'_>) {
+    let ValveControl { zone, channel, reset, limits, mut flow, events, persist, tank } = output;
+    let mut safety = SafetyMonitor::new(limits);
+    let tank_empty = || tank.is_some_and(TankLink::is_empty);
+    let report = |event: Event| {
+        if let Err(err) = events.try_send(event) {
+            warn!("Failed to report event {:?}", err);
+        }
+    };
+    let mut active: Option<(PumpCommand, Instant, Option<Instant>)> = None;
+    loop {
+        let now = Instant::now();
+        let mut deadline = [safety.cutoff(now), active.and_then(|(_, _, until)| until)].into_iter().flatten().min().unwrap_or(Instant::MAX);
+        if tank.is_some() && safety.is_running() {
+            deadline = deadline.min(now + METER_POLL);
+        }
+        let event = select4(channel.command.wait(), reset.wait(), Timer::at(deadline), channel.calibrate.wait()).await;
+        let now = Instant::now();
+        let (ended, next) = match event {
+            Either4::First(command) => {
+                let outcome = match (active.map(|(command, _, _)| command), command) {
+                    (Some(PumpCommand::Duty(_)), PumpCommand::Off) => PumpOutcome::Completed,
+                    (_, PumpCommand::Off) => PumpOutcome::Aborted(AbortReason::Stopped),
+                    _ => PumpOutcome::Aborted(AbortReason::Superseded),
+                };
+                (outcome, Some(command))
+            },
+            Either4::Second(()) => {
+                if let Some(fault) = safety.fault() {
+                    info!("Zone {} valve fault {:?} reset", zone, fault);
+                    safety.reset();
+                    report(Event::PumpFaultReset { zone });
                 }
+                continue;
+            },
+            Either4::Third(()) => {
+                if tank_empty() && safety.is_running() {
+                    warn!("Zone {} valve closed, the tank is empty", zone);
+                    (PumpOutcome::Aborted(AbortReason::TankEmpty), None)
+                } else if let Err(fault) = safety.check(now) {
+                    error!("Zone {} valve closed by safety limit: {:?}", zone, fault);
+                    report(Event::PumpFault { zone, fault });
+                    (PumpOutcome::Aborted(AbortReason::SafetyLimit(fault)), None)
+                } else if active.is_some_and(|(_, _, until)| until.is_some_and(|until| until <= now)) {
+                    (PumpOutcome::Completed, None)
+                } else {
+                    continue;
+                }
+            },
+            Either4::Fourth(point) => {
+                record_flow(zone, &mut flow, FlowPoint { duty: 100, ..point }, persist);
+                continue;
+            },
+        };
+        if let Some((command, started, _)) = active.take() {
+            let runtime = now.checked_duration_since(started).unwrap_or(Duration::from_ticks(0));
+            let volume_ml = flow.volume(100, runtime);
+            send_valve_status(channel, PumpStatus { command, outcome: ended, started, runtime, average_duty: 100.0, volume_ml, current_ma: RunningStats::new() });
+            if let Some(tank) = tank {
+                tank.dispensed(volume_ml);
             }
-            pwm_pin.set_timestamp(target as u16);
         }
+        // `Some` for the commands that open the valve, with the instant it closes again if it does.
+        let opens = match next {
+            Some(PumpCommand::Duty(duty)) if duty > 0 => Some(None),
+            Some(PumpCommand::RunFor(duration, duty)) if duty > 0 => Some(Some(now + duration)),
+            Some(command @ PumpCommand::Dose { ml, .. }) => match flow.plan(ml, 100, limits.max_on_time) {
+                Some((_, run)) => Some(Some(now + run)),
+                None => {
+                    send_valve_status(channel, PumpStatus::aborted(command, AbortReason::Unsupported));
+                    None
+                },
+            },
+            Some(command) if command.reports_status() => {
+                send_valve_status(channel, PumpStatus::skipped(command));
+                None
+            },
+            _ => None,
+        };
+        if let (Some(command), Some(until)) = (next, opens) {
+            let started = if tank_empty() {
+                warn!("Zone {} valve inhibited, the tank is empty", zone);
+                Err(AbortReason::TankEmpty)
+            } else if safety.is_running() {
+                Ok(())
+            } else {
+                safety.start(now).map_err(|fault| {
+                    if fault.latches() {
+                        error!("Zone {} valve refused to open: {:?}", zone, fault);
+                        report(Event::PumpFault { zone, fault });
+                    } else {
+                        warn!("Zone {} valve refused to open: {:?}", zone, fault);
+                    }
+                    AbortReason::Refused(fault)
+                })
+            };
+            match started {
+                Ok(()) => active = Some((command, now, until)),
+                Err(reason) => send_valve_status(channel, PumpStatus::aborted(command, reason)),
+            }
+        }
+        if active.is_none() {
+            safety.stop(now);
+        }
+        valve.set_duty(if active.is_some() { 100.0 } else { 0.0 });
     }
-}
\ No newline at end of file
+}
+
+fn send_valve_status(channel: &PumpChannel, status: PumpStatus) {
+    if let Err(err) = channel.status.try_send(status) {
+        warn!("Failed to report valve status {:?}", err);
+    }
+}
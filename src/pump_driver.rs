@@ -0,0 +1,100 @@
+/// Hardware behind one pump or valve output.
+#[allow(async_fn_in_trait)]
+pub trait PumpDriver {
+    /// Starts the PWM timer or whatever else the output needs before it can run.
+    fn enable(&mut self);
+    /// Stops the PWM timer, the output is off until it is enabled again.
+    fn disable(&mut self);
+    /// Duty in percent, 0.0 .. 100.0.
+    fn set_duty(&mut self, percent: f64);
+    /// Motor current in mA while the output is switched on, `None` without a current sense or when
+    /// the duty leaves too little on-time to sample.
+    async fn sample_current(&mut self) -> Option<f64> {
+        None
+    }
+}
+
+#[cfg(feature = "simulation")]
+pub use mock::{DriverAction, MockPumpDriver};
+
+#[cfg(feature = "simulation")]
+mod mock {
+    use embassy_time::Instant;
+    use heapless::Vec;
+    use log::error;
+
+    use super::PumpDriver;
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum DriverAction {
+        Enable,
+        Disable,
+        Duty(f64),
+    }
+
+    /// Records what the control loop did to the output and when, repeated duties are recorded once.
+    pub struct MockPumpDriver<const N: usize> {
+        timeline: Vec<(Instant, DriverAction), N>,
+        enabled: bool,
+        duty: f64,
+        current_ma: Option<f64>,
+    }
+
+    impl<const N: usize> MockPumpDriver<N> {
+        pub fn new() -> Self {
+            Self { timeline: Vec::new(), enabled: false, duty: 0.0, current_ma: None }
+        }
+
+        pub fn timeline(&self) -> &[(Instant, DriverAction)] {
+            &self.timeline
+        }
+
+        pub fn is_enabled(&self) -> bool {
+            self.enabled
+        }
+
+        pub fn duty(&self) -> f64 {
+            self.duty
+        }
+
+        /// Current the output reports while it runs, `None` acts like an output without current sense.
+        pub fn set_current(&mut self, ma: Option<f64>) {
+            self.current_ma = ma;
+        }
+
+        fn record(&mut self, action: DriverAction) {
+            if self.timeline.push((Instant::now(), action)).is_err() {
+                error!("Mock pump driver timeline is full");
+            }
+        }
+    }
+
+    impl<const N: usize> Default for MockPumpDriver<N> {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl<const N: usize> PumpDriver for MockPumpDriver<N> {
+        fn enable(&mut self) {
+            self.enabled = true;
+            self.record(DriverAction::Enable);
+        }
+
+        fn disable(&mut self) {
+            self.enabled = false;
+            self.record(DriverAction::Disable);
+        }
+
+        fn set_duty(&mut self, percent: f64) {
+            if percent != self.duty {
+                self.duty = percent;
+                self.record(DriverAction::Duty(percent));
+            }
+        }
+
+        async fn sample_current(&mut self) -> Option<f64> {
+            self.current_ma.filter(|_| self.enabled && self.duty > 0.0)
+        }
+    }
+}
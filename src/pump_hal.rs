@@ -0,0 +1,284 @@
+use core::cell::{Cell, RefCell};
+
+use embassy_futures::join::{join, join3};
+use embassy_time::Duration;
+use esp32_hal::{analog::{adc::{AdcPin, ADC}, ADC1}, clock::Clocks, gpio::{InputPin, OutputPin}, mcpwm::{operator::{Operator, PwmPin, PwmPinConfig}, timer::{PwmWorkingMode, Timer, TimerClockConfig}, FrequencyError, PeripheralClockConfig, PwmPeripheral, MCPWM}, pcnt::{channel::{self, PcntSource}, unit::{self, Unit}}, peripheral::Peripheral, prelude::{_embedded_hal_adc_OneShot as OneShot, _fugit_RateExtU32}};
+
+use crate::{expander::ExpanderValve, flow_meter::FlowMeter, pump_control::{run_output, PumpOutput}, pump_driver::PumpDriver, stepper::StepperDriver};
+
+/// Timer period in ticks, a timestamp of `PWM_PERIOD` is full duty.
+const PWM_PERIOD: u16 = 256;
+const PWM_FREQUENCY_HZ: u32 = 20_000;
+/// The pulse counter wraps to zero when it reaches this value.
+const PULSE_COUNTER_LIMIT: i16 = 30_000;
+/// Timer count after the switch-on edge at which the shunt amplifier has settled.
+const SAMPLE_AT: u16 = 16;
+/// Counts the ADC samples its input for, a conversion has to start this long before the switch-off edge.
+const ADC_SAMPLE: u16 = 32;
+/// Shortest on-time in timer counts that leaves room to start a conversion between the settling and
+/// the ADC sample phase.
+const MIN_SAMPLE_ON: u16 = 64;
+/// PWM periods waited for before a current sample is given up, the task may wake up past the window.
+const SYNC_ATTEMPTS: usize = 4;
+/// Busy loop iterations that hold a step pulse high, a few µs, longer than any step/dir driver needs.
+const STEP_PULSE_SPINS: usize = 500;
+
+/// ADC1 input a pump shunt is wired to.
+pub trait ShuntInput {
+    fn read(&mut self, adc: &mut ADC<'static, ADC1>) -> Option<u16>;
+}
+
+impl<PIN> ShuntInput for AdcPin<PIN, ADC1>
+where ADC<'static, ADC1>: OneShot<ADC1, u16, AdcPin<PIN, ADC1>>
+{
+    fn read(&mut self, adc: &mut ADC<'static, ADC1>) -> Option<u16> {
+        nb::block!(adc.read(self)).ok()
+    }
+}
+
+/// Low side shunt of a pump motor, it only carries the motor current while the output is on.
+pub struct Shunt<'a> {
+    /// ADC1 is shared by the shunts of all outputs.
+    pub adc: &'a RefCell<ADC<'static, ADC1>>,
+    pub input: &'a mut dyn ShuntInput,
+    pub ma_per_count: f64,
+}
+
+/// Timer of an operator, shared by its two pins and running while either of them is enabled.
+struct SharedTimer<'d, const OP: u8, PWM> {
+    timer: RefCell<Timer<OP, PWM>>,
+    config: TimerClockConfig<'d>,
+    users: Cell<u8>,
+}
+
+impl<'d, const OP: u8, PWM: PwmPeripheral> SharedTimer<'d, OP, PWM> {
+    fn new(timer: Timer<OP, PWM>, config: TimerClockConfig<'d>) -> Self {
+        Self { timer: RefCell::new(timer), config, users: Cell::new(0) }
+    }
+
+    fn acquire(&self) {
+        if self.users.get() == 0 {
+            self.timer.borrow_mut().start(self.config);
+        }
+        self.users.set(self.users.get() + 1);
+    }
+
+    fn release(&self) {
+        let users = self.users.get().saturating_sub(1);
+        self.users.set(users);
+        if users == 0 {
+            self.timer.borrow_mut().stop();
+        }
+    }
+
+    fn counter(&self) -> u16 {
+        self.timer.borrow().status().0
+    }
+}
+
+/// Pin A or B of an MCPWM operator.
+pub struct McpwmDriver<'s, 'd, const OP: u8, PWM, PIN, const IS_A: bool> {
+    timer: &'s SharedTimer<'d, OP, PWM>,
+    pin: PwmPin<'d, PIN, PWM, OP, IS_A>,
+    /// Timestamp written to the operator, the output is on while the timer counts below it.
+    on_ticks: u16,
+    shunt: Option<Shunt<'d>>,
+}
+
+impl<'s, 'd, const OP: u8, PWM, PIN, const IS_A: bool> McpwmDriver<'s, 'd, OP, PWM, PIN, IS_A> {
+    fn new(timer: &'s SharedTimer<'d, OP, PWM>, pin: PwmPin<'d, PIN, PWM, OP, IS_A>, shunt: Option<Shunt<'d>>) -> Self {
+        Self { timer, pin, on_ticks: 0, shunt }
+    }
+}
+
+impl<const OP: u8, PWM: PwmPeripheral, PIN: OutputPin, const IS_A: bool> PumpDriver for McpwmDriver<'_, '_, OP, PWM, PIN, IS_A> {
+    fn enable(&mut self) {
+        self.timer.acquire();
+    }
+
+    fn disable(&mut self) {
+        self.timer.release();
+    }
+
+    fn set_duty(&mut self, percent: f64) {
+        self.on_ticks = (percent.clamp(0.0, 100.0) * PWM_PERIOD as f64 / 100.0) as u16;
+        self.pin.set_timestamp(self.on_ticks);
+    }
+
+    /// Starts the conversion in the on-phase of a PWM period, so it samples the shunt while it carries
+    /// the motor current. Waiting for the on-phase sleeps, the other tasks keep running meanwhile.
+    async fn sample_current(&mut self) -> Option<f64> {
+        let shunt = self.shunt.as_mut()?;
+        if self.on_ticks < MIN_SAMPLE_ON {
+            return None;
+        }
+        for _ in 0..SYNC_ATTEMPTS {
+            let counter = self.timer.counter();
+            if (SAMPLE_AT..self.on_ticks - ADC_SAMPLE).contains(&counter) {
+                let raw = shunt.input.read(&mut shunt.adc.borrow_mut())?;
+                return Some(raw as f64 * shunt.ma_per_count);
+            }
+            let counts = (SAMPLE_AT + PWM_PERIOD - counter) % PWM_PERIOD;
+            embassy_time::Timer::after(counts_to_duration(counts)).await;
+        }
+        None
+    }
+}
+
+/// Time the PWM timer takes to count `counts`.
+fn counts_to_duration(counts: u16) -> Duration {
+    Duration::from_nanos(counts as u64 * 1_000_000_000 / (PWM_FREQUENCY_HZ as u64 * PWM_PERIOD as u64))
+}
+
+/// On/off output such as a solenoid valve, any duty above zero switches it on.
+pub struct GpioSwitch<P>(pub P);
+
+impl<P: OutputPin> PumpDriver for GpioSwitch<P> {
+    fn enable(&mut self) {}
+
+    fn disable(&mut self) {}
+
+    fn set_duty(&mut self, percent: f64) {
+        self.0.set_output_high(percent > 0.0);
+    }
+}
+
+/// STEP input of a step/dir driver whose DIR and EN inputs are strapped, the driver reduces the
+/// coil current on its own while no steps arrive.
+pub struct StepPin<P>(pub P);
+
+impl<P: OutputPin> StepperDriver for StepPin<P> {
+    fn enable(&mut self) {}
+
+    fn disable(&mut self) {}
+
+    fn step(&mut self) {
+        self.0.set_output_high(true);
+        for _ in 0..STEP_PULSE_SPINS {
+            core::hint::spin_loop();
+        }
+        self.0.set_output_high(false);
+    }
+}
+
+/// Solenoid valve on a GPIO or an expander pin.
+pub enum Valve<'a, P> {
+    Gpio(GpioSwitch<P>),
+    Expander(ExpanderValve<'a>),
+}
+
+impl<P: OutputPin> PumpDriver for Valve<'_, P> {
+    fn enable(&mut self) {}
+
+    fn disable(&mut self) {}
+
+    fn set_duty(&mut self, percent: f64) {
+        match self {
+            Self::Gpio(switch) => switch.set_duty(percent),
+            Self::Expander(valve) => valve.set_duty(percent),
+        }
+    }
+}
+
+/// Hall effect flow sensor counted by a PCNT unit on its rising edges.
+pub struct PcntFlowMeter {
+    unit: Unit,
+    last: i16,
+    ml_per_pulse: f64,
+}
+
+impl PcntFlowMeter {
+    pub fn new<'d, P: InputPin>(mut unit: Unit, pin: impl Peripheral<P = P> + 'd, pulses_per_litre: f64) -> Result<Self, unit::Error> {
+        unit.configure(unit::Config { low_limit: 0, high_limit: PULSE_COUNTER_LIMIT, filter: Some(1023), ..Default::default() })?;
+        let mut channel = unit.get_channel(channel::Number::Channel0);
+        channel.configure(PcntSource::from_pin(pin), PcntSource::always_high(), channel::Config {
+            lctrl_mode: channel::CtrlMode::Keep,
+            hctrl_mode: channel::CtrlMode::Keep,
+            pos_edge: channel::EdgeMode::Increment,
+            neg_edge: channel::EdgeMode::Hold,
+            invert_ctrl: false,
+            invert_sig: false,
+        });
+        unit.clear();
+        unit.resume();
+        Ok(Self { unit, last: 0, ml_per_pulse: 1000.0 / pulses_per_litre })
+    }
+}
+
+impl FlowMeter for PcntFlowMeter {
+    fn take_volume(&mut self) -> f64 {
+        let value = self.unit.get_value();
+        let pulses = (value as i32 - self.last as i32).rem_euclid(PULSE_COUNTER_LIMIT as i32);
+        self.last = value;
+        pulses as f64 * self.ml_per_pulse
+    }
+}
+
+/// A pump on an MCPWM pin with its optional flow meter and current sense.
+pub struct MotorOutput<'a, P> {
+    pub pin: P,
+    pub meter: Option<PcntFlowMeter>,
+    pub shunt: Option<Shunt<'a>>,
+    pub output: PumpOutput<'a>,
+}
+
+/// Pins A and B of each operator of an MCPWM unit.
+pub type MotorOutputs<'a, P> = [[Option<MotorOutput<'a, P>>; 2]; 3];
+
+pub struct PumpController<'a, PWM, P: Peripheral> {
+    peripheral: PWM,
+    pwm_config: PeripheralClockConfig<'a>,
+    timer_config: TimerClockConfig<'a>,
+    outputs: MotorOutputs<'a, P>,
+}
+
+impl<'a, PWM: PwmPeripheral + Peripheral<P = PWM> + 'a, P: Peripheral + 'a> PumpController<'a, PWM, P>
+where <P as Peripheral>::P: OutputPin
+{
+    /// `outputs` are driven by pins A and B of operator 0, 1 and 2, the two pins of an operator share its timer.
+    pub fn new<'b: 'a>(peripheral: PWM, clocks: &'b Clocks<'a>, outputs: MotorOutputs<'a, P>) -> Result<Self, FrequencyError> {
+        PeripheralClockConfig::with_frequency(clocks, 5400u32.kHz()).and_then(|pwm_config|{
+            pwm_config.timer_clock_with_frequency(PWM_PERIOD, PwmWorkingMode::Increase, PWM_FREQUENCY_HZ.Hz()).map(move |timer_config| {
+                Self{
+                    peripheral,
+                    pwm_config,
+                    timer_config,
+                    outputs,
+                }
+            })
+        })
+    }
+
+    pub async fn run_motor_control(self)
+    {
+        let mcpwm = MCPWM::new(self.peripheral, self.pwm_config);
+        let MCPWM { timer0, timer1, timer2, mut operator0, mut operator1, mut operator2, .. } = mcpwm;
+        operator0.set_timer(&timer0);
+        operator1.set_timer(&timer1);
+        operator2.set_timer(&timer2);
+        let timer0 = SharedTimer::new(timer0, self.timer_config);
+        let timer1 = SharedTimer::new(timer1, self.timer_config);
+        let timer2 = SharedTimer::new(timer2, self.timer_config);
+        let [outputs0, outputs1, outputs2] = self.outputs;
+        join3(run_operator(operator0, &timer0, outputs0), run_operator(operator1, &timer1, outputs1), run_operator(operator2, &timer2, outputs2)).await;
+    }
+}
+
+/// Runs the pumps on the pins of one operator.
+async fn run_operator<'d, const OP: u8, PWM: PwmPeripheral, P: Peripheral + 'd>(operator: Operator<OP, PWM>, timer: &SharedTimer<'d, OP, PWM>, outputs: [Option<MotorOutput<'d, P>>; 2])
+where <P as Peripheral>::P: OutputPin
+{
+    match outputs {
+        [Some(a), Some(b)] => {
+            let (pin_a, pin_b) = operator.with_pins(a.pin, PwmPinConfig::UP_ACTIVE_HIGH, b.pin, PwmPinConfig::UP_ACTIVE_HIGH);
+            join(run_motor(McpwmDriver::new(timer, pin_a, a.shunt), a.meter, a.output), run_motor(McpwmDriver::new(timer, pin_b, b.shunt), b.meter, b.output)).await;
+        },
+        [Some(a), None] => run_motor(McpwmDriver::new(timer, operator.with_pin_a(a.pin, PwmPinConfig::UP_ACTIVE_HIGH), a.shunt), a.meter, a.output).await,
+        [None, Some(b)] => run_motor(McpwmDriver::new(timer, operator.with_pin_b(b.pin, PwmPinConfig::UP_ACTIVE_HIGH), b.shunt), b.meter, b.output).await,
+        [None, None] => {},
+    }
+}
+
+async fn run_motor<D: PumpDriver>(mut driver: D, mut meter: Option<PcntFlowMeter>, output: PumpOutput<'_>) {
+    run_output(&mut driver, meter.as_mut(), output).await;
+}
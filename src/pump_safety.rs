@@ -0,0 +1,177 @@
+use embassy_time::{Duration, Instant};
+use serde::Serialize;
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+/// Run time is summed per bucket, so a run counts against the daily limit for up to one bucket longer than a day.
+const BUCKET: Duration = Duration::from_secs(15 * 60);
+/// Enough buckets to cover the day before any instant within the newest one.
+const BUCKETS: usize = (DAY.as_ticks() / BUCKET.as_ticks()) as usize + 1;
+
+/// Hard limits enforced by the pump subsystem no matter what it is commanded.
+#[derive(Debug, Clone, Copy)]
+pub struct SafetyLimits {
+    /// Longest continuous run.
+    pub max_on_time: Duration,
+    /// Total run time allowed in any 24 h window.
+    pub max_daily_runtime: Duration,
+    /// Shortest pause between the end of one run and the start of the next.
+    pub min_cooldown: Duration,
+    /// Longest a running pump may go without metered flow, only checked with a flow meter.
+    pub no_flow_timeout: Duration,
+}
+
+impl Default for SafetyLimits {
+    fn default() -> Self {
+        Self {
+            max_on_time: Duration::from_secs(60),
+            max_daily_runtime: Duration::from_secs(10 * 60),
+            min_cooldown: Duration::from_secs(60),
+            no_flow_timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PumpFault {
+    MaxOnTime,
+    DailyRuntime,
+    Cooldown,
+    /// The flow meter saw no water while the pump ran, the tank is empty or the line is clogged.
+    NoFlow,
+    /// The motor draws too much current, the rotor is blocked or the line clogged.
+    Stalled,
+    /// The motor draws too little current, it spins without water.
+    DryRunning,
+    /// No current flows, the motor or its wiring is disconnected.
+    OpenCircuit,
+}
+
+impl PumpFault {
+    /// The pump did not move water normally, so its flow readings are meaningless.
+    pub fn blocks_flow(&self) -> bool {
+        matches!(self, Self::NoFlow | Self::Stalled | Self::DryRunning | Self::OpenCircuit)
+    }
+
+    /// Keeps the pump off until it is reset, a cooldown only refuses starts until it is over.
+    pub fn latches(&self) -> bool {
+        *self != Self::Cooldown
+    }
+}
+
+/// Tracks the runs of one pump and latches a fault when a limit other than the cooldown is hit.
+pub struct SafetyMonitor {
+    limits: SafetyLimits,
+    /// Run time per bucket, indexed by the bucket number modulo `BUCKETS`.
+    runtime: [Duration; BUCKETS],
+    newest: u64,
+    on_since: Option<Instant>,
+    last_off: Option<Instant>,
+    fault: Option<PumpFault>,
+}
+
+impl SafetyMonitor {
+    pub fn new(limits: SafetyLimits) -> Self {
+        Self { limits, runtime: [Duration::from_ticks(0); BUCKETS], newest: 0, on_since: None, last_off: None, fault: None }
+    }
+
+    pub fn limits(&self) -> &SafetyLimits {
+        &self.limits
+    }
+
+    pub fn fault(&self) -> Option<PumpFault> {
+        self.fault
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.on_since.is_some()
+    }
+
+    /// Clears a latched fault, the run history is kept so the daily limit still holds.
+    pub fn reset(&mut self) {
+        self.fault = None;
+    }
+
+    /// Checks a pump start against the latched fault, the cooldown and the daily budget.
+    pub fn start(&mut self, now: Instant) -> Result<(), PumpFault> {
+        if let Some(fault) = self.fault {
+            return Err(fault);
+        }
+        if let Some(last_off) = self.last_off {
+            if now < last_off + self.limits.min_cooldown {
+                return Err(PumpFault::Cooldown);
+            }
+        }
+        if self.daily_runtime(now) >= self.limits.max_daily_runtime {
+            return Err(self.latch(PumpFault::DailyRuntime));
+        }
+        self.on_since = Some(now);
+        Ok(())
+    }
+
+    pub fn stop(&mut self, now: Instant) {
+        if let Some(on_since) = self.on_since.take() {
+            self.advance(now);
+            let oldest = self.newest.saturating_sub(BUCKETS as u64 - 1);
+            let mut from = on_since.max(Instant::from_ticks(oldest * BUCKET.as_ticks()));
+            while from < now {
+                let bucket = from.as_ticks() / BUCKET.as_ticks();
+                let until = now.min(Instant::from_ticks((bucket + 1) * BUCKET.as_ticks()));
+                self.runtime[bucket as usize % BUCKETS] += until - from;
+                from = until;
+            }
+            self.last_off = Some(now);
+        }
+    }
+
+    /// Instant at which the current run hits a limit.
+    pub fn cutoff(&mut self, now: Instant) -> Option<Instant> {
+        let on_since = self.on_since?;
+        let budget = self.limits.max_daily_runtime.checked_sub(self.daily_runtime(now)).unwrap_or(Duration::from_ticks(0));
+        Some((on_since + self.limits.max_on_time).min(now + budget))
+    }
+
+    /// Stops the current run and latches a fault if it exceeded a limit.
+    pub fn check(&mut self, now: Instant) -> Result<(), PumpFault> {
+        let Some(on_since) = self.on_since else {
+            return Ok(());
+        };
+        let fault = if now.checked_duration_since(on_since).is_some_and(|on| on >= self.limits.max_on_time) {
+            PumpFault::MaxOnTime
+        } else if self.daily_runtime(now) >= self.limits.max_daily_runtime {
+            PumpFault::DailyRuntime
+        } else {
+            return Ok(());
+        };
+        self.stop(now);
+        Err(self.latch(fault))
+    }
+
+    /// Stops the current run and latches a fault found outside the monitor.
+    pub fn trip(&mut self, fault: PumpFault, now: Instant) -> PumpFault {
+        self.stop(now);
+        self.latch(fault)
+    }
+
+    /// Run time within the last 24 h, including the current run.
+    fn daily_runtime(&mut self, now: Instant) -> Duration {
+        self.advance(now);
+        let current = self.on_since.and_then(|on_since| now.checked_duration_since(on_since)).unwrap_or(Duration::from_ticks(0));
+        self.runtime.iter().fold(current, |total, runtime| total + *runtime)
+    }
+
+    /// Moves the newest bucket up to `now` and clears the buckets that left the day.
+    fn advance(&mut self, now: Instant) {
+        let bucket = now.as_ticks() / BUCKET.as_ticks();
+        if bucket > self.newest {
+            for stale in (self.newest + 1..=bucket).take(BUCKETS) {
+                self.runtime[stale as usize % BUCKETS] = Duration::from_ticks(0);
+            }
+            self.newest = bucket;
+        }
+    }
+
+    fn latch(&mut self, fault: PumpFault) -> PumpFault {
+        *self.fault.get_or_insert(fault)
+    }
+}
@@ -0,0 +1,108 @@
+use embassy_time::{Duration, Instant};
+
+/// How the duty moves towards a new target.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RampProfile {
+    /// Jump straight to the target.
+    Step,
+    /// Change linearly by `percent_per_s`.
+    Slew { percent_per_s: f64 },
+    /// Smoothstep that takes `full_scale` for a 0 to 100 % change and proportionally less for smaller ones.
+    SCurve { full_scale: Duration },
+}
+
+impl RampProfile {
+    /// Time to change the duty by `delta` percent.
+    pub fn duration(&self, delta: f64) -> Duration {
+        let seconds = match *self {
+            Self::Step => 0.0,
+            Self::Slew { percent_per_s } if percent_per_s > 0.0 => delta.abs() / percent_per_s,
+            Self::Slew { .. } => 0.0,
+            Self::SCurve { full_scale } => full_scale.as_millis() as f64 / 1000.0 * delta.abs() / 100.0,
+        };
+        Duration::from_millis((seconds * 1000.0) as u64)
+    }
+
+    /// Progress of the duty at progress `t` in time, both 0.0 .. 1.0.
+    fn shape(&self, t: f64) -> f64 {
+        match self {
+            Self::Step | Self::Slew { .. } => t,
+            Self::SCurve { .. } => t * t * (3.0 - 2.0 * t),
+        }
+    }
+}
+
+/// Soft-start and soft-stop of a pump motor, limits the inrush current on the shared supply.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RampConfig {
+    pub up: RampProfile,
+    pub down: RampProfile,
+}
+
+impl Default for RampConfig {
+    fn default() -> Self {
+        Self { up: RampProfile::SCurve { full_scale: Duration::from_secs(1) }, down: RampProfile::SCurve { full_scale: Duration::from_millis(250) } }
+    }
+}
+
+impl RampConfig {
+    /// Run time at `duty` lost while ramping up and delivered while ramping down again, every
+    /// profile runs at half the duty on average.
+    pub fn run_compensation(&self, duty: u8) -> (Duration, Duration) {
+        let up = self.up.duration(duty as f64);
+        let down = self.down.duration(duty as f64);
+        (up / 2, down / 2)
+    }
+}
+
+/// Duty of one output moving towards its target.
+pub struct Ramp {
+    config: RampConfig,
+    from: f64,
+    to: f64,
+    start: Instant,
+    duration: Duration,
+}
+
+impl Ramp {
+    pub fn new(config: RampConfig) -> Self {
+        Self { config, from: 0.0, to: 0.0, start: Instant::from_ticks(0), duration: Duration::from_ticks(0) }
+    }
+
+    pub fn config(&self) -> &RampConfig {
+        &self.config
+    }
+
+    /// Starts ramping from the current duty to `target` percent.
+    pub fn set_target(&mut self, target: u8, now: Instant) {
+        let target = target.min(100) as f64;
+        if target == self.to {
+            return;
+        }
+        let from = self.duty_at(now);
+        let profile = if target > from { self.config.up } else { self.config.down };
+        self.from = from;
+        self.to = target;
+        self.start = now;
+        self.duration = profile.duration(target - from);
+    }
+
+    /// Duty in percent at `now`.
+    pub fn duty_at(&self, now: Instant) -> f64 {
+        let elapsed = now.checked_duration_since(self.start).unwrap_or(Duration::from_ticks(0));
+        if elapsed >= self.duration {
+            return self.to;
+        }
+        let t = elapsed.as_micros() as f64 / self.duration.as_micros() as f64;
+        let profile = if self.to > self.from { self.config.up } else { self.config.down };
+        self.from + (self.to - self.from) * profile.shape(t)
+    }
+
+    pub fn settles_at(&self) -> Instant {
+        self.start + self.duration
+    }
+
+    pub fn is_settled(&self, now: Instant) -> bool {
+        now >= self.settles_at()
+    }
+}
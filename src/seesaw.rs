@@ -1,18 +1,18 @@
-use embassy_time::{Duration, Timer};
+use embassy_time::{Duration, Instant, Timer};
 use embedded_hal_async::i2c::I2c;
 use embassy_sync::{blocking_mutex::raw::RawMutex, channel::Sender};
 use log::{info, warn, error};
-use esp_backtrace as _;
 
 #[allow(dead_code)]
 #[derive(Debug, Clone, Copy)]
 enum SeesawReg {
     Status(SeesawStatus),
-    GPIO,
+    Gpio,
     Sercom0,
 
     Timer,
     Adc,
+    AdcChannel(u8),
     Dac,
     Interrupt,
     Dap,
@@ -31,18 +31,19 @@ impl SeesawReg {
     }
     fn get_register(&self) -> [u8; 2] {
         match self {
-            Self::Status(status) => [0x00, status.clone() as u8],
-            Self::GPIO => [0x01, 0x00],
+            Self::Status(status) => [0x00, *status as u8],
+            Self::Gpio => [0x01, 0x00],
             Self::Sercom0 => [0x02, 0x00],
 
             Self::Timer => [0x08, 0x00],
             Self::Adc => [0x09, 0x00],
+            Self::AdcChannel(channel) => [0x09, 0x07 + channel],
             Self::Dac => [0x0A, 0x00],
             Self::Interrupt => [0x0B, 0x00],
             Self::Dap => [0x0C, 0x00],
             Self::Eeprom => [0x0D, 0x00],
             Self::Neopixel => [0x0E, 0x00],
-            Self::Touch(touch) => [0x0F, touch.clone() as u8],
+            Self::Touch(touch) => [0x0F, *touch as u8],
             Self::Keypad => [0x10, 0x00],
             Self::Encoder => [0x11, 0x00],
             Self::Spectrum => [0x12, 0x00],
@@ -55,7 +56,7 @@ enum SeesawStatus {
     HwId = 0x01,
     Version = 0x02,
     Options = 0x03,
-    TEMP = 0x04,
+    Temp = 0x04,
     Reset = 0x7F,
 }
 
@@ -73,10 +74,22 @@ impl<T:I2c> I2CInterfaces<T> {
         Self { i2c }
     }
 
+    /// The bus, for other devices sharing it with the Seesaw boards.
+    pub fn bus(&mut self) -> &mut T {
+        &mut self.i2c
+    }
+
     async fn seesaw_request(&mut self, address: u8, reg: &SeesawReg) -> Result<(), T::Error>{
         self.i2c.write(address, &reg.get_register()).await
     }
 
+    /// Raw 10 bit reading of an analog input of the Seesaw at `address`.
+    pub async fn read_adc(&mut self, address: u8, channel: u8) -> Result<u16, T::Error> {
+        self.seesaw_request(address, &SeesawReg::AdcChannel(channel)).await?;
+        Timer::after(Duration::from_millis(1)).await;
+        self.seesaw_read_u16(address).await
+    }
+
     async fn seesaw_read_u8(&mut self, address: u8) -> Result<u8, T::Error>{
         let mut read_buf = [0x00 ; 1];
         match self.i2c.read(address, &mut read_buf).await {
@@ -112,6 +125,7 @@ enum SoilSensorState {
 pub struct Messurement {
     pub temp: f32,
     pub moisture: u16,
+    pub at: Instant,
 }
 
 pub struct SoilSensor<'a, M:RawMutex, const N: usize> {
@@ -178,6 +192,7 @@ impl<'a, M: RawMutex, const N: usize> SoilSensor<'a, M, N> {
         Ok(Messurement{
             temp: self.read_temp(i2c).await?,
             moisture: self.read_moisture(i2c).await?,
+            at: Instant::now(),
         })
     }
     async fn reset_sensor<I: I2c>(&mut self, i2c: &mut I2CInterfaces<I>)-> Result<(), I::Error> {
@@ -198,7 +213,7 @@ impl<'a, M: RawMutex, const N: usize> SoilSensor<'a, M, N> {
         Ok(SeesawReg::Touch(SeesawTouch::ChannelOffset).in_options(options))
     }
     async fn read_temp<I: I2c>(&mut self, i2c: &mut I2CInterfaces<I>)-> Result<f32, I::Error> {
-        i2c.seesaw_request(self.address, &SeesawReg::Status(SeesawStatus::TEMP)).await?;
+        i2c.seesaw_request(self.address, &SeesawReg::Status(SeesawStatus::Temp)).await?;
         Ok((1.0 / (1u32 << 16) as f32) * i2c.seesaw_read_u32(self.address).await? as f32)
     }
     async fn read_moisture<I: I2c>(&mut self, i2c: &mut I2CInterfaces<I>)-> Result<u16, I::Error> {
@@ -0,0 +1,92 @@
+use serde::Serialize;
+
+use crate::seesaw;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SensorFault {
+    MoistureOutOfRange(u16),
+    TemperatureOutOfRange(f32),
+    Stuck { moisture: u16, samples: u32 },
+    ImpossibleStep { from: u16, to: u16 },
+}
+
+/// Bounds a healthy Seesaw soil probe stays within.
+#[derive(Debug, Clone, Copy)]
+pub struct PlausibilityLimits {
+    pub moisture_min: u16,
+    pub moisture_max: u16,
+    pub temperature_min: f32,
+    pub temperature_max: f32,
+    /// Largest raw moisture change allowed between two consecutive samples.
+    pub max_step: u16,
+    /// Number of consecutive samples agreeing on a new level after which a step is taken as a real shift.
+    pub settle_samples: u32,
+    /// Number of bit identical samples after which the probe is considered frozen.
+    pub stuck_samples: u32,
+}
+
+impl Default for PlausibilityLimits {
+    fn default() -> Self {
+        // The capacitive channel reads ~200 in air and ~2000 submerged, anything outside is a bus or probe fault.
+        Self {
+            moisture_min: 200,
+            moisture_max: 2000,
+            temperature_min: -20.0,
+            temperature_max: 60.0,
+            max_step: 400,
+            settle_samples: 3,
+            stuck_samples: 30,
+        }
+    }
+}
+
+pub struct SensorFaultDetector {
+    limits: PlausibilityLimits,
+    last: Option<(u16, f32)>,
+    repeated: u32,
+    /// Level of the samples rejected as a step in a row and how many there were.
+    shifted: Option<(u16, u32)>,
+}
+
+impl SensorFaultDetector {
+    pub fn new(limits: PlausibilityLimits) -> Self {
+        Self { limits, last: None, repeated: 0, shifted: None }
+    }
+
+    pub fn check(&mut self, sample: &seesaw::Messurement) -> Result<(), SensorFault> {
+        let limits = &self.limits;
+        if !(limits.moisture_min..=limits.moisture_max).contains(&sample.moisture) {
+            return Err(SensorFault::MoistureOutOfRange(sample.moisture));
+        }
+        if !(limits.temperature_min..=limits.temperature_max).contains(&sample.temp) {
+            return Err(SensorFault::TemperatureOutOfRange(sample.temp));
+        }
+
+        if let Some((moisture, temperature)) = self.last {
+            // A spike is judged against the last good sample, so the sample after it passes again. A
+            // lasting shift, like a re-seated probe, becomes the new baseline once enough samples agree on it.
+            if moisture.abs_diff(sample.moisture) > limits.max_step {
+                let agreeing = match self.shifted {
+                    Some((level, count)) if level.abs_diff(sample.moisture) <= limits.max_step => count + 1,
+                    _ => 1,
+                };
+                self.shifted = Some((sample.moisture, agreeing));
+                if agreeing < limits.settle_samples {
+                    return Err(SensorFault::ImpossibleStep { from: moisture, to: sample.moisture });
+                }
+            }
+            self.shifted = None;
+            if moisture == sample.moisture && temperature == sample.temp {
+                self.repeated += 1;
+            } else {
+                self.repeated = 0;
+            }
+        }
+        self.last = Some((sample.moisture, sample.temp));
+        if self.repeated >= limits.stuck_samples {
+            return Err(SensorFault::Stuck { moisture: sample.moisture, samples: self.repeated });
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,158 @@
+use embassy_sync::{blocking_mutex::raw::NoopRawMutex, channel::Channel};
+use embassy_time::{Duration, Instant};
+use heapless::Vec;
+use log::{info, warn};
+use serde::Deserialize;
+
+use crate::{calibration::Calibration, flow_calibration::FlowPoint, manual_override::ManualCommand, soil_estimator::EstimatorInput, watering_history::{Fluid, HistoryQuery, RunReason}, zone::ZoneIo};
+
+/// Commands taken from one upload response, further ones are refused with the response.
+pub const MAX_COMMANDS: usize = 4;
+
+/// Something the server asked the device to do in its response to an upload.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ServerCommand {
+    /// Manual control of the pump of a zone.
+    Pump { zone: usize, command: ManualCommand },
+    /// Change to the soil estimator of a zone.
+    Estimator { zone: usize, input: EstimatorInput },
+    /// Flow of the pump or valve of a zone, measured with a cup.
+    Flow { zone: usize, point: FlowPoint },
+}
+
+/// Commands of the server, queued by the upload for the tasks they are meant for.
+pub type ServerCommandQueue = Channel<NoopRawMutex, ServerCommand, MAX_COMMANDS>;
+
+/// `ManualCommand` on the wire.
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum PumpJson {
+    On { duty: u8 },
+    Off,
+    RunFor { seconds: u32, duty: u8 },
+    Prime,
+    Auto,
+}
+
+/// Probe calibration changes on the wire.
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum CalibrationJson {
+    Manual { dry: f64, wet: f64 },
+    Learn,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum CommandJson {
+    Pump { zone: usize, command: PumpJson },
+    Calibration { zone: usize, command: CalibrationJson },
+    /// Air temperature next to the planters of the zone, e.g. from a weather service.
+    AmbientTemperature { zone: usize, celsius: f64 },
+    /// A timed run at `duty` filled a cup with `ml`.
+    Flow { zone: usize, duty: u8, seconds: f64, ml: f64 },
+}
+
+impl ServerCommand {
+    /// `None` for a command that can not be carried out.
+    fn from_json(command: CommandJson) -> Option<Self> {
+        let command = match command {
+            CommandJson::Pump { zone, command } => Self::Pump {
+                zone,
+                command: match command {
+                    PumpJson::On { duty } => ManualCommand::On(duty),
+                    PumpJson::Off => ManualCommand::Off,
+                    PumpJson::RunFor { seconds, duty } => ManualCommand::RunFor(Duration::from_secs(seconds.into()), duty),
+                    PumpJson::Prime => ManualCommand::Prime,
+                    PumpJson::Auto => ManualCommand::Auto,
+                },
+            },
+            CommandJson::Calibration { zone, command } => Self::Estimator {
+                zone,
+                input: match command {
+                    CalibrationJson::Manual { dry, wet } => EstimatorInput::ManualCalibration(Calibration::new(dry, wet)),
+                    CalibrationJson::Learn => EstimatorInput::LearnCalibration,
+                },
+            },
+            CommandJson::AmbientTemperature { zone, celsius } => Self::Estimator {
+                zone,
+                input: EstimatorInput::AmbientTemperature { celsius, at: Instant::now() },
+            },
+            CommandJson::Flow { zone, duty, seconds, ml } => {
+                let run = Duration::from_millis((seconds.max(0.0) * 1000.0) as u64);
+                let Some(point) = FlowPoint::measured(duty, run, ml) else {
+                    warn!("Ignoring flow measurement of zone {} without a run time", zone);
+                    return None;
+                };
+                Self::Flow { zone, point }
+            },
+        };
+        Some(command)
+    }
+}
+
+/// `HistoryQuery` on the wire, times since boot mean nothing to the server so it pages by id.
+#[derive(Deserialize)]
+struct HistoryJson {
+    #[serde(default)]
+    zone: Option<usize>,
+    #[serde(default)]
+    fluid: Option<Fluid>,
+    #[serde(default)]
+    after_id: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct ResponseJson {
+    #[serde(default)]
+    commands: Vec<CommandJson, MAX_COMMANDS>,
+    #[serde(default)]
+    history: Option<HistoryJson>,
+}
+
+/// What the server answered to an upload.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ServerResponse {
+    pub commands: Vec<ServerCommand, MAX_COMMANDS>,
+    /// Watering records the server wants with the next upload.
+    pub history: Option<HistoryQuery>,
+}
+
+/// Body of an upload response, an empty body carries nothing.
+pub fn parse(body: &[u8]) -> Result<ServerResponse, serde_json_core::de::Error> {
+    if body.iter().all(u8::is_ascii_whitespace) {
+        return Ok(ServerResponse::default());
+    }
+    let (response, _) = serde_json_core::from_slice::<ResponseJson>(body)?;
+    Ok(ServerResponse {
+        commands: response.commands.into_iter().filter_map(ServerCommand::from_json).collect(),
+        history: response.history.map(|history| HistoryQuery { zone: history.zone, fluid: history.fluid, since: None, after_id: history.after_id }),
+    })
+}
+
+/// Hands the commands of the server to the configured `zones`.
+pub async fn run_server_commands(commands: &ServerCommandQueue, zones: &[ZoneIo]) {
+    loop {
+        let command = commands.receive().await;
+        info!("Server command {:?}", command);
+        let zone = match command {
+            ServerCommand::Pump { zone, .. } | ServerCommand::Estimator { zone, .. } | ServerCommand::Flow { zone, .. } => zone,
+        };
+        let Some(io) = zones.get(zone) else {
+            warn!("Server command for unknown zone {}", zone);
+            continue;
+        };
+        let queued = match command {
+            ServerCommand::Pump { command, .. } => io.manual.try_send((command, RunReason::Remote)).is_ok(),
+            ServerCommand::Estimator { input, .. } => io.estimator.try_send(input).is_ok(),
+            ServerCommand::Flow { point, .. } => {
+                // Passed on by the override layer and the manifold to the output that runs the zone.
+                io.pump.calibrate.signal(point);
+                true
+            },
+        };
+        if !queued {
+            warn!("Zone {} has too many commands queued, dropping {:?}", zone, command);
+        }
+    }
+}
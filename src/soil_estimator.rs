@@ -1,43 +1,284 @@
-use core::ops::Rem;
+use core::mem::discriminant;
 
-use embassy_sync::{blocking_mutex::raw::NoopRawMutex, channel::{Receiver, Sender}, signal::Signal};
-use crate::seesaw;
-use log::{info, error};
+use embassy_time::{Duration, Instant};
+
+use embassy_futures::select::{select, Either};
+use embassy_sync::{blocking_mutex::raw::NoopRawMutex, channel::{Channel, Receiver, Sender}};
+use crate::{calibration::{Calibration, CalibrationLearner, CalibrationMode, LearningRates}, drying::{DryingModel, DryingSnapshot}, events::Event, persistence::{PersistQueue, PersistRequest, StoredCalibration}, pump_command::{PumpChannel, PumpCommand, PumpStatus}, plant_profile::PlantProfile, seesaw, sensor_fault::{SensorFault, SensorFaultDetector}, statistics::{AggregateRecord, AggregateSample, Aggregator}, watering::{WateringController, WateringSnapshot, WateringState}};
+use log::{info, warn, error};
 
 #[derive(Debug, Clone, Copy, Default)]
 pub struct FilteredMessurement {
+    pub zone: usize,
     pub moisture: f64,
+    /// Moisture mapped through the probe calibration, 0.0 dry .. 1.0 saturated.
+    pub relative_moisture: f64,
     pub temperature: f64,
+    /// Fitted exponential drying rate per hour since the last watering.
+    pub drying_rate: Option<f64>,
+    /// Predicted hours until the moisture drops to the watering threshold.
+    pub hours_to_watering: Option<f64>,
+}
+
+/// Rise in raw moisture that marks a watering and restarts the drying curve fit.
+const REWET_STEP: f64 = 60.0;
+/// Interval between estimator snapshots written to flash.
+const SNAPSHOT_PERIOD: Duration = Duration::from_secs(30 * 60);
+/// Snapshots closer than this to the last persisted one are not written, the flash sector wears out.
+const SNAPSHOT_MOISTURE_STEP: f64 = 10.0;
+const SNAPSHOT_TEMPERATURE_STEP: f64 = 1.0;
+/// Samples a restored filter gets to catch up with the current readings before it is acted upon.
+const RESTORED_WARMUP_SAMPLES: u64 = 5;
+/// Ambient temperatures older than this are ignored, the frost and heat rules fall back to the probe.
+const AMBIENT_TIMEOUT: Duration = Duration::from_secs(2 * 60 * 60);
+
+/// Changes to an estimator while it runs, e.g. sent by the server.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EstimatorInput {
+    /// Pins the probe calibration to hand measured endpoints.
+    ManualCalibration(Calibration),
+    /// Lets the calibration follow the watering cycles again, starting from the current endpoints.
+    LearnCalibration,
+    /// Air temperature next to the planter in °C, measured at `at`.
+    AmbientTemperature { celsius: f64, at: Instant },
 }
 
-pub struct SoilEstimator<'a, const RN: usize, const ON: usize>{
+pub type EstimatorInputs = Channel<NoopRawMutex, EstimatorInput, 4>;
+
+/// Estimator state that survives a reset.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EstimatorSnapshot {
+    pub moisture: f64,
+    pub temperature: f64,
+    pub drying: DryingSnapshot,
+    pub watering: WateringSnapshot,
+}
+
+impl EstimatorSnapshot {
+    /// Restoring `other` instead would make no difference beyond the probe noise.
+    fn is_close_to(&self, other: &Self) -> bool {
+        (self.moisture - other.moisture).abs() < SNAPSHOT_MOISTURE_STEP
+            && (self.temperature - other.temperature).abs() < SNAPSHOT_TEMPERATURE_STEP
+            && self.watering.soak_remaining.is_some() == other.watering.soak_remaining.is_some()
+    }
+}
+
+/// Channels an estimator takes its samples from and reports to.
+pub struct EstimatorChannels<'a, const RN: usize, const ON: usize, const EN: usize> {
+    pub messurements: Receiver<'a, NoopRawMutex, seesaw::Messurement, RN>,
+    /// Commands of the watering controller and the statuses of the pump runs.
+    pub pump: &'a PumpChannel,
+    pub aggregates: Sender<'a, NoopRawMutex, AggregateRecord, ON>,
+    pub events: Sender<'a, NoopRawMutex, Event, EN>,
+    pub persist: &'a PersistQueue,
+}
+
+pub struct SoilEstimator<'a, const RN: usize, const ON: usize, const EN: usize>{
     messurements: Receiver<'a, NoopRawMutex, seesaw::Messurement, RN>,
-    messurement_log: Sender<'a, NoopRawMutex, FilteredMessurement, ON>,
-    command: &'a Signal<NoopRawMutex, u8>,
+    messurement_log: Sender<'a, NoopRawMutex, AggregateRecord, ON>,
+    events: Sender<'a, NoopRawMutex, Event, EN>,
+    pump: &'a PumpChannel,
+    profile: &'a PlantProfile,
+    calibration: CalibrationLearner,
+    persist: &'a PersistQueue,
+    zone: usize,
+    watering: WateringController,
+    fault_detector: SensorFaultDetector,
+    fault: Option<SensorFault>,
     low_pass_messurement: FilteredMessurement,
+    drying: DryingModel,
     samples: u64,
+    aggregator: Aggregator,
+    last_snapshot: Option<Instant>,
+    /// Last snapshot sent to flash and the watering it was taken after.
+    persisted: Option<(EstimatorSnapshot, Option<Instant>)>,
+    /// Last ambient temperature and when it was measured.
+    ambient_temperature: Option<(f64, Instant)>,
 }
 
-impl<'a, const RN: usize, const ON: usize> SoilEstimator<'a, RN, ON> {
-    pub fn new(messurements: Receiver<'a, NoopRawMutex, seesaw::Messurement, RN>, command: &'a Signal<NoopRawMutex, u8>, filtered: Sender<'a, NoopRawMutex, FilteredMessurement, ON>) -> Self {
+impl<'a, const RN: usize, const ON: usize, const EN: usize> SoilEstimator<'a, RN, ON, EN> {
+    pub fn new(zone: usize, channels: EstimatorChannels<'a, RN, ON, EN>, fault_detector: SensorFaultDetector, profile: &'a PlantProfile, calibration: StoredCalibration) -> Self {
+        let EstimatorChannels { messurements, pump, aggregates, events, persist } = channels;
         Self {
-            messurements, command, low_pass_messurement: FilteredMessurement::default(), samples: 0, messurement_log: filtered
+            messurements, pump, low_pass_messurement: FilteredMessurement { zone, ..Default::default() }, zone, samples: 0, messurement_log: aggregates, aggregator: Aggregator::new(zone, profile.aggregation_window), events, fault_detector, fault: None,
+            drying: DryingModel::new(calibration.calibration.dry, REWET_STEP), watering: WateringController::new(profile), profile,
+            calibration: CalibrationLearner::new(calibration.calibration, calibration.mode, LearningRates::default()), persist, last_snapshot: None, persisted: None, ambient_temperature: None,
         }
     }
 
-    pub async fn update_estimator(&mut self) {
-        let sample = self.messurements.receive().await;
+    /// Continues from the state saved before a reset instead of starting blind.
+    pub fn restore(&mut self, snapshot: &EstimatorSnapshot, now: Instant) {
+        self.low_pass_messurement.moisture = snapshot.moisture;
+        self.low_pass_messurement.temperature = snapshot.temperature;
+        self.drying.restore(&snapshot.drying, now);
+        self.watering.restore(&snapshot.watering, now);
+        self.samples = self.profile.warmup_samples.saturating_sub(RESTORED_WARMUP_SAMPLES);
+        self.last_snapshot = Some(now);
+        self.persisted = Some((*snapshot, self.watering.last_watering()));
+    }
+
+    fn snapshot(&self, now: Instant) -> EstimatorSnapshot {
+        EstimatorSnapshot {
+            moisture: self.low_pass_messurement.moisture,
+            temperature: self.low_pass_messurement.temperature,
+            drying: self.drying.snapshot(),
+            watering: self.watering.snapshot(now),
+        }
+    }
+
+    /// Takes samples and `inputs` as they come.
+    pub async fn run(&mut self, inputs: &EstimatorInputs) {
+        loop {
+            match select(self.messurements.receive(), inputs.receive()).await {
+                Either::First(sample) => self.update(sample),
+                Either::Second(input) => self.apply(input),
+            }
+        }
+    }
+
+    pub fn apply(&mut self, input: EstimatorInput) {
+        match input {
+            EstimatorInput::ManualCalibration(calibration) if calibration.wet <= calibration.dry => {
+                warn!("Zone {} refused calibration {:?}, wet has to read above dry", self.zone, calibration);
+                return;
+            },
+            EstimatorInput::ManualCalibration(calibration) => {
+                self.calibration.set_manual(calibration);
+                self.drying.set_floor(calibration.dry);
+            },
+            EstimatorInput::LearnCalibration if self.calibration.mode() == CalibrationMode::Learning => return,
+            EstimatorInput::LearnCalibration => self.calibration.resume_learning(),
+            EstimatorInput::AmbientTemperature { celsius, at } => {
+                // Frost and heat rules use it in addition to the probe until it goes stale.
+                self.ambient_temperature = Some((celsius, at));
+                return;
+            },
+        }
+        info!("Zone {} calibration {:?} set to {:?}", self.zone, self.calibration.mode(), self.calibration.calibration());
+        self.persist_calibration();
+    }
+
+    /// Takes one sample, `run` takes them from the sensor task.
+    pub fn update(&mut self, sample: seesaw::Messurement) {
+        if let Err(fault) = self.fault_detector.check(&sample) {
+            if self.fault.is_none() {
+                // Never water on readings we can not trust.
+                self.pump.command.signal(PumpCommand::Off);
+            }
+            if self.fault.map(|active| discriminant(&active)) != Some(discriminant(&fault)) {
+                error!("Zone {} soil sensor fault {:?}", self.zone, fault);
+                self.report(Event::SensorFault { zone: self.zone, fault });
+            }
+            self.fault = Some(fault);
+            return;
+        }
+        if self.fault.take().is_some() {
+            info!("Zone {} soil sensor recovered", self.zone);
+            self.report(Event::SensorRecovered { zone: self.zone });
+        }
+
         self.samples += 1;
         self.low_pass_messurement.moisture    = 0.5 * self.low_pass_messurement.moisture    + 0.5 * sample.moisture as f64;
         self.low_pass_messurement.temperature = 0.5 * self.low_pass_messurement.temperature + 0.5 * sample.temp as f64;
+        self.low_pass_messurement.relative_moisture = self.calibration.calibration().relative(self.low_pass_messurement.moisture);
+        if self.samples > self.profile.warmup_samples {
+            self.drying.update(self.low_pass_messurement.moisture, sample.at);
+            let estimate = self.drying.estimate(self.calibration.calibration().raw(self.profile.water_below));
+            self.low_pass_messurement.drying_rate = estimate.map(|estimate| estimate.rate_per_hour);
+            self.low_pass_messurement.hours_to_watering = estimate.map(|estimate| estimate.hours_to_threshold);
+        }
         info!("Estimator state: {:?}", self.low_pass_messurement);
-        if self.samples > 50 {
-            if self.samples.rem(30) == 0 {
-                if let Err(err) = self.messurement_log.try_send(self.low_pass_messurement) {
-                    error!("Failed to log {:?}", err);
-                }
+        if self.samples > self.profile.warmup_samples {
+            self.aggregate(sample.at);
+            let previous_state = self.watering.state();
+            let ambient = self.ambient_temperature.filter(|(_, at)| sample.at.saturating_duration_since(*at) < AMBIENT_TIMEOUT).map(|(celsius, _)| celsius);
+            let (command, decision) = self.watering.update(self.low_pass_messurement.relative_moisture, self.low_pass_messurement.temperature, ambient, sample.at);
+            if let Some(decision) = decision {
+                info!("Zone {} watering decision {:?}", self.zone, decision);
+                self.report(Event::Watering { zone: self.zone, decision });
+            }
+            if let Some(command) = command {
+                self.pump.command.signal(command);
+            }
+            self.learn_calibration(previous_state);
+            self.persist_snapshot(sample.at);
+        }
+    }
+
+    fn aggregate(&mut self, now: Instant) {
+        while let Ok(status) = self.pump.status.try_receive() {
+            self.aggregator.add_pumping(status.runtime, status.volume_ml, &status.current_ma);
+            self.dose_finished(&status, now);
+        }
+        let filtered = &self.low_pass_messurement;
+        let sample = AggregateSample {
+            moisture: filtered.moisture,
+            relative_moisture: filtered.relative_moisture,
+            temperature: filtered.temperature,
+            drying_rate: filtered.drying_rate,
+            hours_to_watering: filtered.hours_to_watering,
+        };
+        let (window, day) = self.aggregator.push(&sample, now);
+        for record in window.into_iter().chain(day) {
+            if let Err(err) = self.messurement_log.try_send(record) {
+                error!("Failed to log {:?}", err);
             }
-            self.command.signal(0);
         }
     }
-}
\ No newline at end of file
+
+    fn dose_finished(&mut self, status: &PumpStatus, now: Instant) {
+        let PumpCommand::Dose { ml, .. } = status.command else {
+            return;
+        };
+        info!("Zone {} watered {} ml of {} ml in {} ms", self.zone, status.volume_ml, ml, status.runtime.as_millis());
+        self.report(Event::Watered { zone: self.zone, requested_ml: ml, delivered_ml: status.volume_ml, outcome: status.outcome });
+        self.watering.dose_finished(now);
+    }
+
+    fn persist_snapshot(&mut self, now: Instant) {
+        let last_snapshot = *self.last_snapshot.get_or_insert(now);
+        if now < last_snapshot + SNAPSHOT_PERIOD {
+            return;
+        }
+        self.last_snapshot = Some(now);
+        let snapshot = self.snapshot(now);
+        let last_watering = self.watering.last_watering();
+        if self.persisted.is_some_and(|(persisted, watering)| watering == last_watering && snapshot.is_close_to(&persisted)) {
+            return;
+        }
+        self.persisted = Some((snapshot, last_watering));
+        if let Err(err) = self.persist.try_send(PersistRequest::Estimator { zone: self.zone, snapshot }) {
+            error!("Failed to queue estimator snapshot for storage {:?}", err);
+        }
+    }
+
+    fn learn_calibration(&mut self, previous_state: WateringState) {
+        let moisture = self.low_pass_messurement.moisture;
+        match self.watering.state() {
+            WateringState::Soaking { .. } => self.calibration.observe_soaking(moisture),
+            WateringState::Idle => {
+                if matches!(previous_state, WateringState::Soaking { .. }) && self.calibration.cycle_finished() {
+                    let calibration = self.calibration.calibration();
+                    info!("Zone {} learned calibration {:?}", self.zone, calibration);
+                    self.drying.set_floor(calibration.dry);
+                    self.persist_calibration();
+                }
+                self.calibration.observe_drying(moisture);
+            },
+            WateringState::Dosing { .. } => (),
+        }
+    }
+
+    fn persist_calibration(&self) {
+        let request = PersistRequest::Calibration { zone: self.zone, calibration: StoredCalibration { calibration: self.calibration.calibration(), mode: self.calibration.mode() } };
+        if let Err(err) = self.persist.try_send(request) {
+            error!("Failed to queue calibration for storage {:?}", err);
+        }
+    }
+
+    fn report(&self, event: Event) {
+        if let Err(err) = self.events.try_send(event) {
+            warn!("Failed to report event {:?}", err);
+        }
+    }
+}
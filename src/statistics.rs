@@ -0,0 +1,201 @@
+use embassy_time::{Duration, Instant};
+use libm::sqrt;
+use serde::Serialize;
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Summary {
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub stddev: f64,
+}
+
+/// Welford accumulator that can be merged, so windows roll up into days without keeping samples.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RunningStats {
+    n: u32,
+    mean: f64,
+    m2: f64,
+    min: f64,
+    max: f64,
+}
+
+impl RunningStats {
+    pub const fn new() -> Self {
+        Self { n: 0, mean: 0.0, m2: 0.0, min: f64::MAX, max: f64::MIN }
+    }
+
+    pub fn push(&mut self, value: f64) {
+        self.n += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.n as f64;
+        self.m2 += delta * (value - self.mean);
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+    }
+
+    pub fn merge(&mut self, other: &Self) {
+        if other.n == 0 {
+            return;
+        }
+        let n = self.n + other.n;
+        let delta = other.mean - self.mean;
+        self.mean += delta * other.n as f64 / n as f64;
+        self.m2 += other.m2 + delta * delta * self.n as f64 * other.n as f64 / n as f64;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.n = n;
+    }
+
+    pub fn summary(&self) -> Option<Summary> {
+        (self.n > 0).then(|| Summary {
+            min: self.min,
+            max: self.max,
+            mean: self.mean,
+            stddev: sqrt(self.m2 / self.n as f64),
+        })
+    }
+}
+
+impl Default for RunningStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AggregateSpan {
+    Window,
+    Day,
+}
+
+/// Upload record summarising the soil and pump activity of one zone over a window or a day.
+#[derive(Debug, Clone, Copy)]
+pub struct AggregateRecord {
+    pub zone: usize,
+    pub span: AggregateSpan,
+    pub start: Instant,
+    pub duration: Duration,
+    pub samples: u32,
+    pub moisture: Summary,
+    pub relative_moisture: Summary,
+    pub temperature: Summary,
+    pub water_delivered_ml: f64,
+    pub pump_runtime: Duration,
+    /// Motor current of the pump runs in mA.
+    pub pump_current_ma: Option<Summary>,
+    /// Drying curve fit at the end of the span.
+    pub drying_rate: Option<f64>,
+    pub hours_to_watering: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Accumulator {
+    start: Option<Instant>,
+    moisture: RunningStats,
+    relative_moisture: RunningStats,
+    temperature: RunningStats,
+    water_delivered_ml: f64,
+    pump_runtime: Duration,
+    pump_current_ma: RunningStats,
+}
+
+impl Accumulator {
+    const fn new() -> Self {
+        Self {
+            start: None,
+            moisture: RunningStats::new(),
+            relative_moisture: RunningStats::new(),
+            temperature: RunningStats::new(),
+            water_delivered_ml: 0.0,
+            pump_runtime: Duration::from_ticks(0),
+            pump_current_ma: RunningStats::new(),
+        }
+    }
+
+    fn merge(&mut self, other: &Self) {
+        self.start = self.start.or(other.start);
+        self.moisture.merge(&other.moisture);
+        self.relative_moisture.merge(&other.relative_moisture);
+        self.temperature.merge(&other.temperature);
+        self.water_delivered_ml += other.water_delivered_ml;
+        self.pump_runtime += other.pump_runtime;
+        self.pump_current_ma.merge(&other.pump_current_ma);
+    }
+
+    fn finish(&mut self, zone: usize, span: AggregateSpan, end: Instant, drying_rate: Option<f64>, hours_to_watering: Option<f64>) -> Option<AggregateRecord> {
+        let finished = core::mem::replace(self, Self::new());
+        let start = finished.start?;
+        Some(AggregateRecord {
+            zone,
+            span,
+            start,
+            duration: end.checked_duration_since(start).unwrap_or(Duration::from_ticks(0)),
+            samples: finished.moisture.n,
+            moisture: finished.moisture.summary()?,
+            relative_moisture: finished.relative_moisture.summary()?,
+            temperature: finished.temperature.summary()?,
+            water_delivered_ml: finished.water_delivered_ml,
+            pump_runtime: finished.pump_runtime,
+            pump_current_ma: finished.pump_current_ma.summary(),
+            drying_rate,
+            hours_to_watering,
+        })
+    }
+}
+
+/// A filtered sample together with the latest drying curve fit.
+#[derive(Debug, Clone, Copy)]
+pub struct AggregateSample {
+    pub moisture: f64,
+    pub relative_moisture: f64,
+    pub temperature: f64,
+    pub drying_rate: Option<f64>,
+    pub hours_to_watering: Option<f64>,
+}
+
+/// Rolls filtered samples and pump activity into fixed windows and the windows into days.
+pub struct Aggregator {
+    zone: usize,
+    window: Duration,
+    current: Accumulator,
+    day: Accumulator,
+}
+
+impl Aggregator {
+    pub fn new(zone: usize, window: Duration) -> Self {
+        Self { zone, window, current: Accumulator::new(), day: Accumulator::new() }
+    }
+
+    pub fn add_pumping(&mut self, runtime: Duration, volume_ml: f64, current_ma: &RunningStats) {
+        self.current.pump_runtime += runtime;
+        self.current.water_delivered_ml += volume_ml;
+        self.current.pump_current_ma.merge(current_ma);
+    }
+
+    /// Adds a sample, returns the records of the window and the day it closed.
+    pub fn push(&mut self, sample: &AggregateSample, at: Instant) -> (Option<AggregateRecord>, Option<AggregateRecord>) {
+        let window_start = *self.current.start.get_or_insert(at);
+        self.current.moisture.push(sample.moisture);
+        self.current.relative_moisture.push(sample.relative_moisture);
+        self.current.temperature.push(sample.temperature);
+        if !elapsed(window_start, at, self.window) {
+            return (None, None);
+        }
+
+        self.day.merge(&self.current);
+        let window = self.current.finish(self.zone, AggregateSpan::Window, at, sample.drying_rate, sample.hours_to_watering);
+        let day = match self.day.start {
+            Some(day_start) if elapsed(day_start, at, DAY) => self.day.finish(self.zone, AggregateSpan::Day, at, sample.drying_rate, sample.hours_to_watering),
+            _ => None,
+        };
+        (window, day)
+    }
+}
+
+fn elapsed(start: Instant, now: Instant, span: Duration) -> bool {
+    now.checked_duration_since(start).is_some_and(|elapsed| elapsed >= span)
+}
@@ -0,0 +1,342 @@
+use embassy_futures::select::{select4, Either4};
+use embassy_sync::{blocking_mutex::raw::NoopRawMutex, signal::Signal};
+use embassy_time::{Duration, Instant, Timer};
+use log::{error, info, warn};
+
+use crate::{events::{Event, EventSender}, flow_calibration::FlowCalibration, persistence::PersistQueue, pump_command::{AbortReason, PumpChannel, PumpCommand, PumpOutcome, PumpStatus}, pump_control::record_flow, pump_safety::{SafetyLimits, SafetyMonitor}, statistics::RunningStats, tank::TankLink};
+
+/// Motion limits of a stepper driven peristaltic pump.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StepperProfile {
+    /// Step rate at 100 % duty in steps/s.
+    pub max_speed: f64,
+    /// Change of the step rate while starting and stopping in steps/s².
+    pub acceleration: f64,
+}
+
+/// Step input of a step/dir driver, the direction is fixed by the wiring.
+pub trait StepperDriver {
+    /// Powers the coils.
+    fn enable(&mut self);
+    fn disable(&mut self);
+    /// Emits one step pulse.
+    fn step(&mut self);
+}
+
+/// Trapezoidal step rate, the rate changes by the acceleration from one step to the next.
+#[derive(Clone, Copy)]
+struct Motion {
+    profile: StepperProfile,
+    /// Step rate of the current step, zero while standing.
+    speed: f64,
+    target: f64,
+    /// Steps left of a dose, the rate drops in time to stop on the last one.
+    remaining: Option<u64>,
+}
+
+impl Motion {
+    fn new(profile: StepperProfile) -> Self {
+        Self { profile, speed: 0.0, target: 0.0, remaining: None }
+    }
+
+    fn is_moving(&self) -> bool {
+        self.speed > 0.0
+    }
+
+    /// Moves towards `duty` percent of the top speed, stopping after `steps` if given.
+    fn run(&mut self, duty: u8, steps: Option<u64>) {
+        self.target = self.profile.max_speed * duty.min(100) as f64 / 100.0;
+        self.remaining = steps;
+    }
+
+    /// Decelerates to a stop.
+    fn stop(&mut self) {
+        self.target = 0.0;
+        self.remaining = None;
+    }
+
+    /// Steps taken while decelerating from the current rate to a stop.
+    fn stopping_steps(&self) -> u64 {
+        (self.speed * self.speed / (2.0 * self.profile.acceleration)) as u64
+    }
+
+    /// Steps and time of a stop from the current rate, counted the way the steps are taken.
+    fn stopping(&self) -> (u64, Duration) {
+        let mut motion = Self { target: 0.0, remaining: None, ..*self };
+        let mut steps = 0;
+        let mut time = Duration::from_ticks(0);
+        while let Some(interval) = motion.next_interval() {
+            steps += 1;
+            time += interval;
+        }
+        (steps, time)
+    }
+
+    /// Counts a step that was taken.
+    fn stepped(&mut self) {
+        if let Some(remaining) = self.remaining.as_mut() {
+            *remaining = remaining.saturating_sub(1);
+        }
+    }
+
+    /// Rate of the next step and the time until it is due, `None` once the motion stands.
+    fn next_interval(&mut self) -> Option<Duration> {
+        if self.remaining == Some(0) {
+            self.speed = 0.0;
+            return None;
+        }
+        let step_change = 2.0 * self.profile.acceleration;
+        let target = match self.remaining {
+            Some(remaining) if remaining <= self.stopping_steps() => 0.0,
+            _ => self.target,
+        };
+        let squared = self.speed * self.speed;
+        self.speed = if self.speed < target { (squared + step_change).sqrt().min(target) } else { (squared - step_change).max(0.0).sqrt().max(target) };
+        if target == 0.0 && self.speed * self.speed < step_change / 2.0 {
+            // Rounding leaves a crawl of a step every few hours instead of a stop.
+            self.speed = 0.0;
+        }
+        if self.remaining.is_some() {
+            // A dose takes its last steps at the start rate instead of stopping short.
+            self.speed = self.speed.max(step_change.sqrt());
+        }
+        (self.speed > 0.0).then(|| Duration::from_micros((1_000_000.0 / self.speed) as u64))
+    }
+}
+
+/// The command channel of a stepper pump and the limits it runs under.
+pub struct StepperOutput<'a> {
+    pub zone: usize,
+    pub channel: &'a PumpChannel,
+    /// Clears a latched safety fault.
+    pub reset: &'a Signal<NoopRawMutex, ()>,
+    pub limits: SafetyLimits,
+    pub profile: StepperProfile,
+    /// Flow over duty, linear for a peristaltic pump, it sets the steps per ml.
+    pub flow: FlowCalibration,
+    pub events: EventSender<'a>,
+    /// Stores the flow calibration when a measurement was added.
+    pub persist: &'a PersistQueue,
+    /// Reservoir the pump draws from, it is kept off while the tank is empty.
+    pub tank: Option<&'a TankLink>,
+}
+
+/// The command a stepper pump is currently carrying out.
+struct ActiveRun {
+    command: PumpCommand,
+    started: Instant,
+    until: Option<Instant>,
+    steps: u64,
+}
+
+struct StepperState<'a> {
+    zone: usize,
+    channel: &'a PumpChannel,
+    events: EventSender<'a>,
+    safety: SafetyMonitor,
+    flow: FlowCalibration,
+    persist: &'a PersistQueue,
+    tank: Option<&'a TankLink>,
+    motion: Motion,
+    active: Option<ActiveRun>,
+}
+
+impl StepperState<'_> {
+    fn steps_per_ml(&self) -> Option<f64> {
+        let ml_per_s = self.flow.flow_at(100).filter(|flow| *flow > 0.0)?;
+        Some(self.motion.profile.max_speed / ml_per_s)
+    }
+
+    /// Duty in percent, the steps of a dose and when the command ends, `None` if it can not run.
+    fn plan(&self, command: PumpCommand, now: Instant) -> Option<(u8, Option<u64>, Option<Instant>)> {
+        match command {
+            PumpCommand::Off => Some((0, None, None)),
+            PumpCommand::Duty(duty) => Some((duty.min(100), None, None)),
+            PumpCommand::RunFor(duration, duty) => Some((duty.min(100), None, Some(now + duration))),
+            PumpCommand::Dose { ml, duty } => {
+                let (duty, _) = self.flow.plan(ml, duty, self.safety.limits().max_on_time)?;
+                let steps = (ml * self.steps_per_ml()?).round() as u64;
+                (steps > 0).then_some((duty, Some(steps), None))
+            },
+        }
+    }
+
+    fn tank_empty(&self) -> bool {
+        self.tank.is_some_and(TankLink::is_empty)
+    }
+
+    /// Ends the active command and reports how it went, `stopping` counts the steps and the time of
+    /// the deceleration.
+    fn finish(&mut self, outcome: PumpOutcome, stopping: bool, now: Instant) {
+        let Some(active) = self.active.take() else {
+            return;
+        };
+        let mut steps = active.steps;
+        let mut runtime = now.saturating_duration_since(active.started);
+        if stopping {
+            let (stopping_steps, stopping_time) = self.motion.stopping();
+            steps += stopping_steps;
+            runtime += stopping_time;
+        }
+        let average_duty = match runtime.as_micros() {
+            0 => 0.0,
+            us => steps as f64 * 1_000_000.0 / us as f64 / self.motion.profile.max_speed * 100.0,
+        };
+        let status = PumpStatus {
+            command: active.command,
+            outcome,
+            started: active.started,
+            runtime,
+            average_duty,
+            volume_ml: self.steps_per_ml().map_or(0.0, |steps_per_ml| steps as f64 / steps_per_ml),
+            current_ma: RunningStats::new(),
+        };
+        info!("Zone {} stepper pump {:?} after {} steps", self.zone, status, steps);
+        if let Err(err) = self.channel.status.try_send(status) {
+            warn!("Failed to report pump status {:?}", err);
+        }
+        if let Some(tank) = self.tank {
+            tank.dispensed(status.volume_ml);
+        }
+    }
+
+    fn refuse(&self, command: PumpCommand, reason: AbortReason) {
+        if let Err(err) = self.channel.status.try_send(PumpStatus::aborted(command, reason)) {
+            warn!("Failed to report pump status {:?}", err);
+        }
+    }
+
+    fn report(&self, event: Event) {
+        if let Err(err) = self.events.try_send(event) {
+            warn!("Failed to report event {:?}", err);
+        }
+    }
+}
+
+/// Carries out the commands of a peristaltic pump on a stepper driver within its safety limits.
+/// The step rate follows the acceleration of the profile, duty sets the step rate as a share of the
+/// top speed and doses are counted out in steps, including those of the deceleration.
+pub async fn run_stepper<D: StepperDriver>(driver: &mut D, output: StepperOutput<'_>) {
+    let mut state = StepperState {
+        zone: output.zone,
+        channel: output.channel,
+        events: output.events,
+        safety: SafetyMonitor::new(output.limits),
+        flow: output.flow,
+        persist: output.persist,
+        tank: output.tank,
+        motion: Motion::new(output.profile),
+        active: None,
+    };
+    let mut next_step: Option<Instant> = None;
+    loop {
+        let now = Instant::now();
+        let deadline = [next_step, state.safety.cutoff(now), state.active.as_ref().and_then(|active| active.until)].into_iter().flatten().min().unwrap_or(Instant::MAX);
+        let event = select4(state.channel.command.wait(), output.reset.wait(), Timer::at(deadline), state.channel.calibrate.wait()).await;
+        let now = Instant::now();
+        let stop = match event {
+            Either4::First(command) => {
+                let replaced_by = if command == PumpCommand::Off { AbortReason::Stopped } else { AbortReason::Superseded };
+                let replaced = match state.active.as_ref().map(|active| active.command) {
+                    Some(PumpCommand::Duty(_)) if command == PumpCommand::Off => PumpOutcome::Completed,
+                    _ => PumpOutcome::Aborted(replaced_by),
+                };
+                let plan = state.plan(command, now);
+                let runs = matches!(plan, Some((duty, _, _)) if duty > 0) && !state.tank_empty();
+                state.finish(replaced, !runs, now);
+                match plan {
+                    Some((duty, _, _)) if duty > 0 && state.tank_empty() => {
+                        warn!("Zone {} pump inhibited, the tank is empty", state.zone);
+                        state.refuse(command, AbortReason::TankEmpty);
+                        true
+                    },
+                    Some((duty, steps, until)) if duty > 0 => {
+                        if !state.safety.is_running() {
+                            if let Err(fault) = state.safety.start(now) {
+                                if fault.latches() {
+                                    error!("Zone {} pump refused to start: {:?}", state.zone, fault);
+                                    state.report(Event::PumpFault { zone: state.zone, fault });
+                                } else {
+                                    warn!("Zone {} pump refused to start: {:?}", state.zone, fault);
+                                }
+                                state.refuse(command, AbortReason::Refused(fault));
+                                continue;
+                            }
+                        }
+                        if !state.motion.is_moving() {
+                            driver.enable();
+                        }
+                        state.motion.run(duty, steps);
+                        state.active = Some(ActiveRun { command, started: now, until, steps: 0 });
+                        if next_step.is_none() {
+                            next_step = state.motion.next_interval().map(|interval| now + interval);
+                        }
+                        false
+                    },
+                    Some(_) => {
+                        // Asks for no water, e.g. a timed run at 0 %, it is done at once.
+                        if command.reports_status() {
+                            if let Err(err) = state.channel.status.try_send(PumpStatus::skipped(command)) {
+                                warn!("Failed to report pump status {:?}", err);
+                            }
+                        }
+                        true
+                    },
+                    None => {
+                        state.refuse(command, AbortReason::Unsupported);
+                        true
+                    },
+                }
+            },
+            Either4::Second(()) => {
+                if let Some(fault) = state.safety.fault() {
+                    info!("Zone {} pump fault {:?} reset", state.zone, fault);
+                    state.safety.reset();
+                    state.report(Event::PumpFaultReset { zone: state.zone });
+                }
+                false
+            },
+            Either4::Third(()) => {
+                if let Some(due) = next_step.filter(|due| *due <= now) {
+                    driver.step();
+                    state.motion.stepped();
+                    if let Some(active) = state.active.as_mut() {
+                        active.steps += 1;
+                    }
+                    // Steps missed by a late wakeup are not caught up, the rate would jump.
+                    next_step = state.motion.next_interval().map(|interval| (due + interval).max(now));
+                    if next_step.is_none() {
+                        driver.disable();
+                    }
+                }
+                if state.tank_empty() && state.safety.is_running() {
+                    warn!("Zone {} pump stopped, the tank is empty", state.zone);
+                    state.finish(PumpOutcome::Aborted(AbortReason::TankEmpty), true, now);
+                    true
+                } else if let Err(fault) = state.safety.check(now) {
+                    error!("Zone {} pump stopped by safety limit: {:?}", state.zone, fault);
+                    state.report(Event::PumpFault { zone: state.zone, fault });
+                    state.finish(PumpOutcome::Aborted(AbortReason::SafetyLimit(fault)), true, now);
+                    true
+                } else if state.active.as_ref().is_some_and(|active| active.until.is_some_and(|until| until <= now)) {
+                    state.finish(PumpOutcome::Completed, true, now);
+                    true
+                } else if state.active.is_some() && next_step.is_none() {
+                    // A dose stops on its last step.
+                    state.finish(PumpOutcome::Completed, false, now);
+                    true
+                } else {
+                    false
+                }
+            },
+            Either4::Fourth(point) => {
+                record_flow(state.zone, &mut state.flow, point, state.persist);
+                false
+            },
+        };
+        if stop {
+            state.safety.stop(now);
+            state.motion.stop();
+        }
+    }
+}
@@ -0,0 +1,239 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use embassy_futures::select::{select, Either};
+use embassy_sync::{blocking_mutex::raw::NoopRawMutex, channel::Channel};
+use embassy_time::{Duration, Instant, Timer};
+use log::{error, info, warn};
+use serde::Serialize;
+
+use crate::events::{Event, EventSender};
+
+/// Rise in level that counts as a refill, as a fraction of the capacity.
+const REFILL_STEP: f64 = 0.1;
+/// A float switch has to stay wet this long to count as a refill, it bounces at the mark while a pump draws.
+const REFILL_SETTLE: Duration = Duration::from_secs(5 * 60);
+/// Usage over less time than this gives no useful refill prediction.
+const MIN_USAGE_SPAN: Duration = Duration::from_secs(12 * 60 * 60);
+/// Interval between status reports with the refill prediction.
+const STATUS_PERIOD: Duration = Duration::from_secs(6 * 60 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TankReading {
+    /// Float switch at the empty mark, `wet` while the water is above it.
+    Switch { wet: bool },
+    /// Fill level, 0.0 empty .. 1.0 full.
+    Level(f64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TankSensorError {
+    Pin,
+    I2c,
+    /// The ultrasonic sensor did not answer, e.g. the water surface is too close or not level.
+    NoEcho,
+}
+
+/// Anything that can tell how much water is left in the reservoir.
+#[allow(async_fn_in_trait)]
+pub trait TankLevelSensor {
+    async fn read(&mut self) -> Result<TankReading, TankSensorError>;
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TankConfig {
+    pub capacity_ml: f64,
+    /// A low water alert goes out once less than this is left.
+    pub low_ml: f64,
+    /// Level at or below which the tank counts as empty and the pumps are inhibited.
+    pub empty_level: f64,
+    pub sample_period: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TankAlert {
+    /// The pumps drawing from the tank are inhibited until it is refilled.
+    Empty,
+    Low { remaining_ml: f64, hours_to_empty: Option<f64> },
+    Refilled { remaining_ml: f64 },
+    Status { remaining_ml: f64, hours_to_empty: Option<f64> },
+}
+
+/// Shared between the tank monitor and the pump outputs drawing from the tank.
+pub struct TankLink {
+    empty: AtomicBool,
+    dispensed: Channel<NoopRawMutex, f64, 8>,
+}
+
+impl TankLink {
+    pub const fn new() -> Self {
+        Self { empty: AtomicBool::new(false), dispensed: Channel::new() }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.empty.load(Ordering::Relaxed)
+    }
+
+    /// Reports water taken from the tank.
+    pub fn dispensed(&self, ml: f64) {
+        if let Err(err) = self.dispensed.try_send(ml) {
+            warn!("Failed to report dispensed water to the tank monitor {:?}", err);
+        }
+    }
+}
+
+impl Default for TankLink {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Remaining volume from the level readings and the water dispensed in between.
+pub struct TankMonitor {
+    config: TankConfig,
+    /// Volume known at the last level reading or refill.
+    reference_ml: f64,
+    dispensed_since_reference: f64,
+    /// Water used since the last refill, for the refill prediction.
+    usage_ml: f64,
+    usage_since: Instant,
+    last_reading: Option<TankReading>,
+    /// The float switch of an empty tank is wet since then.
+    wet_since: Option<Instant>,
+    empty: bool,
+    low_reported: bool,
+    last_status: Option<Instant>,
+}
+
+impl TankMonitor {
+    /// Assumes a full tank until a reading says otherwise.
+    pub fn new(config: TankConfig, now: Instant) -> Self {
+        Self {
+            config,
+            reference_ml: config.capacity_ml,
+            dispensed_since_reference: 0.0,
+            usage_ml: 0.0,
+            usage_since: now,
+            last_reading: None,
+            wet_since: None,
+            empty: false,
+            low_reported: false,
+            last_status: None,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.empty
+    }
+
+    pub fn remaining_ml(&self) -> f64 {
+        (self.reference_ml - self.dispensed_since_reference).max(0.0)
+    }
+
+    /// Hours until the tank runs dry at the average usage since the last refill.
+    pub fn hours_to_empty(&self, now: Instant) -> Option<f64> {
+        let span = now.checked_duration_since(self.usage_since).filter(|span| *span >= MIN_USAGE_SPAN)?;
+        let ml_per_hour = self.usage_ml / (span.as_secs() as f64 / 3600.0);
+        (ml_per_hour > 0.0).then(|| self.remaining_ml() / ml_per_hour)
+    }
+
+    pub fn add_dispensed(&mut self, ml: f64, now: Instant) -> Option<TankAlert> {
+        self.dispensed_since_reference += ml;
+        self.usage_ml += ml;
+        self.check_low(now)
+    }
+
+    /// Takes a sensor reading and returns what changed, the most urgent change first.
+    pub fn update(&mut self, reading: TankReading, now: Instant) -> Option<TankAlert> {
+        if let TankReading::Switch { wet } = reading {
+            if wet && self.empty {
+                self.wet_since.get_or_insert(now);
+            } else {
+                self.wet_since = None;
+            }
+        }
+        let refilled = match (self.last_reading, reading) {
+            (_, TankReading::Switch { .. }) => self.wet_since.is_some_and(|since| now.checked_duration_since(since).is_some_and(|wet| wet >= REFILL_SETTLE)),
+            (Some(TankReading::Level(last)), TankReading::Level(level)) => level - last >= REFILL_STEP,
+            _ => false,
+        };
+        self.last_reading = Some(reading);
+        match reading {
+            TankReading::Switch { wet } => {
+                if refilled {
+                    self.reference_ml = self.config.capacity_ml;
+                    self.dispensed_since_reference = 0.0;
+                    self.wet_since = None;
+                } else if !wet {
+                    self.reference_ml = 0.0;
+                    self.dispensed_since_reference = 0.0;
+                }
+            },
+            TankReading::Level(level) => {
+                self.reference_ml = level.clamp(0.0, 1.0) * self.config.capacity_ml;
+                self.dispensed_since_reference = 0.0;
+            },
+        }
+        let empty = match reading {
+            TankReading::Switch { wet } => !wet || (self.empty && !refilled),
+            TankReading::Level(level) => level <= self.config.empty_level,
+        };
+        let became_empty = empty && !self.empty;
+        self.empty = empty;
+        if became_empty {
+            return Some(TankAlert::Empty);
+        }
+        if refilled {
+            self.usage_ml = 0.0;
+            self.usage_since = now;
+            self.low_reported = false;
+            return Some(TankAlert::Refilled { remaining_ml: self.remaining_ml() });
+        }
+        self.check_low(now).or_else(|| self.status(now))
+    }
+
+    fn check_low(&mut self, now: Instant) -> Option<TankAlert> {
+        if self.low_reported || self.remaining_ml() >= self.config.low_ml {
+            return None;
+        }
+        self.low_reported = true;
+        Some(TankAlert::Low { remaining_ml: self.remaining_ml(), hours_to_empty: self.hours_to_empty(now) })
+    }
+
+    fn status(&mut self, now: Instant) -> Option<TankAlert> {
+        if self.last_status.is_some_and(|last| now < last + STATUS_PERIOD) {
+            return None;
+        }
+        self.last_status = Some(now);
+        Some(TankAlert::Status { remaining_ml: self.remaining_ml(), hours_to_empty: self.hours_to_empty(now) })
+    }
+}
+
+/// Reads the level sensor every sample period, keeps the pumps off while the tank is empty and
+/// reports alerts.
+pub async fn run_tank<S: TankLevelSensor>(sensor: &mut S, config: TankConfig, link: &TankLink, events: EventSender<'_>) {
+    let mut monitor = TankMonitor::new(config, Instant::now());
+    let mut next_read = Instant::now();
+    loop {
+        let alert = match select(Timer::at(next_read), link.dispensed.receive()).await {
+            Either::First(()) => {
+                next_read += config.sample_period;
+                match sensor.read().await {
+                    Ok(reading) => monitor.update(reading, Instant::now()),
+                    Err(err) => {
+                        error!("Tank level sensor failed {:?}", err);
+                        None
+                    },
+                }
+            },
+            Either::Second(ml) => monitor.add_dispensed(ml, Instant::now()),
+        };
+        link.empty.store(monitor.is_empty(), Ordering::Relaxed);
+        if let Some(alert) = alert {
+            info!("Tank {:?}", alert);
+            if let Err(err) = events.try_send(Event::Tank { alert }) {
+                warn!("Failed to report event {:?}", err);
+            }
+        }
+    }
+}
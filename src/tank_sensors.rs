@@ -0,0 +1,91 @@
+use embassy_futures::select::{select, Either};
+use embassy_time::{Duration, Instant, Timer};
+use embedded_hal::digital::{InputPin, OutputPin};
+use embedded_hal_async::{digital::Wait, i2c::I2c};
+
+use crate::{seesaw::I2CInterfaces, tank::{TankLevelSensor, TankReading, TankSensorError}};
+
+/// Longest echo of an ultrasonic sensor, about 4 m to the surface and back.
+const ECHO_TIMEOUT: Duration = Duration::from_millis(25);
+
+/// Float switch at the empty mark.
+pub struct FloatSwitch<P> {
+    pin: P,
+    /// Level of the pin while the float is lifted by the water.
+    wet_high: bool,
+}
+
+impl<P: InputPin> FloatSwitch<P> {
+    pub fn new(pin: P, wet_high: bool) -> Self {
+        Self { pin, wet_high }
+    }
+}
+
+impl<P: InputPin> TankLevelSensor for FloatSwitch<P> {
+    async fn read(&mut self) -> Result<TankReading, TankSensorError> {
+        let high = self.pin.is_high().map_err(|_| TankSensorError::Pin)?;
+        Ok(TankReading::Switch { wet: high == self.wet_high })
+    }
+}
+
+/// Ultrasonic distance sensor such as the JSN-SR04T looking down onto the water surface.
+pub struct UltrasonicLevel<T, E> {
+    trigger: T,
+    echo: E,
+    /// Distance to the surface of an empty and a full tank in mm.
+    empty_mm: f64,
+    full_mm: f64,
+}
+
+impl<T: OutputPin, E: InputPin + Wait> UltrasonicLevel<T, E> {
+    pub fn new(trigger: T, echo: E, empty_mm: f64, full_mm: f64) -> Self {
+        Self { trigger, echo, empty_mm, full_mm }
+    }
+
+    async fn distance_mm(&mut self) -> Result<f64, TankSensorError> {
+        self.trigger.set_high().map_err(|_| TankSensorError::Pin)?;
+        Timer::after(Duration::from_micros(10)).await;
+        self.trigger.set_low().map_err(|_| TankSensorError::Pin)?;
+        let start = match select(self.echo.wait_for_high(), Timer::after(ECHO_TIMEOUT)).await {
+            Either::First(result) => result.map(|_| Instant::now()).map_err(|_| TankSensorError::Pin)?,
+            Either::Second(()) => return Err(TankSensorError::NoEcho),
+        };
+        let echo = match select(self.echo.wait_for_low(), Timer::after(ECHO_TIMEOUT)).await {
+            Either::First(result) => result.map(|_| Instant::now() - start).map_err(|_| TankSensorError::Pin)?,
+            Either::Second(()) => return Err(TankSensorError::NoEcho),
+        };
+        // Sound travels 0.343 mm/µs, there and back.
+        Ok(echo.as_micros() as f64 * 0.343 / 2.0)
+    }
+}
+
+impl<T: OutputPin, E: InputPin + Wait> TankLevelSensor for UltrasonicLevel<T, E> {
+    async fn read(&mut self) -> Result<TankReading, TankSensorError> {
+        let distance = self.distance_mm().await?;
+        Ok(TankReading::Level(((self.empty_mm - distance) / (self.empty_mm - self.full_mm)).clamp(0.0, 1.0)))
+    }
+}
+
+/// Resistive or capacitive level probe on an analog input of a Seesaw board.
+pub struct SeesawLevelProbe<I> {
+    i2c: I2CInterfaces<I>,
+    address: u8,
+    channel: u8,
+    /// Raw readings of an empty and a full tank.
+    empty_raw: u16,
+    full_raw: u16,
+}
+
+impl<I: I2c> SeesawLevelProbe<I> {
+    pub fn new(i2c: I2CInterfaces<I>, address: u8, channel: u8, empty_raw: u16, full_raw: u16) -> Self {
+        Self { i2c, address, channel, empty_raw, full_raw }
+    }
+}
+
+impl<I: I2c> TankLevelSensor for SeesawLevelProbe<I> {
+    async fn read(&mut self) -> Result<TankReading, TankSensorError> {
+        let raw = self.i2c.read_adc(self.address, self.channel).await.map_err(|_| TankSensorError::I2c)?;
+        let level = (raw as f64 - self.empty_raw as f64) / (self.full_raw as f64 - self.empty_raw as f64);
+        Ok(TankReading::Level(level.clamp(0.0, 1.0)))
+    }
+}
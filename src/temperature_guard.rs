@@ -0,0 +1,84 @@
+use embassy_time::{Duration, Instant};
+use serde::Serialize;
+
+/// What to do when a watering is due while the soil is hotter than the heat limit.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HeatAction {
+    /// Wait for the soil to cool down, at most `max` before watering anyway.
+    Defer { max: Duration },
+    /// Water right away and scale the dose to make up for the evaporation.
+    ExtraDose { factor: f64 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TemperatureLimits {
+    /// Watering is inhibited below this temperature in °C.
+    pub freeze_below: f64,
+    /// `heat_action` applies above this temperature in °C.
+    pub heat_above: f64,
+    pub heat_action: HeatAction,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WateringDecision {
+    Water { dose_factor: f64 },
+    FrostInhibit { temperature: f64 },
+    HeatDefer { temperature: f64 },
+    /// Watered after the longest allowed deferral even though it is still hot.
+    HeatDeferralExpired { temperature: f64 },
+    HeatExtraDose { temperature: f64, dose_factor: f64 },
+}
+
+impl WateringDecision {
+    pub fn dose_factor(&self) -> Option<f64> {
+        match self {
+            Self::Water { dose_factor } | Self::HeatExtraDose { dose_factor, .. } => Some(*dose_factor),
+            Self::HeatDeferralExpired { .. } => Some(1.0),
+            Self::FrostInhibit { .. } | Self::HeatDefer { .. } => None,
+        }
+    }
+}
+
+/// Decides whether a due watering may run given the probe and, if known, the ambient temperature.
+pub struct TemperatureGuard {
+    limits: TemperatureLimits,
+    deferred_since: Option<Instant>,
+}
+
+impl TemperatureGuard {
+    pub fn new(limits: TemperatureLimits) -> Self {
+        Self { limits, deferred_since: None }
+    }
+
+    /// Frost is judged on the colder and heat on the warmer of both temperatures.
+    pub fn decide(&mut self, soil: f64, ambient: Option<f64>, at: Instant) -> WateringDecision {
+        let coldest = ambient.map_or(soil, |ambient| ambient.min(soil));
+        let warmest = ambient.map_or(soil, |ambient| ambient.max(soil));
+        if coldest < self.limits.freeze_below {
+            self.deferred_since = None;
+            return WateringDecision::FrostInhibit { temperature: coldest };
+        }
+        if warmest <= self.limits.heat_above {
+            self.deferred_since = None;
+            return WateringDecision::Water { dose_factor: 1.0 };
+        }
+        match self.limits.heat_action {
+            HeatAction::ExtraDose { factor } => WateringDecision::HeatExtraDose { temperature: warmest, dose_factor: factor },
+            HeatAction::Defer { max } => {
+                let since = *self.deferred_since.get_or_insert(at);
+                if at.checked_duration_since(since).is_some_and(|deferred| deferred >= max) {
+                    self.deferred_since = None;
+                    WateringDecision::HeatDeferralExpired { temperature: warmest }
+                } else {
+                    WateringDecision::HeatDefer { temperature: warmest }
+                }
+            },
+        }
+    }
+
+    /// A dose in progress is stopped as soon as it starts freezing.
+    pub fn must_stop(&self, soil: f64, ambient: Option<f64>) -> bool {
+        ambient.map_or(soil, |ambient| ambient.min(soil)) < self.limits.freeze_below
+    }
+}
@@ -0,0 +1,166 @@
+use heapless::Vec;
+use serde::Serialize;
+
+use crate::{events::Event, manual_override::ManualCommand, pump_command::PumpOutcome, pump_safety::PumpFault, sensor_fault::SensorFault, statistics::{AggregateRecord, AggregateSpan, Summary}, tank::TankAlert, temperature_guard::WateringDecision, watering_history::{EndCause, Fluid, RunReason, WateringRecord}};
+
+pub const MAX_MESSUREMENTS: usize = 10;
+pub const MAX_EVENTS: usize = 16;
+/// Watering records sent in answer to a history request of the server.
+pub const MAX_HISTORY: usize = 8;
+
+/// Places the times since boot of a batch, the device has no wall clock. The server notes its own
+/// time against `uptime_ms` and starts over when `boot_id` changes.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Uptime {
+    /// Drawn at random on every boot.
+    pub boot_id: u32,
+    pub uptime_ms: u64,
+}
+
+/// `AggregateRecord` on the wire, times in ms since boot.
+#[derive(Serialize)]
+struct MessurementJson {
+    zone: usize,
+    span: AggregateSpan,
+    start_ms: u64,
+    duration_ms: u64,
+    samples: u32,
+    moisture: Summary,
+    relative_moisture: Summary,
+    temperature: Summary,
+    water_delivered_ml: f64,
+    pump_runtime_ms: u64,
+    pump_current_ma: Option<Summary>,
+    drying_rate: Option<f64>,
+    hours_to_watering: Option<f64>,
+}
+
+impl From<&AggregateRecord> for MessurementJson {
+    fn from(record: &AggregateRecord) -> Self {
+        Self {
+            zone: record.zone,
+            span: record.span,
+            start_ms: record.start.as_millis(),
+            duration_ms: record.duration.as_millis(),
+            samples: record.samples,
+            moisture: record.moisture,
+            relative_moisture: record.relative_moisture,
+            temperature: record.temperature,
+            water_delivered_ml: record.water_delivered_ml,
+            pump_runtime_ms: record.pump_runtime.as_millis(),
+            pump_current_ma: record.pump_current_ma,
+            drying_rate: record.drying_rate,
+            hours_to_watering: record.hours_to_watering,
+        }
+    }
+}
+
+/// `WateringRecord` on the wire, times in ms since boot.
+#[derive(Serialize)]
+struct RecordJson {
+    id: u32,
+    zone: usize,
+    fluid: Fluid,
+    started_ms: u64,
+    duration_ms: u64,
+    average_duty: f64,
+    volume_ml: f64,
+    reason: RunReason,
+    end: EndCause,
+}
+
+impl From<&WateringRecord> for RecordJson {
+    fn from(record: &WateringRecord) -> Self {
+        Self {
+            id: record.id,
+            zone: record.zone,
+            fluid: record.fluid,
+            started_ms: record.started.as_millis(),
+            duration_ms: record.duration.as_millis(),
+            average_duty: record.average_duty,
+            volume_ml: record.volume_ml,
+            reason: record.reason,
+            end: record.end,
+        }
+    }
+}
+
+/// `ManualCommand` on the wire.
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+enum ManualCommandJson {
+    On { duty: u8 },
+    Off,
+    RunFor { duration_ms: u64, duty: u8 },
+    Prime,
+    Auto,
+}
+
+impl From<ManualCommand> for ManualCommandJson {
+    fn from(command: ManualCommand) -> Self {
+        match command {
+            ManualCommand::On(duty) => Self::On { duty },
+            ManualCommand::Off => Self::Off,
+            ManualCommand::RunFor(duration, duty) => Self::RunFor { duration_ms: duration.as_millis(), duty },
+            ManualCommand::Prime => Self::Prime,
+            ManualCommand::Auto => Self::Auto,
+        }
+    }
+}
+
+/// `Event` on the wire, its fields next to a `kind` tag.
+#[derive(Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum EventJson {
+    SensorFault { zone: usize, fault: SensorFault },
+    SensorRecovered { zone: usize },
+    Watering { zone: usize, decision: WateringDecision },
+    Watered { zone: usize, requested_ml: f64, delivered_ml: f64, outcome: PumpOutcome },
+    PumpFault { zone: usize, fault: PumpFault },
+    PumpFaultReset { zone: usize },
+    PumpRun { record: RecordJson },
+    Tank { alert: TankAlert },
+    ManualControl { zone: usize, command: ManualCommandJson },
+    AutomaticControl { zone: usize },
+}
+
+impl From<&Event> for EventJson {
+    fn from(event: &Event) -> Self {
+        match *event {
+            Event::SensorFault { zone, fault } => Self::SensorFault { zone, fault },
+            Event::SensorRecovered { zone } => Self::SensorRecovered { zone },
+            Event::Watering { zone, decision } => Self::Watering { zone, decision },
+            Event::Watered { zone, requested_ml, delivered_ml, outcome } => Self::Watered { zone, requested_ml, delivered_ml, outcome },
+            Event::PumpFault { zone, fault } => Self::PumpFault { zone, fault },
+            Event::PumpFaultReset { zone } => Self::PumpFaultReset { zone },
+            Event::PumpRun { record } => Self::PumpRun { record: RecordJson::from(&record) },
+            Event::Tank { alert } => Self::Tank { alert },
+            Event::ManualControl { zone, command } => Self::ManualControl { zone, command: command.into() },
+            Event::AutomaticControl { zone } => Self::AutomaticControl { zone },
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct BatchJson {
+    boot_id: u32,
+    uptime_ms: u64,
+    messurements: Vec<MessurementJson, MAX_MESSUREMENTS>,
+    events: Vec<EventJson, MAX_EVENTS>,
+    /// Only sent when the server asked for it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    history: Option<Vec<RecordJson, MAX_HISTORY>>,
+}
+
+/// Writes messurements, events and the `history` the server asked for as one JSON object into
+/// `buffer`, returns its length.
+pub fn serialize_batch(uptime: Uptime, messurements: &[AggregateRecord], events: &[Event], history: Option<&[WateringRecord]>, buffer: &mut [u8]) -> Result<usize, serde_json_core::ser::Error> {
+    let batch = BatchJson {
+        boot_id: uptime.boot_id,
+        uptime_ms: uptime.uptime_ms,
+        messurements: messurements.iter().take(MAX_MESSUREMENTS).map(MessurementJson::from).collect(),
+        events: events.iter().take(MAX_EVENTS).map(EventJson::from).collect(),
+        history: history.map(|records| records.iter().take(MAX_HISTORY).map(RecordJson::from).collect()),
+    };
+    serde_json_core::to_slice(&batch, buffer)
+}
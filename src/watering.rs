@@ -0,0 +1,111 @@
+use embassy_time::{Duration, Instant};
+use core::mem::discriminant;
+
+use log::info;
+
+use crate::{plant_profile::PlantProfile, pump_command::PumpCommand, temperature_guard::{TemperatureGuard, WateringDecision}};
+
+/// A dose whose end was never reported is given up after this long.
+const DOSE_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WateringState {
+    Idle,
+    Dosing { until: Instant },
+    Soaking { until: Instant },
+}
+
+/// Watering progress relative to the time the snapshot was taken.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WateringSnapshot {
+    pub soak_remaining: Option<Duration>,
+    pub since_watering: Option<Duration>,
+}
+
+/// Doses once the soil dries below the profile threshold and then waits for the water to soak in.
+pub struct WateringController {
+    profile: PlantProfile,
+    state: WateringState,
+    last_watering: Option<Instant>,
+    guard: TemperatureGuard,
+    last_decision: Option<WateringDecision>,
+}
+
+impl WateringController {
+    pub fn new(profile: &PlantProfile) -> Self {
+        Self { profile: *profile, state: WateringState::Idle, last_watering: None, guard: TemperatureGuard::new(profile.temperature), last_decision: None }
+    }
+
+    pub fn state(&self) -> WateringState {
+        self.state
+    }
+
+    pub fn last_watering(&self) -> Option<Instant> {
+        self.last_watering
+    }
+
+    pub fn snapshot(&self, now: Instant) -> WateringSnapshot {
+        let soak_remaining = match self.state {
+            WateringState::Idle => None,
+            // A dose that is cut short by a reset still has to soak in completely.
+            WateringState::Dosing { .. } => Some(self.profile.soak_time),
+            WateringState::Soaking { until } => Some(until.checked_duration_since(now).unwrap_or(Duration::from_ticks(0))),
+        };
+        WateringSnapshot { soak_remaining, since_watering: self.last_watering.and_then(|at| now.checked_duration_since(at)) }
+    }
+
+    /// Resumes from a snapshot taken just before `now`, a dose in progress is never resumed.
+    pub fn restore(&mut self, snapshot: &WateringSnapshot, now: Instant) {
+        self.state = match snapshot.soak_remaining {
+            Some(remaining) => WateringState::Soaking { until: now + remaining },
+            None => WateringState::Idle,
+        };
+        self.last_watering = snapshot.since_watering.map(|since| now.checked_sub(since).unwrap_or(Instant::from_ticks(0)));
+    }
+
+    /// Advances the state machine and returns the pump command to send, if any, along with the
+    /// temperature decision on a due watering whenever it differs from the previous one.
+    pub fn update(&mut self, relative_moisture: f64, temperature: f64, ambient: Option<f64>, at: Instant) -> (Option<PumpCommand>, Option<WateringDecision>) {
+        let mut decision = None;
+        let mut command = None;
+        self.state = match self.state {
+            WateringState::Idle if relative_moisture < self.profile.water_below => {
+                let verdict = self.guard.decide(temperature, ambient, at);
+                decision = Some(verdict);
+                match verdict.dose_factor() {
+                    Some(dose_factor) => {
+                        info!("Watering {} at moisture {}", self.profile.name, relative_moisture);
+                        self.last_watering = Some(at);
+                        command = Some(PumpCommand::Dose { ml: self.profile.dose_ml * dose_factor, duty: self.profile.dose_duty });
+                        WateringState::Dosing { until: at + DOSE_TIMEOUT }
+                    },
+                    None => WateringState::Idle,
+                }
+            },
+            WateringState::Dosing { .. } if self.guard.must_stop(temperature, ambient) => {
+                decision = Some(WateringDecision::FrostInhibit { temperature });
+                command = Some(PumpCommand::Off);
+                WateringState::Soaking { until: at + self.profile.soak_time }
+            },
+            WateringState::Dosing { until } if at >= until => WateringState::Soaking { until: at + self.profile.soak_time },
+            WateringState::Soaking { until } if at >= until => WateringState::Idle,
+            state => state,
+        };
+        (command, decision.filter(|decision| self.changed(decision)))
+    }
+
+    /// The pump reported the end of the dose, the water starts to soak in.
+    pub fn dose_finished(&mut self, at: Instant) {
+        if let WateringState::Dosing { .. } = self.state {
+            self.state = WateringState::Soaking { until: at + self.profile.soak_time };
+        }
+    }
+
+    /// Only changes of the kind of decision are reported, not every sample of a long frost.
+    fn changed(&mut self, decision: &WateringDecision) -> bool {
+        let changed = self.last_decision.map(|last| discriminant(&last)) != Some(discriminant(decision))
+            || decision.dose_factor().is_some();
+        self.last_decision = Some(*decision);
+        changed
+    }
+}
@@ -0,0 +1,172 @@
+use core::cell::RefCell;
+
+use embassy_sync::blocking_mutex::{raw::NoopRawMutex, Mutex};
+use embassy_time::{Duration, Instant};
+use heapless::{Deque, Vec};
+use serde::{Deserialize, Serialize};
+
+use crate::{pump_command::{AbortReason, PumpOutcome, PumpStatus}, pump_safety::PumpFault};
+
+/// Pump runs kept on the device, the oldest are dropped first.
+pub const HISTORY_LEN: usize = 64;
+
+/// Why the pump ran.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RunReason {
+    /// Started by the soil estimator of the zone.
+    Auto,
+    /// Started with the maintenance button.
+    Manual,
+    /// Started over the network.
+    Remote,
+}
+
+/// What a run delivered.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Fluid {
+    Water,
+    /// Liquid fertilizer from the nutrient pump of the zone.
+    Nutrient,
+}
+
+/// Why a run ended.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EndCause {
+    /// Ran for its time or volume, or was switched off as planned.
+    Completed,
+    /// Stopped or replaced by another command before it finished.
+    Interrupted,
+    /// Cut short by a run time limit.
+    SafetyLimit(PumpFault),
+    /// Cut short because the pump or its line misbehaved.
+    Fault(PumpFault),
+    TankEmpty,
+}
+
+impl EndCause {
+    pub fn from_outcome(outcome: PumpOutcome) -> Self {
+        match outcome {
+            PumpOutcome::Completed => Self::Completed,
+            PumpOutcome::Aborted(AbortReason::SafetyLimit(fault) | AbortReason::Refused(fault)) if fault.blocks_flow() => Self::Fault(fault),
+            PumpOutcome::Aborted(AbortReason::SafetyLimit(fault) | AbortReason::Refused(fault)) => Self::SafetyLimit(fault),
+            PumpOutcome::Aborted(AbortReason::TankEmpty) => Self::TankEmpty,
+            PumpOutcome::Aborted(AbortReason::Stopped | AbortReason::Superseded | AbortReason::Overridden | AbortReason::Unsupported) => Self::Interrupted,
+        }
+    }
+}
+
+/// One run of a zone pump.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WateringRecord {
+    /// Increases with every run, so uploads can continue where they stopped.
+    pub id: u32,
+    pub zone: usize,
+    pub fluid: Fluid,
+    pub started: Instant,
+    pub duration: Duration,
+    /// Duty in percent, ramps included.
+    pub average_duty: f64,
+    pub volume_ml: f64,
+    pub reason: RunReason,
+    pub end: EndCause,
+}
+
+/// Selects records from the history, unset fields match every record.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct HistoryQuery {
+    pub zone: Option<usize>,
+    pub fluid: Option<Fluid>,
+    /// Only runs started at or after this time.
+    pub since: Option<Instant>,
+    /// Only records with a larger id.
+    pub after_id: Option<u32>,
+}
+
+impl HistoryQuery {
+    fn matches(&self, record: &WateringRecord) -> bool {
+        self.zone.iter().all(|zone| record.zone == *zone)
+            && self.fluid.iter().all(|fluid| record.fluid == *fluid)
+            && self.since.iter().all(|since| record.started >= *since)
+            && self.after_id.iter().all(|id| record.id > *id)
+    }
+}
+
+/// Ring buffer of the latest pump runs.
+pub struct WateringHistory<const N: usize> {
+    records: Deque<WateringRecord, N>,
+    next_id: u32,
+}
+
+impl<const N: usize> WateringHistory<N> {
+    pub const fn new() -> Self {
+        Self { records: Deque::new(), next_id: 0 }
+    }
+
+    /// Records a run that moved the pump, returns the stored record.
+    pub fn push(&mut self, zone: usize, fluid: Fluid, status: &PumpStatus, reason: RunReason) -> WateringRecord {
+        let record = WateringRecord {
+            id: self.next_id,
+            zone,
+            fluid,
+            started: status.started,
+            duration: status.runtime,
+            average_duty: status.average_duty,
+            volume_ml: status.volume_ml,
+            reason,
+            end: EndCause::from_outcome(status.outcome),
+        };
+        self.next_id = self.next_id.wrapping_add(1);
+        if self.records.is_full() {
+            self.records.pop_front();
+        }
+        let _ = self.records.push_back(record);
+        record
+    }
+
+    /// Matching records oldest first, at most `M` of them.
+    pub fn query<const M: usize>(&self, query: &HistoryQuery) -> Vec<WateringRecord, M> {
+        self.records.iter().filter(|record| query.matches(record)).take(M).copied().collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+}
+
+impl<const N: usize> Default for WateringHistory<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// History shared by the pumps of all zones.
+pub struct WateringLog {
+    history: Mutex<NoopRawMutex, RefCell<WateringHistory<HISTORY_LEN>>>,
+}
+
+impl WateringLog {
+    pub const fn new() -> Self {
+        Self { history: Mutex::new(RefCell::new(WateringHistory::new())) }
+    }
+
+    pub fn record(&self, zone: usize, fluid: Fluid, status: &PumpStatus, reason: RunReason) -> WateringRecord {
+        self.history.lock(|history| history.borrow_mut().push(zone, fluid, status, reason))
+    }
+
+    pub fn query<const M: usize>(&self, query: &HistoryQuery) -> Vec<WateringRecord, M> {
+        self.history.lock(|history| history.borrow().query(query))
+    }
+}
+
+impl Default for WateringLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
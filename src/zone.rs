@@ -0,0 +1,185 @@
+use embassy_sync::{blocking_mutex::raw::NoopRawMutex, channel::Channel, signal::Signal};
+use embassy_time::Duration;
+
+use crate::{calibration::Calibration, flow_calibration::FlowPoint, manual_override::ManualQueue, motor_current::CurrentLimits, plant_profile::{self, PlantProfile}, pump_command::PumpChannel, pump_safety::SafetyLimits, ramp::RampConfig, nutrient::{NutrientLimits, NutrientSchedule}, seesaw, soil_estimator::EstimatorInputs, stepper::StepperProfile, watering_history::WateringRecord};
+
+pub const MAX_ZONES: usize = 4;
+/// Valve pins, flow sensor inputs and shunt inputs wired up in `main`.
+pub const VALVE_PINS: usize = 2;
+pub const FLOW_SENSOR_INPUTS: usize = 2;
+pub const SHUNT_INPUTS: usize = 3;
+/// Raw messurements buffered per zone between the I2C task and the zone estimator.
+pub const MESSUREMENT_QUEUE: usize = 16;
+
+/// Water runs of a zone waiting for their nutrient dose.
+pub type WateredChannel = Channel<NoopRawMutex, WateringRecord, 4>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum McpwmUnit {
+    Mcpwm0,
+    Mcpwm1,
+}
+
+/// Operators of an MCPWM unit, each runs on its own timer shared by its two pins.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operator {
+    Operator0,
+    Operator1,
+    Operator2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PwmPin {
+    A,
+    B,
+}
+
+/// One PWM output pin, the GPIOs are wired up in `main`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PwmOutput {
+    pub unit: McpwmUnit,
+    pub operator: Operator,
+    pub pin: PwmPin,
+}
+
+impl PwmOutput {
+    pub const fn new(unit: McpwmUnit, operator: Operator, pin: PwmPin) -> Self {
+        Self { unit, operator, pin }
+    }
+
+    /// Only pin A of the MCPWM1 operators is wired up in `main`.
+    pub fn is_wired(&self) -> bool {
+        self.unit == McpwmUnit::Mcpwm0 || self.pin == PwmPin::A
+    }
+}
+
+/// On/off output of a solenoid valve.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ValveOutput {
+    /// Index into the valve pins wired up in `main`.
+    Gpio(usize),
+    /// Pin of an I2C port expander, `expander` indexes the expanders configured in `main`.
+    Expander { expander: usize, pin: u8 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Actuator {
+    /// DC pump of its own.
+    Pump(PwmOutput),
+    /// Solenoid valve on a pressurised line.
+    Valve(ValveOutput),
+    /// Valve of a manifold fed by a pump shared with other zones. The pump settings of the first
+    /// zone on the pump apply to all of them.
+    Manifold { pump: PwmOutput, valve: ValveOutput },
+    /// Peristaltic pump on a step/dir driver, the step pulses are timed in software on the GPIO of
+    /// `step`, which then drives no DC pump.
+    Stepper { step: PwmOutput, profile: StepperProfile },
+}
+
+/// Level sensor of the reservoir the pumps draw from, the pins are wired up in `main`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TankSensor {
+    FloatSwitch { wet_high: bool },
+    /// Distances to the water surface of an empty and a full tank in mm.
+    Ultrasonic { empty_mm: f64, full_mm: f64 },
+    /// Raw ADC readings of an empty and a full tank.
+    SeesawProbe { address: u8, channel: u8, empty_raw: u16, full_raw: u16 },
+}
+
+/// Hall effect flow sensor in the line of a pump.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FlowSensor {
+    /// Index into the flow sensor pins wired up in `main`.
+    pub input: usize,
+    pub pulses_per_litre: f64,
+}
+
+/// Low side shunt of a pump motor on an ADC1 input.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CurrentSense {
+    /// Index into the shunt inputs wired up in `main`.
+    pub input: usize,
+    /// mA per ADC count, from the shunt resistance and the amplifier gain.
+    pub ma_per_count: f64,
+    pub limits: CurrentLimits,
+}
+
+/// Pump of the nutrient channel of a zone.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NutrientPump {
+    Pump(PwmOutput),
+    Stepper { step: PwmOutput, profile: StepperProfile },
+}
+
+/// Liquid fertilizer dosed into a zone after it was watered.
+#[derive(Debug, Clone, Copy)]
+pub struct NutrientConfig {
+    pub pump: NutrientPump,
+    pub schedule: NutrientSchedule,
+    /// Caps on the fertilizer, on top of the run time limits of the pump.
+    pub limits: NutrientLimits,
+    pub pump_limits: SafetyLimits,
+    /// Flow of the nutrient pump, it is not learned.
+    pub flow: &'static [FlowPoint],
+    /// Duty in percent the doses are pumped at.
+    pub duty: u8,
+}
+
+/// One row of the device configuration table: a soil probe, the plant it watches and what waters it.
+#[derive(Debug, Clone, Copy)]
+pub struct ZoneConfig {
+    pub name: &'static str,
+    pub sensor_address: u8,
+    /// Name of one of the `plant_profile::PRESETS`.
+    pub profile: &'static str,
+    pub actuator: Actuator,
+    pub pump_limits: SafetyLimits,
+    /// Soft-start and soft-stop of the pump motor, ignored for valves and steppers.
+    pub pump_ramp: RampConfig,
+    /// Meters the water of a pump, ignored for valves and steppers.
+    pub flow_sensor: Option<FlowSensor>,
+    /// Samples the motor current of a pump to detect stalls and dry running, ignored for valves and steppers.
+    pub current_sense: Option<CurrentSense>,
+    /// Flow of the pump or open valve, used until a measured flow calibration was stored.
+    pub pump_flow: &'static [FlowPoint],
+    /// Run time that fills the empty line of the zone when priming.
+    pub prime_time: Duration,
+    /// Hand measured probe endpoints, set to pin the calibration and disable learning.
+    pub manual_calibration: Option<Calibration>,
+    pub nutrient: Option<NutrientConfig>,
+}
+
+impl ZoneConfig {
+    /// The preset named by `profile`, `None` for a typo in the configuration.
+    pub fn plant_profile(&self) -> Option<&'static PlantProfile> {
+        plant_profile::by_name(self.profile)
+    }
+}
+
+/// Channels between the tasks serving one zone.
+pub struct ZoneIo {
+    pub messurements: Channel<NoopRawMutex, seesaw::Messurement, MESSUREMENT_QUEUE>,
+    pub estimator: EstimatorInputs,
+    /// Commands of the automatic controller, passed on by the override layer.
+    pub pump: PumpChannel,
+    /// Commands that reach the actuator.
+    pub actuator: PumpChannel,
+    pub manual: ManualQueue,
+    pub pump_reset: Signal<NoopRawMutex, ()>,
+    pub watered: WateredChannel,
+    /// Commands of the nutrient dosing to its pump.
+    pub nutrient: PumpChannel,
+    pub nutrient_reset: Signal<NoopRawMutex, ()>,
+}
+
+impl ZoneIo {
+    pub const fn new() -> Self {
+        Self { messurements: Channel::new(), estimator: Channel::new(), pump: PumpChannel::new(), actuator: PumpChannel::new(), manual: Channel::new(), pump_reset: Signal::new(), watered: Channel::new(), nutrient: PumpChannel::new(), nutrient_reset: Signal::new() }
+    }
+}
+
+impl Default for ZoneIo {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,157 @@
+#!/usr/bin/env python3
+"""Local stand-in for the upload server, verifies the signed uploads of a device.
+
+    python3 tools/auth_server.py --port 8080 DEVICE_ID SECRET [--command JSON ...]
+
+The device gets the same pair as DEWY_DEVICE_ID and DEWY_DEVICE_SECRET at build time.
+Handshake, `GET /auth`:
+    Authorization: Dewy-Handshake id=<id>,nonce=<16 hex device nonce>
+    -> X-Dewy-Nonce: <16 hex server nonce>
+       X-Dewy-Signature: hex HMAC-SHA256(secret, "server\\n<id>\\n<device nonce>\\n<server nonce>")
+Signed request:
+    Authorization: Dewy-HMAC-SHA256 id=<id>,nonce=<server nonce>,signature=<hex>
+    signature = HMAC-SHA256(secret, "device\\n<id>\\n<method>\\n<path>\\n<server nonce>\\n<body>")
+Every server nonce is accepted once and only within NONCE_TTL of its handshake.
+An accepted upload is acknowledged with the commands given with --command, once, e.g.
+    --command '{"pump": {"zone": 0, "command": {"run_for": {"seconds": 10, "duty": 80}}}}'
+    --command '{"calibration": {"zone": 0, "command": {"manual": {"dry": 350, "wet": 1400}}}}'
+    --command '{"flow": {"zone": 0, "duty": 80, "seconds": 30, "ml": 540}}'
+    --command '{"ambient_temperature": {"zone": 0, "celsius": 3.5}}'
+With --history it also asks for the watering records of the device, e.g. '{"zone": 0}', and
+pages through them by id until a short page arrives.
+"""
+
+import argparse
+import hashlib
+import hmac
+import json
+import secrets
+import threading
+import time
+from http.server import BaseHTTPRequestHandler, ThreadingHTTPServer
+
+HANDSHAKE_PATH = "/auth"
+UPLOAD_PATH = "/api/upload"
+NONCE_TTL = 60.0
+# Watering records the device sends per upload, MAX_HISTORY in src/upload.rs.
+HISTORY_PAGE = 8
+
+
+def mac(secret, role, *parts):
+    message = b"\n".join([role.encode()] + [part if isinstance(part, bytes) else part.encode() for part in parts])
+    return hmac.new(secret, message, hashlib.sha256).hexdigest()
+
+
+def parse_authorization(value, scheme):
+    """Fields of an `Authorization` header of `scheme`, None for other schemes."""
+    if not value or not value.startswith(scheme + " "):
+        return None
+    fields = {}
+    for field in value[len(scheme) + 1:].split(","):
+        name, _, field_value = field.strip().partition("=")
+        fields[name] = field_value
+    return fields
+
+
+class Verifier:
+    """Issues server nonces and checks the requests signed with them."""
+
+    def __init__(self, secrets_by_id):
+        self.secrets = secrets_by_id
+        self.issued = {}
+        self.lock = threading.Lock()
+
+    def handshake(self, authorization):
+        """Server nonce and signature for a handshake, None if the device is unknown."""
+        fields = parse_authorization(authorization, "Dewy-Handshake")
+        if fields is None or fields.get("id") not in self.secrets or len(fields.get("nonce", "")) != 16:
+            return None
+        device, device_nonce = fields["id"], fields["nonce"].lower()
+        server_nonce = secrets.token_hex(8)
+        with self.lock:
+            self.issued[server_nonce] = (device, time.monotonic())
+        return server_nonce, mac(self.secrets[device], "server", device, device_nonce, server_nonce)
+
+    def verify(self, authorization, method, path, body):
+        """Device id of a correctly signed request, None otherwise. The nonce is used up either way."""
+        fields = parse_authorization(authorization, "Dewy-HMAC-SHA256")
+        if fields is None:
+            return None
+        device, nonce, signature = fields.get("id"), fields.get("nonce", "").lower(), fields.get("signature", "")
+        with self.lock:
+            now = time.monotonic()
+            self.issued = {issued: entry for issued, entry in self.issued.items() if now - entry[1] <= NONCE_TTL}
+            entry = self.issued.pop(nonce, None)
+        if entry is None or entry[0] != device:
+            return None
+        expected = mac(self.secrets[device], "device", device, method, path, nonce, body)
+        return device if hmac.compare_digest(expected, signature.lower()) else None
+
+
+class Handler(BaseHTTPRequestHandler):
+    protocol_version = "HTTP/1.1"
+    verifier = None
+    commands = []
+    history = None
+
+    def reply(self, status, headers=(), body=b""):
+        self.send_response(status)
+        for name, value in headers:
+            self.send_header(name, value)
+        self.send_header("Content-Length", str(len(body)))
+        self.end_headers()
+        self.wfile.write(body)
+
+    def do_GET(self):
+        if self.path != HANDSHAKE_PATH:
+            return self.reply(404)
+        issued = self.verifier.handshake(self.headers.get("Authorization"))
+        if issued is None:
+            return self.reply(401)
+        server_nonce, signature = issued
+        self.reply(200, [("X-Dewy-Nonce", server_nonce), ("X-Dewy-Signature", signature)])
+
+    def do_POST(self):
+        body = self.rfile.read(int(self.headers.get("Content-Length", 0)))
+        if self.path != UPLOAD_PATH:
+            return self.reply(404)
+        device = self.verifier.verify(self.headers.get("Authorization"), "POST", self.path, body)
+        if device is None:
+            self.log_message("refused upload, bad signature or reused nonce")
+            return self.reply(401)
+        batch = json.loads(body)
+        self.log_message("%s boot %08x at %.1f s uploaded %d messurements and %d events", device, batch["boot_id"], batch["uptime_ms"] / 1000, len(batch["messurements"]), len(batch["events"]))
+        for event in batch["events"]:
+            self.log_message("event %s", json.dumps(event))
+        records = batch.get("history")
+        if records is not None:
+            for record in records:
+                self.log_message("watering %s", json.dumps(record))
+            # A full page may have more behind it.
+            Handler.history = dict(Handler.history, after_id=records[-1]["id"]) if len(records) == HISTORY_PAGE else None
+        if not Handler.commands and Handler.history is None:
+            return self.reply(204)
+        commands, Handler.commands = Handler.commands, []
+        self.log_message("sending %d commands", len(commands))
+        response = {"commands": commands}
+        if Handler.history is not None:
+            response["history"] = Handler.history
+        self.reply(200, [("Content-Type", "application/json")], json.dumps(response).encode())
+
+
+def main():
+    parser = argparse.ArgumentParser(description=__doc__.splitlines()[0])
+    parser.add_argument("--port", type=int, default=8080)
+    parser.add_argument("device_id")
+    parser.add_argument("secret")
+    parser.add_argument("--command", action="append", default=[], type=json.loads, help="command sent with the next acknowledgement")
+    parser.add_argument("--history", type=json.loads, help="watering records to ask for, e.g. '{}' for all")
+    args = parser.parse_args()
+    Handler.commands = args.command
+    Handler.history = args.history
+    Handler.verifier = Verifier({args.device_id: args.secret.encode()})
+    ThreadingHTTPServer(("", args.port), Handler).serve_forever()
+
+
+if __name__ == "__main__":
+    main()